@@ -0,0 +1,145 @@
+/// Разобранный атрибут вида `#[path(key = "value", flag)]`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Attribute {
+  pub path: String,
+  pub args: Vec<(String, Option<String>)>,
+}
+
+impl Attribute {
+  /// Разбирает строку атрибута. Для строк, не являющихся атрибутами, возвращает `None`.
+  pub(crate) fn parse(line: impl AsRef<str>) -> Option<Self> {
+    let inner = line.as_ref().trim().strip_prefix("#[")?.strip_suffix(']')?.trim();
+    let (path, args) = match inner.find(['(', '=']) {
+      Some(pos) if inner[pos..].starts_with('(') => {
+        let args = inner[pos + 1..].trim_end().strip_suffix(')')?;
+        (inner[..pos].trim(), Self::split_args(args).into_iter().map(|a| Self::parse_arg(&a)).collect())
+      },
+      _ => (inner, vec![]),
+    };
+    // `#[path = "..."]`
+    let (path, args) = match path.split_once('=') {
+      Some((path, value)) => (path.trim(), vec![(String::new(), Some(Self::unquote(value)))]),
+      None => (path, args),
+    };
+    Some(Self { path: path.to_owned(), args })
+  }
+
+  /// Разбирает атрибуты из строк, раскрывая `#[cfg_attr(условие, ...)]`, если условие выполняется для `cfg`
+  /// (`feature = "serde"`, `unix`), и отбрасывая, если нет.
+  pub(crate) fn parse_lines<'l>(lines: impl Iterator<Item = &'l str>, cfg: &[String]) -> Vec<Self> {
+    lines.flat_map(|line| Self::parse_with_cfg(line.trim(), cfg)).collect()
+  }
+
+  fn parse_with_cfg(line: &str, cfg: &[String]) -> Vec<Self> {
+    let cfg_attr = line
+      .strip_prefix("#[")
+      .and_then(|l| l.strip_suffix(']'))
+      .and_then(|l| l.trim().strip_prefix("cfg_attr"))
+      .and_then(|l| l.trim().strip_prefix('('))
+      .and_then(|l| l.trim_end().strip_suffix(')'));
+    let Some(args) = cfg_attr else { return Self::parse(line).into_iter().collect() };
+    let args = Self::split_args(args);
+    match args.split_first() {
+      Some((predicate, attrs)) if Self::cfg_active(predicate, cfg) => {
+        attrs.iter().flat_map(|a| Self::parse_with_cfg(&format!("#[{}]", a.trim()), cfg)).collect()
+      },
+      _ => vec![],
+    }
+  }
+
+  /// Проверяет условие `cfg`: `feature = "x"`, `name`, `all(...)`, `any(...)`, `not(...)`.
+  fn cfg_active(predicate: &str, cfg: &[String]) -> bool {
+    let predicate = predicate.trim();
+    let call = |name: &str| predicate.strip_prefix(name).map(|p| p.trim()).and_then(|p| p.strip_prefix('(')).and_then(|p| p.strip_suffix(')'));
+    if let Some(args) = call("all") {
+      Self::split_args(args).iter().all(|p| Self::cfg_active(p, cfg))
+    } else if let Some(args) = call("any") {
+      Self::split_args(args).iter().any(|p| Self::cfg_active(p, cfg))
+    } else if let Some(args) = call("not") {
+      !Self::cfg_active(args, cfg)
+    } else {
+      let normalize = |c: &str| c.chars().filter(|c| !c.is_whitespace() && *c != '"').collect::<String>();
+      let predicate = normalize(predicate);
+      cfg.iter().any(|c| normalize(c) == predicate)
+    }
+  }
+
+  /// Ищет аргумент `key` среди атрибутов `path`. Для флагов без значения возвращает `Some(None)`.
+  pub(crate) fn find<'a>(attrs: &'a [Attribute], path: &str, key: &str) -> Option<Option<&'a str>> {
+    attrs
+      .iter()
+      .filter(|a| a.path == path)
+      .flat_map(|a| a.args.iter())
+      .find(|(k, _)| k == key)
+      .map(|(_, v)| v.as_deref())
+  }
+
+  fn parse_arg(arg: &str) -> (String, Option<String>) {
+    match arg.split_once('=') {
+      Some((key, value)) => (key.trim().to_owned(), Some(Self::unquote(value))),
+      None => (arg.trim().to_owned(), None),
+    }
+  }
+
+  fn unquote(value: &str) -> String {
+    let value = value.trim();
+    value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value).to_owned()
+  }
+
+  /// Делит аргументы по запятым верхнего уровня, не заходя в строки и вложенные скобки.
+  fn split_args(args: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut depth = 0usize;
+    let mut in_string = false;
+
+    for sym in args.chars() {
+      match sym {
+        '"' => in_string = !in_string,
+        '(' | '[' if !in_string => depth += 1,
+        ')' | ']' if !in_string => depth = depth.saturating_sub(1),
+        ',' if !in_string && depth == 0 => {
+          parts.push(std::mem::take(&mut current));
+          continue
+        },
+        _ => {},
+      }
+      current.push(sym);
+    }
+    parts.push(current);
+
+    parts.into_iter().filter(|p| !p.trim().is_empty()).collect()
+  }
+}
+
+#[cfg(test)]
+mod attrs_tests {
+  use super::*;
+
+  #[test]
+  fn parse_attribute_test() {
+    assert_eq!(Attribute::parse("String,"), None);
+    assert_eq!(
+      Attribute::parse(r#"#[proto(example = "a, b", zigzag)]"#),
+      Some(Attribute { path: "proto".into(), args: vec![("example".into(), Some("a, b".into())), ("zigzag".into(), None)] }),
+    );
+    assert_eq!(Attribute::parse("#[deprecated]"), Some(Attribute { path: "deprecated".into(), args: vec![] }));
+    assert_eq!(
+      Attribute::parse(r#"#[path = "../shared/types.rs"]"#),
+      Some(Attribute { path: "path".into(), args: vec![(String::new(), Some("../shared/types.rs".into()))] }),
+    );
+  }
+
+  #[test]
+  fn cfg_attr_test() {
+    let cfg = vec![r#"feature="serde""#.to_owned(), "unix".to_owned()];
+    let rename = Attribute { path: "serde".into(), args: vec![("rename".into(), Some("x".into()))] };
+    assert_eq!(Attribute::parse_lines([r#"#[cfg_attr(feature = "serde", serde(rename = "x"))]"#].into_iter(), &cfg), vec![rename]);
+    assert_eq!(Attribute::parse_lines([r#"#[cfg_attr(feature = "json", serde(rename = "x"))]"#].into_iter(), &cfg), vec![]);
+    assert_eq!(
+      Attribute::parse_lines([r#"#[cfg_attr(all(unix, not(windows)), derive(Debug), deprecated)]"#, "#[deprecated]"].into_iter(), &cfg).len(),
+      3,
+    );
+    assert_eq!(Attribute::parse_lines([r#"#[cfg_attr(any(windows, feature = "x"), deprecated)]"#].into_iter(), &cfg), vec![]);
+  }
+}
@@ -0,0 +1,87 @@
+//! Помощник для вызова конвертации из `build.rs`.
+//!
+//! ```no_run
+//! // `fn main()` в build.rs
+//! r2proto3::build::compile(".", "proto/generated.proto").unwrap();
+//! ```
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::options::Options;
+use crate::parser::Parser;
+use crate::utils::{MResult, R2Proto3Error};
+
+/// Генерирует файл Protobuf по крейту в `crate_root` и записывает его в `out_path`, возвращая путь к нему.
+///
+/// Для каждого просмотренного файла `.rs` выводится `cargo:rerun-if-changed`, поэтому Cargo перезапускает сборочный
+/// скрипт только при изменении исходников. Новый файл модуля подхватывается, потому что для его подключения меняется
+/// файл с объявлением `mod`. Предупреждения выводятся как `cargo:warning`.
+pub fn compile(crate_root: impl AsRef<Path>, out_path: impl AsRef<Path>) -> MResult<PathBuf> {
+  compile_with_options(crate_root, out_path, Options::default())
+}
+
+/// То же, что [`compile`], но с заданными настройками конвертации.
+pub fn compile_with_options(crate_root: impl AsRef<Path>, out_path: impl AsRef<Path>, options: Options) -> MResult<PathBuf> {
+  compile_into(crate_root.as_ref(), out_path.as_ref(), options, &mut std::io::stdout().lock())
+}
+
+fn compile_into(crate_root: &Path, out_path: &Path, options: Options, directives: &mut impl Write) -> MResult<PathBuf> {
+  let crate_name = crate_root
+    .to_str()
+    .ok_or_else(|| R2Proto3Error::new(None, format!("crate root `{}` isn't valid UTF-8", crate_root.display())))?;
+  let mut parser = Parser::new(crate_name, Options { quiet: true, ..options })?;
+  let parsed = parser.parse();
+  
+  // Файлы отслеживаются и при ошибке разбора, иначе сборка не перезапустится после её исправления
+  let mut output = String::new();
+  for path in &parser.scanned_files {
+    output += &format!("cargo:rerun-if-changed={}\n", path.display());
+  }
+  for warning in parser.warnings() {
+    output += &format!("cargo:warning={}\n", warning.replace('\n', " "));
+  }
+  directives.write_all(output.as_bytes()).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write build script directives"))?;
+  parsed?;
+  
+  // Неизменившийся файл не перезаписывается, чтобы не запускать зависящие от него шаги сборки
+  let contents = parser.generate();
+  if std::fs::read_to_string(out_path).ok().as_deref() != Some(contents.as_str()) {
+    if let Some(dir) = out_path.parent().filter(|d| !d.as_os_str().is_empty()) {
+      std::fs::create_dir_all(dir).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot create output directory"))?;
+    }
+    std::fs::write(out_path, contents).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write proto contents to file"))?;
+  }
+  Ok(out_path.to_path_buf())
+}
+
+#[cfg(test)]
+mod build_tests {
+  use super::*;
+
+  #[test]
+  fn compile_test() {
+    let root = std::env::temp_dir().join(format!("r2proto3_build_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    std::fs::write(root.join("src/lib.rs"), "mod users;\n#[path = \"gone.rs\"]\nmod gone;\n").unwrap();
+    std::fs::write(root.join("src/users.rs"), "// NOTE: ToProtobuf\nstruct User {\n  id: u64,\n}\n").unwrap();
+    
+    let out_path = root.join("proto/generated.proto");
+    let mut directives = vec![];
+    let written = compile_into(&root, &out_path, Options::default(), &mut directives).unwrap();
+    assert_eq!(written, out_path);
+    assert!(std::fs::read_to_string(&out_path).unwrap().contains("message User {"));
+    
+    let directives = String::from_utf8(directives).unwrap();
+    assert!(directives.contains(&format!("cargo:rerun-if-changed={}\n", root.join("src/lib.rs").display())), "{}", directives);
+    assert!(directives.contains(&format!("cargo:rerun-if-changed={}\n", root.join("src/users.rs").display())), "{}", directives);
+    assert!(directives.contains("cargo:warning=module file") && directives.contains("gone.rs"), "{}", directives);
+    
+    // Без изменений файл не перезаписывается
+    let modified = std::fs::metadata(&out_path).unwrap().modified().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    compile_into(&root, &out_path, Options::default(), &mut vec![]).unwrap();
+    assert_eq!(std::fs::metadata(&out_path).unwrap().modified().unwrap(), modified);
+  }
+}
@@ -0,0 +1,219 @@
+//! Запись `FileDescriptorSet` (`google/protobuf/descriptor.proto`) в двоичном формате Protobuf.
+//! Сообщений в дескрипторе немного, поэтому они собираются вручную, без генератора кода.
+
+/// Типы полей `FieldDescriptorProto.Type`.
+pub(crate) const TYPE_MESSAGE: u64 = 11;
+pub(crate) const TYPE_ENUM: u64 = 14;
+
+/// Метки полей `FieldDescriptorProto.Label`.
+pub(crate) const LABEL_OPTIONAL: u64 = 1;
+pub(crate) const LABEL_REQUIRED: u64 = 2;
+pub(crate) const LABEL_REPEATED: u64 = 3;
+
+/// `MethodOptions.IdempotencyLevel.IDEMPOTENT`
+pub(crate) const IDEMPOTENT: u64 = 2;
+
+/// Номер `FieldDescriptorProto.Type` для скалярного типа Protobuf.
+pub(crate) fn scalar_type(name: &str) -> Option<u64> {
+  Some(match name {
+    "double" => 1,
+    "float" => 2,
+    "int64" => 3,
+    "uint64" => 4,
+    "int32" => 5,
+    "fixed64" => 6,
+    "fixed32" => 7,
+    "bool" => 8,
+    "string" => 9,
+    "bytes" => 12,
+    "uint32" => 13,
+    "sfixed32" => 15,
+    "sfixed64" => 16,
+    "sint32" => 17,
+    "sint64" => 18,
+    _ => return None,
+  })
+}
+
+/// Имя поля в JSON, которое `protoc` записывает в `json_name`: `user_id` -> `userId`.
+pub(crate) fn json_name(name: &str) -> String {
+  let mut result = String::new();
+  let mut capitalize = false;
+  for sym in name.chars() {
+    match sym {
+      '_' => capitalize = true,
+      _ if capitalize => {
+        result.extend(sym.to_uppercase());
+        capitalize = false;
+      },
+      _ => result.push(sym),
+    }
+  }
+  result
+}
+
+/// Сообщение, записываемое поле за полем. Поля выводятся в порядке вызовов.
+#[derive(Debug, Default)]
+pub(crate) struct Message {
+  bytes: Vec<u8>,
+}
+
+impl Message {
+  fn raw_varint(&mut self, mut value: u64) {
+    while value >= 0x80 {
+      self.bytes.push((value as u8 & 0x7f) | 0x80);
+      value >>= 7;
+    }
+    self.bytes.push(value as u8);
+  }
+
+  fn key(&mut self, field: u32, wire_type: u8) {
+    self.raw_varint(((field as u64) << 3) | wire_type as u64);
+  }
+
+  pub fn varint(&mut self, field: u32, value: u64) -> &mut Self {
+    self.key(field, 0);
+    self.raw_varint(value);
+    self
+  }
+
+  /// `int32`: отрицательные значения записываются десятью байтами, как `int64`.
+  pub fn int32(&mut self, field: u32, value: i32) -> &mut Self {
+    self.varint(field, value as i64 as u64)
+  }
+
+  pub fn bool(&mut self, field: u32, value: bool) -> &mut Self {
+    self.varint(field, value as u64)
+  }
+
+  pub fn bytes(&mut self, field: u32, value: &[u8]) -> &mut Self {
+    self.key(field, 2);
+    self.raw_varint(value.len() as u64);
+    self.bytes.extend_from_slice(value);
+    self
+  }
+
+  pub fn string(&mut self, field: u32, value: &str) -> &mut Self {
+    self.bytes(field, value.as_bytes())
+  }
+
+  pub fn message(&mut self, field: u32, value: &Message) -> &mut Self {
+    self.bytes(field, &value.bytes)
+  }
+
+  /// Упакованный `repeated int32`.
+  pub fn packed(&mut self, field: u32, values: &[i32]) -> &mut Self {
+    let mut packed = Message::default();
+    for value in values {
+      packed.raw_varint(*value as i64 as u64);
+    }
+    self.bytes(field, &packed.bytes)
+  }
+
+  pub fn into_bytes(self) -> Vec<u8> {
+    self.bytes
+  }
+}
+
+/// Значение поля, прочитанное без схемы: число или содержимое поля с длиной.
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+  Varint(u64),
+  Bytes(Vec<u8>),
+}
+
+#[cfg(test)]
+impl Value {
+  pub fn as_str(&self) -> &str {
+    match self {
+      Value::Bytes(bytes) => std::str::from_utf8(bytes).unwrap(),
+      Value::Varint(_) => panic!("expected a length-delimited field"),
+    }
+  }
+
+  pub fn as_message(&self) -> Vec<(u32, Value)> {
+    match self {
+      Value::Bytes(bytes) => decode(bytes),
+      Value::Varint(_) => panic!("expected a length-delimited field"),
+    }
+  }
+
+  pub fn as_packed(&self) -> Vec<i32> {
+    let mut bytes = match self {
+      Value::Bytes(bytes) => bytes.as_slice(),
+      Value::Varint(_) => panic!("expected a length-delimited field"),
+    };
+    let mut values = vec![];
+    while !bytes.is_empty() {
+      values.push(read_varint(&mut bytes) as i64 as i32);
+    }
+    values
+  }
+}
+
+#[cfg(test)]
+fn read_varint(bytes: &mut &[u8]) -> u64 {
+  let mut value = 0;
+  for shift in (0..64).step_by(7) {
+    let (byte, rest) = bytes.split_first().expect("truncated varint");
+    *bytes = rest;
+    value |= ((byte & 0x7f) as u64) << shift;
+    if byte & 0x80 == 0 { break }
+  }
+  value
+}
+
+/// Разбирает сообщение на поля (номер, значение). Поддерживаются только типы, которые пишет [`Message`].
+#[cfg(test)]
+pub(crate) fn decode(mut bytes: &[u8]) -> Vec<(u32, Value)> {
+  let mut fields = vec![];
+  while !bytes.is_empty() {
+    let key = read_varint(&mut bytes);
+    let value = match key & 7 {
+      0 => Value::Varint(read_varint(&mut bytes)),
+      2 => {
+        let len = read_varint(&mut bytes) as usize;
+        let (value, rest) = bytes.split_at(len);
+        bytes = rest;
+        Value::Bytes(value.to_vec())
+      },
+      wire_type => panic!("unexpected wire type {}", wire_type),
+    };
+    fields.push(((key >> 3) as u32, value));
+  }
+  fields
+}
+
+#[cfg(test)]
+mod descriptor_tests {
+  use super::*;
+
+  #[test]
+  fn message_encoding_test() {
+    let mut inner = Message::default();
+    inner.string(1, "id");
+    let mut message = Message::default();
+    message.varint(1, 150).int32(2, -1).bool(3, true).message(4, &inner).packed(5, &[3, 270, -2]);
+    let bytes = message.into_bytes();
+    assert_eq!(&bytes[..3], &[0x08, 0x96, 0x01]);
+    assert_eq!(decode(&bytes), vec![
+      (1, Value::Varint(150)),
+      (2, Value::Varint(u64::MAX)),
+      (3, Value::Varint(1)),
+      (4, Value::Bytes(vec![0x0a, 0x02, b'i', b'd'])),
+      (5, Value::Bytes(vec![0x03, 0x8e, 0x02, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01])),
+    ]);
+    assert_eq!(decode(&bytes)[3].1.as_message(), vec![(1, Value::Bytes(b"id".to_vec()))]);
+    assert_eq!(decode(&bytes)[4].1.as_packed(), vec![3, 270, -2]);
+  }
+
+  #[test]
+  fn names_test() {
+    assert_eq!(scalar_type("sint64"), Some(18));
+    assert_eq!(scalar_type("User"), None);
+    assert_eq!(json_name("user_id"), "userId");
+    assert_eq!(json_name("anonymous_value_1"), "anonymousValue1");
+    assert_eq!(json_name("id"), "id");
+  }
+}
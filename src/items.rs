@@ -0,0 +1,615 @@
+use std::collections::BTreeMap;
+
+use proc_macro2::{LineColumn, Span};
+use syn::ext::IdentExt;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{Expr, Fields, FnArg, Generics, Ident, ImplItemConst, ImplItemFn, ItemConst, ItemEnum, ItemFn, ItemStruct, ItemType, ReturnType, Signature, TraitItemFn, Type};
+
+use crate::attrs::Attribute;
+use crate::utils::{MResult, R2Proto3Error};
+
+/// Пометка, после которой тип переводится в Protobuf.
+pub(crate) const MARKER: &str = "// NOTE: ToProtobuf";
+
+/// Целочисленные типы констант, которые можно подставить в дискриминанты.
+const INTEGER_TYPES: [&str; 12] = ["i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ItemKind {
+  Struct,
+  Enum,
+}
+
+/// Помеченная структура или перечисление.
+#[derive(Debug, PartialEq)]
+pub(crate) struct AnnotatedItem {
+  pub kind: ItemKind,
+  pub name: String,
+  /// Текст между пометкой и ключевым словом `struct`/`enum`: атрибуты, комментарии, модификаторы
+  pub gap: String,
+  /// Поля структуры; у перечислений пустой
+  pub fields: Vec<SourceField>,
+  /// Варианты перечисления; у структур пустой
+  pub variants: Vec<SourceVariant>,
+  /// Имена параметров-типов: `T` у `Page<'a, T>`
+  pub generics: Vec<String>,
+  /// Список конкретизаций из пометки: `User, Order` у `// NOTE: ToProtobuf<User, Order>`; пустой, если его нет
+  pub instantiations: String,
+  /// Строка ключевого слова `struct`/`enum` в исходном файле, начиная с 1
+  pub line: usize,
+}
+
+/// Поле структуры или варианта перечисления.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SourceField {
+  /// Имя поля без `r#`; у полей кортежей его нет
+  pub name: Option<String>,
+  /// Тип поля, склеенный в одну строку: `HashMap<\n  String,\n  u32,\n>` -> `HashMap<String, u32>`
+  pub ty: String,
+  pub annotations: Annotations,
+}
+
+impl SourceField {
+  /// Объявление поля для сообщений об ошибках: `id: u64`.
+  pub(crate) fn declaration(&self) -> String {
+    match &self.name {
+      Some(name) => format!("{}: {}", name, self.ty),
+      None => self.ty.to_owned(),
+    }
+  }
+}
+
+/// Вид данных варианта перечисления.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum VariantShape {
+  /// `Quit`
+  Unit,
+  /// `Write(String)`
+  Tuple,
+  /// `Move { x: i32, y: i32 }`
+  Named,
+}
+
+/// Вариант перечисления.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SourceVariant {
+  pub name: String,
+  pub shape: VariantShape,
+  pub fields: Vec<SourceField>,
+  /// Явный дискриминант: `5` у `Active = 5`
+  pub discriminant: Option<String>,
+  pub annotations: Annotations,
+}
+
+/// Атрибуты и комментарии поля структуры или варианта перечисления.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Annotations {
+  /// Атрибуты (каждый в одну строку) и комментарии перед полем в порядке исходного текста
+  pub preamble: Vec<String>,
+  /// Комментарий в конце строки поля: `// = 7` у `id: u64, // = 7`
+  pub trailing: Option<String>,
+  /// Строка исходного файла, на которой начинается поле (без атрибутов), начиная с 1
+  pub line: usize,
+}
+
+/// Помеченная функция.
+#[derive(Debug, PartialEq)]
+pub(crate) struct AnnotatedFn {
+  /// Аргументы пометки вместе со скобками: `(service = "Admin")` у `// NOTE: ToProtobuf(service = "Admin")`; пустые, если их нет
+  pub marker_args: String,
+  /// Текст между пометкой и ключевым словом `fn`
+  pub gap: String,
+  pub name: String,
+  /// Типы аргументов, кроме `self`
+  pub args: Vec<String>,
+  /// Возвращаемый тип; `None`, если функция ничего не возвращает
+  pub ret: Option<String>,
+}
+
+/// Всё, что извлекается из файла синтаксическим разбором.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct FileItems {
+  pub items: Vec<AnnotatedItem>,
+  pub fns: Vec<AnnotatedFn>,
+  /// Целочисленные константы `const NAME: iN = value;`, в том числе ассоциированные
+  pub consts: BTreeMap<String, String>,
+  /// Псевдонимы типов без параметров `type X = Y;` в порядке объявления
+  pub aliases: Vec<(String, String)>,
+}
+
+/// Собирает документирующий комментарий из строк `///`; обычные комментарии и теги `/// @proto` пропускаются.
+pub(crate) fn doc_comment<'s>(lines: impl Iterator<Item = &'s str>) -> Option<String> {
+  let lines = lines
+    .filter_map(|l| l.trim().strip_prefix("///"))
+    .filter(|l| !l.starts_with('/') && !l.trim_start().starts_with("@proto "))
+    .map(|l| l.strip_prefix(' ').unwrap_or(l).trim_end())
+    .collect::<Vec<_>>();
+  if lines.is_empty() { None } else { Some(lines.join("\n")) }
+}
+
+impl Annotations {
+  pub(crate) fn doc(&self) -> Option<String> {
+    doc_comment(self.preamble.iter().map(|l| l.as_str()))
+  }
+  
+  /// Явный номер поля: атрибут `#[proto(tag = N)]`, комментарий `// field = N` (или `// field: N`) в конце строки поля
+  /// или перед ним, либо короткий комментарий `// = N` в конце строки поля.
+  pub(crate) fn explicit_number(&self, cfg: &[String]) -> MResult<Option<i32>> {
+    let trailing = self.trailing.as_deref();
+    let attrs = self.attributes(cfg);
+    let short = trailing
+      .filter(|l| !l.starts_with("///"))
+      .and_then(|l| l.strip_prefix("//"))
+      .and_then(|l| l.trim().strip_prefix('='));
+    let annotation = self.preamble
+      .iter()
+      .map(|l| l.as_str())
+      .chain(trailing)
+      .filter(|l| !l.starts_with("///"))
+      .filter_map(|l| l.strip_prefix("//"))
+      .filter_map(|l| l.trim().strip_prefix("field"))
+      .find_map(|l| l.trim_start().strip_prefix(['=', ':']));
+    let Some(annotation) = Attribute::find(&attrs, "proto", "tag").flatten().or(annotation).or(short) else { return Ok(None) };
+    
+    match annotation.trim().parse::<i32>() {
+      Ok(number) if (1..=536_870_911).contains(&number) && !(19_000..20_000).contains(&number) => Ok(Some(number)),
+      _ => Err(R2Proto3Error::new(None, format!(
+        "`{}` isn't a valid field number: expected 1..=536870911 outside the reserved 19000..=19999 range",
+        annotation.trim(),
+      ))),
+    }
+  }
+  
+  pub(crate) fn attributes(&self, cfg: &[String]) -> Vec<Attribute> {
+    Attribute::parse_lines(self.preamble.iter().map(|l| l.as_str()), cfg)
+  }
+  
+  /// Атрибут `#[deprecated]` (в том числе с `since`/`note`) или комментарий `// deprecated` перед полем или в конце его строки.
+  pub(crate) fn is_deprecated(&self, cfg: &[String]) -> bool {
+    let trailing = self.trailing.as_deref();
+    let comment = self.preamble
+      .iter()
+      .map(|l| l.as_str())
+      .chain(trailing)
+      .filter(|l| !l.starts_with("///"))
+      .filter_map(|l| l.strip_prefix("//"))
+      .any(|l| l.trim().split([' ', ':']).next() == Some("deprecated"));
+    comment || self.attributes(cfg).iter().any(|a| a.path == "deprecated")
+  }
+  
+  /// Заметки `// TODO`/`// FIXME` перед полем и в конце его строки.
+  pub(crate) fn todos(&self) -> Vec<String> {
+    let trailing = self.trailing.as_deref();
+    self.preamble
+      .iter()
+      .map(|l| l.as_str())
+      .chain(trailing)
+      .filter(|l| !l.starts_with("///"))
+      .filter_map(|l| l.strip_prefix("//"))
+      .map(|l| l.trim())
+      .filter(|l| l.starts_with("TODO") || l.starts_with("FIXME"))
+      .map(|l| l.to_owned())
+      .collect()
+  }
+  
+  /// Опции поля из тегов в документации: `/// @proto json_name=user_id deprecated`.
+  /// Флаг без значения означает `= true`, логические и числовые значения выводятся как есть, прочие - строками.
+  pub(crate) fn doc_tag_options(&self) -> Vec<(String, String)> {
+    let mut options = vec![];
+    for tags in self.preamble.iter().filter_map(|l| l.strip_prefix("///")).filter_map(|l| l.trim().strip_prefix("@proto ")) {
+      for tag in tags.split_whitespace() {
+        let (key, value) = match tag.split_once('=') {
+          None => (tag, "true".to_owned()),
+          Some((key, value)) if value == "true" || value == "false" || value.parse::<f64>().is_ok() => (key, value.to_owned()),
+          Some((key, value)) => (key, format!("\"{}\"", value.trim_matches('"'))),
+        };
+        options.push((key.to_owned(), value));
+      }
+    }
+    options
+  }
+}
+
+/// Находит помеченные типы и функции синтаксическим разбором файла, в том числе внутри модулей, функций и блоков `impl`,
+/// а также все целочисленные константы и псевдонимы типов.
+///
+/// Комментарии в синтаксическое дерево не попадают, поэтому пометка, зазор и комментарии полей берутся из исходного текста
+/// по позициям токенов.
+pub(crate) fn parse_file(contents: &str) -> syn::Result<FileItems> {
+  let file = syn::parse_file(contents)?;
+  let mut collector = Collector { source: Source::new(contents), items: FileItems::default() };
+  collector.visit_file(&file);
+  Ok(collector.items)
+}
+
+/// Исходный текст с переводом позиций токенов (строка, столбец в символах) в байтовые смещения.
+struct Source<'s> {
+  contents: &'s str,
+  line_starts: Vec<usize>,
+}
+
+impl<'s> Source<'s> {
+  fn new(contents: &'s str) -> Self {
+    let line_starts = std::iter::once(0).chain(contents.match_indices('\n').map(|(i, _)| i + 1)).collect();
+    Self { contents, line_starts }
+  }
+
+  fn offset(&self, position: LineColumn) -> usize {
+    let Some(&line_start) = self.line_starts.get(position.line.saturating_sub(1)) else { return self.contents.len() };
+    self.contents[line_start..]
+      .char_indices()
+      .nth(position.column)
+      .map(|(i, _)| line_start + i)
+      .unwrap_or(self.contents.len())
+  }
+
+  fn start(&self, span: Span) -> usize {
+    self.offset(span.start())
+  }
+
+  fn end(&self, span: Span) -> usize {
+    self.offset(span.end())
+  }
+
+  fn text(&self, span: Span) -> &'s str {
+    &self.contents[self.start(span)..self.end(span)]
+  }
+
+  /// Номер строки байтового смещения, начиная с 1.
+  fn line(&self, offset: usize) -> usize {
+    self.line_starts.partition_point(|&start| start <= offset)
+  }
+
+  /// Конец строки, на которой стоит смещение (позиция `\n` или конец файла).
+  fn line_end(&self, offset: usize) -> usize {
+    self.contents[offset..].find('\n').map(|i| offset + i).unwrap_or(self.contents.len())
+  }
+
+  /// Пометка относится к элементу, если стоит среди его атрибутов или перед ним, отделённая только пробелами и комментариями.
+  /// После неё на той же строке может стоять только список конкретизаций `<User, Order>` или аргументы `(service = "Admin")`,
+  /// иначе это не комментарий, а, например, строковый литерал. Возвращает конец пометки и остаток её строки.
+  fn marker_end(&self, item_start: usize, keyword_start: usize) -> Option<(usize, &'s str)> {
+    let marker = self.contents[..keyword_start].rfind(MARKER)?;
+    let mut marker_end = marker + MARKER.len();
+    let rest_of_marker_line = self.line_end(marker_end);
+    let args = self.contents[marker_end..rest_of_marker_line].trim();
+    if !args.is_empty() {
+      marker_end = rest_of_marker_line;
+      if !(args.starts_with('<') && args.ends_with('>') || args.starts_with('(') && args.ends_with(')')) { return None }
+    }
+    if marker >= item_start {
+      return Some((marker_end, args))
+    }
+    self.contents[rest_of_marker_line.min(item_start)..item_start]
+      .lines()
+      .map(|l| l.trim())
+      .all(|l| l.is_empty() || l.starts_with("//"))
+      .then_some((marker_end, args))
+  }
+
+  /// Тип, склеенный в одну строку: `HashMap<\n  String,\n  u32,\n>` -> `HashMap<String, u32>`.
+  fn ty(&self, ty: &Type) -> String {
+    self.text(ty.span())
+      .lines()
+      .map(|l| l.split("//").next().unwrap_or_default().trim())
+      .fold(String::new(), |acc, l| match acc.strip_suffix(',') {
+        // Висячая запятая перед закрывающей скобкой отбрасывается
+        Some(acc) if l.starts_with(['>', ')', ']']) => acc.to_owned() + l,
+        Some(_) => acc + " " + l,
+        None => acc + l,
+      })
+  }
+
+  /// Атрибуты (многострочные склеиваются в одну строку) и комментарии `//` из промежутка `[from, to)` в порядке исходного текста.
+  fn preamble(&self, from: usize, attrs: &[syn::Attribute], to: usize) -> Vec<String> {
+    let comments = |text: &str| text.lines().map(|l| l.trim()).filter(|l| l.starts_with("//")).map(|l| l.to_owned()).collect::<Vec<_>>();
+    let mut preamble = vec![];
+    let mut copied = from;
+    for attr in attrs {
+      let (start, end) = (self.start(attr.span()), self.end(attr.span()));
+      if start < copied { continue }
+      preamble.extend(comments(&self.contents[copied..start]));
+      preamble.push(self.contents[start..end].lines().map(|l| l.trim()).collect::<Vec<_>>().join(" "));
+      copied = end;
+    }
+    preamble.extend(comments(&self.contents[copied..to.max(copied)]));
+    preamble
+  }
+
+  /// Комментарий в конце строки после смещения `end`, если до него стоит только запятая.
+  fn trailing(&self, end: usize) -> Option<String> {
+    let rest = self.contents[end..self.line_end(end)].trim_start();
+    let rest = rest.strip_prefix(',').unwrap_or(rest).trim();
+    rest.starts_with("//").then(|| rest.to_owned())
+  }
+
+  /// Атрибуты и комментарии элемента, который начинается после атрибутов со смещения `start` и заканчивается на `end`.
+  /// `from` - конец предыдущего элемента. Возвращает их вместе с началом промежутка для следующего элемента.
+  fn annotations(&self, from: usize, attrs: &[syn::Attribute], start: usize, end: usize) -> (Annotations, usize) {
+    let trailing = self.trailing(end);
+    // Комментарий в конце строки принадлежит этому элементу, а не следующему
+    let next = if trailing.is_some() { self.line_end(end) } else { end };
+    (Annotations { preamble: self.preamble(from, attrs, start), trailing, line: self.line(start) }, next)
+  }
+
+  /// Поля структуры или варианта; `from` - конец открывающей скобки.
+  fn fields(&self, fields: &Fields, mut from: usize) -> Vec<SourceField> {
+    let mut result = vec![];
+    for field in fields {
+      let start = self.start(field.ident.as_ref().map(|i| i.span()).unwrap_or(field.ty.span()));
+      let (annotations, next) = self.annotations(from, &field.attrs, start, self.end(field.span()));
+      result.push(SourceField { name: field.ident.as_ref().map(|i| i.unraw().to_string()), ty: self.ty(&field.ty), annotations });
+      from = next;
+    }
+    result
+  }
+
+  fn fields_start(&self, fields: &Fields) -> usize {
+    match fields {
+      Fields::Named(fields) => self.end(fields.brace_token.span.open()),
+      Fields::Unnamed(fields) => self.end(fields.paren_token.span.open()),
+      Fields::Unit => 0,
+    }
+  }
+}
+
+struct Collector<'s> {
+  source: Source<'s>,
+  items: FileItems,
+}
+
+impl Collector<'_> {
+  /// Помеченный элемент без полей и вариантов; `None`, если пометки перед ним нет.
+  fn marked(&self, kind: ItemKind, item_span: Span, keyword_span: Span, name: &Ident, generics: &Generics) -> Option<AnnotatedItem> {
+    let keyword_start = self.source.start(keyword_span);
+    let (marker_end, args) = self.source.marker_end(self.source.start(item_span), keyword_start)?;
+    let instantiations = args.strip_prefix('<').and_then(|a| a.strip_suffix('>')).or(args.is_empty().then_some(""))?;
+    Some(AnnotatedItem {
+      kind,
+      name: name.to_string(),
+      gap: self.source.contents[marker_end..keyword_start].to_owned(),
+      fields: vec![],
+      variants: vec![],
+      generics: generics.type_params().map(|p| p.ident.to_string()).collect(),
+      instantiations: instantiations.trim().to_owned(),
+      line: self.source.line(keyword_start),
+    })
+  }
+
+  fn push_fn(&mut self, item_span: Span, sig: &Signature) {
+    let keyword_start = self.source.start(sig.fn_token.span);
+    let Some((marker_end, args)) = self.source.marker_end(self.source.start(item_span), keyword_start) else { return };
+    if args.starts_with('<') { return }
+    self.items.fns.push(AnnotatedFn {
+      marker_args: args.to_owned(),
+      gap: self.source.contents[marker_end..keyword_start].to_owned(),
+      name: sig.ident.unraw().to_string(),
+      args: sig.inputs.iter().filter_map(|arg| match arg {
+        FnArg::Typed(arg) => Some(self.source.ty(&arg.ty)),
+        FnArg::Receiver(_) => None,
+      }).collect(),
+      ret: match &sig.output {
+        ReturnType::Type(_, ty) => Some(self.source.ty(ty)),
+        ReturnType::Default => None,
+      },
+    });
+  }
+
+  fn push_const(&mut self, name: &Ident, ty: &Type, value: &Expr) {
+    if INTEGER_TYPES.contains(&self.source.ty(ty).as_str()) {
+      self.items.consts.insert(name.to_string(), self.source.text(value.span()).trim().to_owned());
+    }
+  }
+}
+
+impl<'ast> Visit<'ast> for Collector<'_> {
+  fn visit_item_struct(&mut self, item: &'ast ItemStruct) {
+    let Some(mut marked) = self.marked(ItemKind::Struct, item.span(), item.struct_token.span, &item.ident, &item.generics) else { return };
+    marked.fields = self.source.fields(&item.fields, self.source.fields_start(&item.fields));
+    self.items.items.push(marked);
+  }
+
+  fn visit_item_enum(&mut self, item: &'ast ItemEnum) {
+    let Some(mut marked) = self.marked(ItemKind::Enum, item.span(), item.enum_token.span, &item.ident, &item.generics) else { return };
+    let mut from = self.source.end(item.brace_token.span.open());
+    for variant in &item.variants {
+      let (annotations, next) = self.source.annotations(from, &variant.attrs, self.source.start(variant.ident.span()), self.source.end(variant.span()));
+      marked.variants.push(SourceVariant {
+        name: variant.ident.unraw().to_string(),
+        shape: match variant.fields {
+          Fields::Named(_) => VariantShape::Named,
+          Fields::Unnamed(_) => VariantShape::Tuple,
+          Fields::Unit => VariantShape::Unit,
+        },
+        fields: self.source.fields(&variant.fields, self.source.fields_start(&variant.fields)),
+        discriminant: variant.discriminant.as_ref().map(|(_, value)| self.source.text(value.span()).trim().to_owned()),
+        annotations,
+      });
+      from = next;
+    }
+    self.items.items.push(marked);
+  }
+
+  fn visit_item_fn(&mut self, item: &'ast ItemFn) {
+    self.push_fn(item.span(), &item.sig);
+    visit::visit_item_fn(self, item);
+  }
+
+  fn visit_impl_item_fn(&mut self, item: &'ast ImplItemFn) {
+    self.push_fn(item.span(), &item.sig);
+    visit::visit_impl_item_fn(self, item);
+  }
+
+  fn visit_trait_item_fn(&mut self, item: &'ast TraitItemFn) {
+    self.push_fn(item.span(), &item.sig);
+    visit::visit_trait_item_fn(self, item);
+  }
+
+  fn visit_item_const(&mut self, item: &'ast ItemConst) {
+    self.push_const(&item.ident, &item.ty, &item.expr);
+    visit::visit_item_const(self, item);
+  }
+
+  fn visit_impl_item_const(&mut self, item: &'ast ImplItemConst) {
+    self.push_const(&item.ident, &item.ty, &item.expr);
+    visit::visit_impl_item_const(self, item);
+  }
+
+  fn visit_item_type(&mut self, item: &'ast ItemType) {
+    if item.generics.params.is_empty() {
+      self.items.aliases.push((item.ident.to_string(), self.source.ty(&item.ty)));
+    }
+    visit::visit_item_type(self, item);
+  }
+}
+
+#[cfg(test)]
+mod items_tests {
+  use super::*;
+
+  fn field(name: &str, ty: &str, preamble: &[&str], trailing: Option<&str>, line: usize) -> SourceField {
+    SourceField {
+      name: Some(name.into()),
+      ty: ty.into(),
+      annotations: Annotations { preamble: preamble.iter().map(|s| s.to_string()).collect(), trailing: trailing.map(|s| s.into()), line },
+    }
+  }
+
+  #[test]
+  fn annotated_items_test() {
+    let contents = r#"
+// NOTE: ToProtobuf
+/// A user.
+#[serde(rename_all = "camelCase", bound(deserialize = "T: Deserialize<'de>"))]
+pub struct User<'a, T: Clone> where T: Default {
+  name: &'a str,
+  #[cfg(feature = "extra")]
+  extra: T,
+}
+
+struct Unmarked {
+  id: u64,
+}
+
+#[derive(Debug)]
+// NOTE: ToProtobuf
+pub(crate) enum Side { Left, Right = 3 }
+
+mod inner {
+  // NOTE: ToProtobuf
+  // Keep in sync with the client.
+  struct Id(u64);
+
+  fn helper() {
+    let _ = "// NOTE: ToProtobuf\nstruct Fake { a: u8 }";
+  }
+}
+"#;
+    let items = parse_file(contents).unwrap().items;
+    assert_eq!(items.iter().map(|i| (i.kind, i.name.as_str(), i.line)).collect::<Vec<_>>(), vec![
+      (ItemKind::Struct, "User", 5),
+      (ItemKind::Enum, "Side", 17),
+      (ItemKind::Struct, "Id", 22),
+    ]);
+    assert_eq!(items[0].gap, "\n/// A user.\n#[serde(rename_all = \"camelCase\", bound(deserialize = \"T: Deserialize<'de>\"))]\npub ");
+    assert_eq!(items[0].generics, vec!["T".to_string()]);
+    assert_eq!(items[0].fields, vec![
+      field("name", "&'a str", &[], None, 6),
+      field("extra", "T", &["#[cfg(feature = \"extra\")]"], None, 8),
+    ]);
+    assert_eq!(items[1].variants.iter().map(|v| (v.name.as_str(), v.shape, v.discriminant.as_deref())).collect::<Vec<_>>(), vec![
+      ("Left", VariantShape::Unit, None),
+      ("Right", VariantShape::Unit, Some("3")),
+    ]);
+    assert_eq!(items[2].gap, "\n  // Keep in sync with the client.\n  ");
+    assert_eq!(items[2].fields.iter().map(|f| (f.name.clone(), f.ty.as_str())).collect::<Vec<_>>(), vec![(None, "u64")]);
+  }
+
+  #[test]
+  fn field_annotations_test() {
+    let contents = "// NOTE: ToProtobuf\nstruct Index {\n  /// Names.\n  // = 4\n  #[serde(\n    rename = \"names\",\n  )]\n  by_name: HashMap<\n    String,\n    Vec<u64>,\n  >, // names\n  r#type: u8,\n}\n";
+    let fields = &parse_file(contents).unwrap().items[0].fields;
+    assert_eq!(fields, &vec![
+      field("by_name", "HashMap<String, Vec<u64>>", &["/// Names.", "// = 4", "#[serde( rename = \"names\", )]"], Some("// names"), 8),
+      field("type", "u8", &[], None, 12),
+    ]);
+    assert_eq!(fields[0].annotations.doc(), Some("Names.".into()));
+    assert_eq!(fields[0].declaration(), "by_name: HashMap<String, Vec<u64>>");
+  }
+
+  #[test]
+  fn data_variants_test() {
+    let contents = "// NOTE: ToProtobuf\nenum Event {\n  Quit, // = 3\n  #[deprecated]\n  Write(String),\n  Move { x: i32, y: i32 },\n}\n";
+    let variants = &parse_file(contents).unwrap().items[0].variants;
+    assert_eq!(variants.iter().map(|v| (v.name.as_str(), v.shape)).collect::<Vec<_>>(), vec![
+      ("Quit", VariantShape::Unit),
+      ("Write", VariantShape::Tuple),
+      ("Move", VariantShape::Named),
+    ]);
+    assert_eq!(variants[0].annotations.trailing.as_deref(), Some("// = 3"));
+    assert_eq!(variants[1].annotations.preamble, vec!["#[deprecated]".to_string()]);
+    assert_eq!(variants[1].fields.iter().map(|f| f.declaration()).collect::<Vec<_>>(), vec!["String"]);
+    assert_eq!(variants[2].fields.iter().map(|f| f.declaration()).collect::<Vec<_>>(), vec!["x: i32", "y: i32"]);
+  }
+
+  #[test]
+  fn marker_separated_by_code_test() {
+    let contents = "// NOTE: ToProtobuf\nconst A: u8 = 1;\nstruct B {\n  b: u8,\n}\n";
+    assert_eq!(parse_file(contents).unwrap().items, vec![]);
+    let contents = "const MARKER: &str = \"// NOTE: ToProtobuf\";\n\n#[derive(Debug)]\nenum Kind {\n  A,\n}\n";
+    assert_eq!(parse_file(contents).unwrap().items, vec![]);
+    assert!(parse_file("struct {").is_err());
+  }
+
+  #[test]
+  fn marker_instantiations_test() {
+    let contents = "// NOTE: ToProtobuf<User, Order>\n/// A page.\nstruct Page<T> {\n  items: Vec<T>,\n}\n\n// NOTE: ToProtobuf <(u8, u16)>\nenum Pair<A, B> { Left(A), Right(B) }\n";
+    let items = parse_file(contents).unwrap().items;
+    assert_eq!(items.iter().map(|i| (i.name.as_str(), i.generics.clone(), i.instantiations.as_str())).collect::<Vec<_>>(), vec![
+      ("Page", vec!["T".to_string()], "User, Order"),
+      ("Pair", vec!["A".to_string(), "B".to_string()], "(u8, u16)"),
+    ]);
+    assert_eq!(parse_file("// NOTE: ToProtobuf for later\nstruct Page<T> {\n  items: Vec<T>,\n}\n").unwrap().items, vec![]);
+  }
+
+  #[test]
+  fn fns_consts_aliases_test() {
+    let contents = r#"
+const LIMIT: u32 = 10;
+const NAME: &str = "x";
+type Id = u64;
+type Page<T> = Vec<T>;
+
+struct Api;
+
+impl Api {
+  const BASE: i64 = -1;
+
+  // NOTE: ToProtobuf(service = "Admin")
+  pub async fn ping(&self, req: PingRequest) -> Result<
+    Pong,
+    Error,
+  > {
+    todo!()
+  }
+}
+
+// NOTE: ToProtobuf
+fn notify(event: Event) {}
+"#;
+    let items = parse_file(contents).unwrap();
+    assert_eq!(items.consts, BTreeMap::from([("BASE".to_string(), "-1".to_string()), ("LIMIT".to_string(), "10".to_string())]));
+    assert_eq!(items.aliases, vec![("Id".to_string(), "u64".to_string())]);
+    assert_eq!(items.fns, vec![
+      AnnotatedFn {
+        marker_args: "(service = \"Admin\")".into(),
+        gap: "\n  pub async ".into(),
+        name: "ping".into(),
+        args: vec!["PingRequest".into()],
+        ret: Some("Result<Pong, Error>".into()),
+      },
+      AnnotatedFn { marker_args: String::new(), gap: "\n".into(), name: "notify".into(), args: vec!["Event".into()], ret: None },
+    ]);
+  }
+}
@@ -0,0 +1,41 @@
+//! Маленькая утилита для конвертации помеченных структур, перечислений и функций в файл Protobuf 3
+//! для обеспечения работы с gRPC-микросервисами.
+//!
+//! Библиотека позволяет вызывать конвертацию из `build.rs` без запуска отдельного бинарника:
+//!
+//! ```no_run
+//! let proto = r2proto3::generate_proto(std::path::Path::new("."), r2proto3::Options::default()).unwrap();
+//! std::fs::write("generated.proto", proto).unwrap();
+//! ```
+
+mod utils;
+
+mod attrs;
+mod items;
+mod options;
+mod types;
+mod descriptor;
+mod parser;
+mod server;
+
+pub mod build;
+
+use std::path::Path;
+
+pub use crate::options::{BignumRepr, BraceStyle, HalfRepr, OptionalBoolRepr, Options, Syntax, TupleFieldStyle, UsizeWidth};
+pub use crate::parser::{convert, GenerationResult, Parser};
+pub use crate::server::serve;
+pub use crate::types::TypesParser;
+pub use crate::utils::{diff_against, load_mapping_file, validate_with_protoc, MResult, R2Proto3Error, Timings};
+
+/// Разбирает крейт в `crate_root` и возвращает текст файла Protobuf.
+///
+/// Файлы пакетов `--auto-package-from-modules` и общих вспомогательных типов не создаются; для них есть [`Parser`].
+pub fn generate_proto(crate_root: &Path, options: Options) -> Result<String, R2Proto3Error> {
+  let crate_root = crate_root
+    .to_str()
+    .ok_or_else(|| R2Proto3Error::new(None, format!("crate root `{}` isn't valid UTF-8", crate_root.display())))?;
+  let mut parser = Parser::new(crate_root, options)?;
+  parser.parse()?;
+  Ok(parser.generate())
+}
@@ -1,17 +1,9 @@
-//! Маленькая утилита для конвертации помеченных структур, перечислений и функций в файл Protobuf 3
-//! для обеспечения работы с gRPC-микросервисами.
+//! Интерфейс командной строки над библиотекой `r2proto3`.
 
-#![feature(let_chains)]
+use clap::{Parser as ArgParser, ValueEnum};
 
-mod utils;
-
-mod types;
-mod parser;
-
-use clap::Parser as ArgParser;
-use utils::R2Proto3Error;
-
-use crate::parser::Parser;
+use r2proto3::{BignumRepr, BraceStyle, HalfRepr, OptionalBoolRepr, Options, Parser, R2Proto3Error, Syntax, TupleFieldStyle, TypesParser, UsizeWidth};
+use r2proto3::{diff_against, load_mapping_file, serve, validate_with_protoc};
 
 /// Translates all `NOTE: ToProtobuf`-attributed structs, enums and functions from whole crate to Protobuf 3 file.
 #[derive(ArgParser, Debug)]
@@ -19,20 +11,188 @@ use crate::parser::Parser;
 // NOTE: ToProtobuf
 struct Args {
   /// Path to selected crate
-  #[arg(short, long)]
-  crate_root: String,
+  #[arg(short, long, required_unless_present = "server")]
+  crate_root: Option<String>,
+  /// Only scan workspace members whose path relative to the crate root matches this glob, e.g. `services/*` (repeatable)
+  #[arg(long)]
+  crate_filter: Vec<String>,
+  /// Skip paths relative to the crate root matching this glob, e.g. `vendor`, `tests/fixtures` or `**/generated` (repeatable).
+  /// Cargo `target` directories are always skipped
+  #[arg(long)]
+  exclude: Vec<String>,
+  /// Skip files and directories listed in `.gitignore`
+  #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+  respect_gitignore: bool,
+  /// Syntax of the generated file; in `proto2` mode fields get `required`/`optional` labels
+  #[arg(long, value_enum, default_value = "proto3")]
+  syntax: Syntax,
   /// Ignore functions (rpc-services)
-  // #[arg(short, long, default_value = "false")]
-  // ignore_rpc: bool,
+  #[arg(short, long, default_value = "false")]
+  ignore_rpc: bool,
   /// Panic when marked type cannot be translated into Protobuf 3
   #[arg(short, long, default_value = "false")]
   panic_to_unsupported: bool,
   /// Verbose mode
   #[arg(short, long, default_value = "false")]
   verbose: bool,
+  /// Warn about lossy or not bit-exact type conversions
+  #[arg(long, default_value = "false")]
+  strict: bool,
+  /// Emit `#[proto(example = "...")]` values as trailing field comments
+  #[arg(long, default_value = "false")]
+  emit_examples: bool,
+  /// Map signed integers to zigzag-encoded `sint32`/`sint64` (not wire-compatible with `int32`/`int64`)
+  #[arg(long, default_value = "false")]
+  signed_as_zigzag: bool,
+  /// File with `module = proto_type` lines mapping `#[serde(with = "module")]` fields to proto types
+  #[arg(long)]
+  serde_with_map: Option<String>,
+  /// Protobuf package, e.g. `acme.users.v1` [default: crate directory name, or `generated` if it isn't a valid package]
+  #[arg(long)]
+  package: Option<String>,
+  /// Name of the service for annotated functions [default: crate directory name in PascalCase].
+  /// Functions marked `// NOTE: ToProtobuf(service = "Admin")` go to a separate `Admin` service
+  #[arg(long)]
+  service_name: Option<String>,
+  /// Put each type into a package derived from its module path (`src/billing/invoices.rs` -> `billing.invoices`).
+  /// Every such package is written to its own file next to the output file (`billing/invoices.proto`);
+  /// types of the crate root module and the service stay in the output file
+  #[arg(long)]
+  auto_package_from_modules: bool,
+  /// Write the types of each module to its own file (`src/billing/invoices.rs` -> `billing/invoices.proto`) in the same package,
+  /// importing the files of the modules it references; types of the crate root module and the service stay in the output file
+  #[arg(long, requires = "output_dir")]
+  split_by_module: bool,
+  /// Template file for the whole output with `{{syntax}}`, `{{package}}`, `{{imports}}` and `{{body}}` placeholders.
+  /// Replaces the built-in preamble, so `--license-header`, `--prepend` and `--emit-content-hash` are not applied
+  #[arg(long)]
+  template: Option<String>,
+  /// File whose lines are emitted as `//` comments before the syntax declaration
+  #[arg(long)]
+  license_header: Option<String>,
+  /// Comment line to insert at the top of the generated file, right after the syntax declaration (repeatable)
+  #[arg(long)]
+  prepend: Vec<String>,
+  /// Resolve references like `crate::users::User` to annotated types by their last path segment
+  #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+  strip_module_paths: bool,
+  /// How to represent `BigInt`/`BigUint`: decimal string or big-endian bytes (`BigDecimal` is always a string)
+  #[arg(long, value_enum, default_value = "string")]
+  bignum_as: BignumRepr,
+  /// How to represent `f16`/`bf16`: widened `float` or bit-exact little-endian `bytes`
+  #[arg(long, value_enum, default_value = "float")]
+  half_as: HalfRepr,
+  /// Integer width assumed for `usize`/`isize`: `64` maps them to `uint64`/`int64`, `32` to `uint32`/`int32`
+  #[arg(long, value_enum, default_value = "64")]
+  usize_as: UsizeWidth,
+  /// Map an annotated `Money { amount: Decimal, currency: String }` struct to `google.type.Money`
+  #[arg(long, default_value = "false")]
+  money_as_google_type: bool,
+  /// Map `geo::Point<f64>`/`geo::Coord<f64>` to a generated `Point { double x; double y; }` message
+  #[arg(long, default_value = "false")]
+  geo_as_message: bool,
+  /// Map `Range<T>`/`RangeInclusive<T>` to generated messages (`RangeUint32`, `RangeInclusiveInt64`) with `start` and `end` fields
+  #[arg(long, default_value = "false")]
+  range_as_message: bool,
+  /// Map `[T; N]` (except `[u8; N]`) to a generated message (`FloatArray16`, or the newtype wrapping the array) with a `repeated` field and the length in a comment
+  #[arg(long, default_value = "false")]
+  fixed_arrays_as_message: bool,
+  /// Put generated helper types (`OptionalBool`, `Range*`, `Point`) into this file next to the output and import it
+  #[arg(long)]
+  shared_wrappers_file: Option<String>,
+  /// Nest messages and enums used by exactly one message inside it and reference them as `Parent.Child`
+  #[arg(long, default_value = "false")]
+  nest_single_use: bool,
+  /// Carry `// TODO`/`// FIXME` notes on fields over as proto comments
+  #[arg(long, default_value = "false")]
+  preserve_todos: bool,
+  /// Name prefix for tuple struct fields
+  #[arg(long, default_value = "anonymous_value_")]
+  tuple_field_prefix: String,
+  /// Suffix for tuple struct field names: proto field number (`field`) or Rust tuple index (`index`)
+  #[arg(long, value_enum, default_value = "field")]
+  tuple_field_style: TupleFieldStyle,
+  /// How to represent `Option<bool>`: `optional bool` or a shared tri-state `OptionalBool` enum
+  #[arg(long, value_enum, default_value = "optional")]
+  optional_bool_as: OptionalBoolRepr,
+  /// Add a `// content-hash: <sha256>` comment computed from the type model for change detection
+  #[arg(long, default_value = "false")]
+  emit_content_hash: bool,
+  /// Append a comment block listing annotated types that were skipped and why
+  #[arg(long, default_value = "false")]
+  annotate_skipped: bool,
+  /// Prepend `<ENUM>_UNSPECIFIED = 0` to enums not starting with an unspecified zero value (shifts values if 0 is taken)
+  #[arg(long, default_value = "false")]
+  ensure_unspecified: bool,
+  /// Carry `///` doc comments between the marker and `fn` over as rpc method comments
+  #[arg(long, default_value = "false")]
+  emit_rpc_docs: bool,
+  /// Add `// nullable` or `// non-null` comments to fields depending on whether the Rust type is `Option<...>`
+  #[arg(long, default_value = "false")]
+  annotate_nullability: bool,
+  /// Convert field names to `snake_case` (`HTTPServer` -> `http_server`) as the Protobuf style guide recommends
+  #[arg(long, default_value = "false")]
+  normalize_names: bool,
+  /// Treat this cfg as active when expanding `#[cfg_attr(...)]`, e.g. `feature="serde"` or `unix` (repeatable)
+  #[arg(long)]
+  cfg: Vec<String>,
+  /// Skip annotated types marked `#[doc(hidden)]`
+  #[arg(long, default_value = "false")]
+  skip_doc_hidden: bool,
+  /// Where to put opening braces of messages, enums, oneofs and services
+  #[arg(long, value_enum, default_value = "same-line")]
+  brace_style: BraceStyle,
+  /// Print a frequency list of Rust types that couldn't be translated to stderr instead of writing output
+  #[arg(long, default_value = "false")]
+  dump_unknown_types: bool,
+  /// Print time spent in each conversion phase to stderr
+  #[arg(long, default_value = "false")]
+  timings: bool,
   /// Output file
   #[arg(short, long, default_value = "generated.proto")]
   output_file: String,
+  /// Directory for the output file and the files of modules and shared wrappers; only the output file's name is used then
+  #[arg(long)]
+  output_dir: Option<String>,
+  /// Translate single types from newline-delimited JSON requests on stdin until EOF, e.g. `{"type": "Vec<Foo>", "known": ["Foo"]}`
+  #[arg(long, default_value = "false")]
+  server: bool,
+  /// Write the list of files imported by the generated proto to this path (JSON array for `.json`, one per line otherwise)
+  #[arg(long)]
+  emit_import_manifest: Option<String>,
+  /// Compare the generated output with an existing file instead of writing it; print a unified diff and exit with code 1 if they differ
+  #[arg(long)]
+  diff: Option<String>,
+  /// Print the generated output to stdout instead of writing any files
+  #[arg(long, default_value = "false")]
+  dry_run: bool,
+  /// Write a report of every message field with its Rust type, proto type and number to this path (JSON for `.json`, CSV otherwise)
+  #[arg(long)]
+  emit_tag_report: Option<String>,
+  /// Write a binary `FileDescriptorSet` of the generated files to this path
+  #[arg(long)]
+  descriptor_set_out: Option<String>,
+  /// Include `SourceCodeInfo` in `--descriptor-set-out`: the location of every type, field, enum value and rpc
+  /// in the generated files, with doc comments as leading comments
+  #[arg(long, default_value = "false", requires = "descriptor_set_out")]
+  include_source_info: bool,
+  /// Write a JSON object mapping full Protobuf names of types, fields and enum values to their Rust `file:line` to this path
+  #[arg(long)]
+  source_map_out: Option<String>,
+  /// Check that the generated files compile by running `protoc` on them (skipped with a warning if `protoc` isn't on PATH)
+  #[arg(long, default_value = "false")]
+  validate: bool,
+  /// Output format
+  #[arg(long, value_enum, default_value = "proto")]
+  format: OutputFormat,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum OutputFormat {
+  /// Plain `.proto` file
+  Proto,
+  /// JSON object with the `.proto` text, translated messages, enums, imports and warnings
+  JsonBundle,
 }
 
 fn main() {
@@ -40,17 +200,163 @@ fn main() {
   use std::io::Write;
   
   std::panic::set_hook(Box::new(|e| {
-    println!("");
-    println!("An error occured: {}", e.to_string());
+    println!();
+    println!("An error occured: {}", e);
   }));
   
   let args = Args::parse();
-  let mut parser = Parser::new(&args.crate_root, args.panic_to_unsupported, args.verbose).unwrap();
+  let options = Options {
+    syntax: args.syntax,
+    panic_to_unsupported: args.panic_to_unsupported,
+    ignore_rpc: args.ignore_rpc,
+    verbose: args.verbose,
+    quiet: args.server,
+    strict: args.strict,
+    emit_examples: args.emit_examples,
+    dump_unknown_types: args.dump_unknown_types,
+    signed_as_zigzag: args.signed_as_zigzag,
+    serde_with_types: match &args.serde_with_map {
+      Some(path) => load_mapping_file(path).unwrap(),
+      None => Default::default(),
+    },
+    prepend: args.prepend.clone(),
+    strip_module_paths: args.strip_module_paths,
+    bignum_as: args.bignum_as,
+    money_as_google_type: args.money_as_google_type,
+    geo_as_message: args.geo_as_message,
+    range_as_message: args.range_as_message,
+    fixed_arrays_as_message: args.fixed_arrays_as_message,
+    shared_wrappers_file: args.shared_wrappers_file.clone(),
+    nest_single_use: args.nest_single_use,
+    package: args.package.clone(),
+    service_name: args.service_name.clone(),
+    half_as: args.half_as,
+    usize_as: args.usize_as,
+    brace_style: args.brace_style,
+    skip_doc_hidden: args.skip_doc_hidden,
+    annotate_nullability: args.annotate_nullability,
+    normalize_names: args.normalize_names,
+    cfg: args.cfg.clone(),
+    preserve_todos: args.preserve_todos,
+    tuple_field_prefix: args.tuple_field_prefix.clone(),
+    tuple_field_style: args.tuple_field_style,
+    optional_bool_as: args.optional_bool_as,
+    emit_content_hash: args.emit_content_hash,
+    annotate_skipped: args.annotate_skipped,
+    ensure_unspecified: args.ensure_unspecified,
+    emit_rpc_docs: args.emit_rpc_docs,
+    crate_filter: args.crate_filter.clone(),
+    exclude: args.exclude.clone(),
+    respect_gitignore: args.respect_gitignore,
+    license_header: match &args.license_header {
+      Some(path) => std::fs::read_to_string(path)
+        .map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot read license header file"))
+        .unwrap()
+        .lines()
+        .map(|l| l.to_owned())
+        .collect(),
+      None => vec![],
+    },
+    auto_package_from_modules: args.auto_package_from_modules,
+    split_by_module: args.split_by_module,
+    root_file: std::path::Path::new(&args.output_file).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+    template: args.template.as_ref().map(|path| {
+      std::fs::read_to_string(path)
+        .map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot read template file"))
+        .unwrap()
+    }),
+  };
+  if args.server {
+    let types_parser = TypesParser::new(&options).unwrap();
+    serve(&types_parser, std::io::stdin().lock(), std::io::stdout().lock()).unwrap();
+    return
+  }
+  
+  let output_path = match &args.output_dir {
+    Some(dir) => std::path::Path::new(dir).join(std::path::Path::new(&args.output_file).file_name().unwrap_or_default()),
+    None => std::path::PathBuf::from(&args.output_file),
+  };
+  
+  let mut parser = Parser::new(args.crate_root.as_deref().unwrap(), options).unwrap();
   match parser.parse() {
     Err(err) => panic!("{}", err),
+    Ok(()) if args.dump_unknown_types => eprint!("{}", parser.unknown_types_report()),
     Ok(()) => {
-      let mut file = File::create(args.output_file).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot truncate or create file")).unwrap();
-      file.write(parser.generate().as_bytes()).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write proto contents to file")).unwrap();
+      let generation_start = std::time::Instant::now();
+      let contents = match args.format {
+        OutputFormat::Proto => parser.generate(),
+        OutputFormat::JsonBundle => parser.generate_json_bundle(),
+      };
+      parser.timings.generation = generation_start.elapsed();
+      
+      if args.validate {
+        let root_file = std::path::Path::new(&args.output_file).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let mut files = parser.generate_module_files();
+        if let Some(name) = &args.shared_wrappers_file && let Some(shared) = parser.generate_shared_wrappers() {
+          files.insert(name.clone(), shared);
+        }
+        files.insert(root_file.clone(), parser.generate());
+        match validate_with_protoc("protoc", &root_file, &files) {
+          Ok(true) => {},
+          Ok(false) => println!("Warning: `protoc` isn't found on PATH, the generated files aren't validated"),
+          Err(err) => panic!("{}", err),
+        }
+      }
+      
+      if let Some(existing) = &args.diff {
+        if args.timings { eprint!("{}", parser.timings); }
+        if let Some(diff) = diff_against(existing, &contents).unwrap() {
+          print!("{}", diff);
+          std::process::exit(1);
+        }
+        return
+      }
+      
+      if args.dry_run {
+        if args.timings { eprint!("{}", parser.timings); }
+        print!("{}", contents);
+        return
+      }
+      
+      if let Some(dir) = &args.output_dir {
+        std::fs::create_dir_all(dir).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot create output directory")).unwrap();
+      }
+      let mut file = File::create(&output_path).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot truncate or create file")).unwrap();
+      file.write_all(contents.as_bytes()).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write proto contents to file")).unwrap();
+      
+      for (name, module) in parser.generate_module_files() {
+        let path = output_path.with_file_name(name);
+        if let Some(dir) = path.parent() {
+          std::fs::create_dir_all(dir).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot create module directory")).unwrap();
+        }
+        std::fs::write(path, module).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write module file")).unwrap();
+      }
+      
+      if let Some(name) = &args.shared_wrappers_file && let Some(shared) = parser.generate_shared_wrappers() {
+        let path = output_path.with_file_name(name);
+        std::fs::write(path, shared).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write shared wrappers file")).unwrap();
+      }
+      
+      if let Some(path) = &args.emit_import_manifest {
+        let manifest = parser.import_manifest(path.ends_with(".json"));
+        std::fs::write(path, manifest).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write import manifest")).unwrap();
+      }
+      
+      if let Some(path) = &args.emit_tag_report {
+        let report = parser.tag_report(path.ends_with(".json"));
+        std::fs::write(path, report).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write tag report")).unwrap();
+      }
+      
+      if let Some(path) = &args.descriptor_set_out {
+        let descriptor_set = parser.descriptor_set(args.include_source_info);
+        std::fs::write(path, descriptor_set).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write descriptor set")).unwrap();
+      }
+      
+      if let Some(path) = &args.source_map_out {
+        std::fs::write(path, parser.source_map()).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write source map")).unwrap();
+      }
+      
+      if args.timings { eprint!("{}", parser.timings); }
     },
   }
 }
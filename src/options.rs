@@ -0,0 +1,217 @@
+//! Настройки конвертации, общие для парсера структур и транслятора типов.
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+
+/// Представление чисел произвольной точности (`BigInt`, `BigUint`, `BigDecimal`).
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum BignumRepr {
+  /// Десятичная запись числа, как её выдаёт `Display`
+  #[default]
+  String,
+  /// Big-endian байты (`to_signed_bytes_be` для `BigInt`, `to_bytes_be` для `BigUint`); `BigDecimal` остаётся строкой
+  Bytes,
+}
+
+/// Представление чисел половинной точности (`f16`, `half::f16`, `half::bf16`).
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum HalfRepr {
+  /// `float`: значение расширяется без потерь, но обратно может не поместиться
+  #[default]
+  Float,
+  /// Два байта в порядке little-endian (`to_le_bytes`), без потерь в обе стороны
+  Bytes,
+}
+
+/// Способ нумерации безымянных полей кортежных структур.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum TupleFieldStyle {
+  /// Номер поля в Protobuf, начиная с 1: `anonymous_value_1`
+  #[default]
+  Field,
+  /// Индекс поля в Rust, начиная с 0: `_0`
+  Index,
+}
+
+/// Представление `Option<bool>`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum OptionalBoolRepr {
+  /// `optional bool`
+  #[default]
+  Optional,
+  /// Общее перечисление `OptionalBool` с тремя состояниями
+  Enum,
+}
+
+/// Расположение открывающих фигурных скобок сообщений, перечислений и сервисов.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum BraceStyle {
+  /// `message Foo {`
+  #[default]
+  SameLine,
+  /// `message Foo` и `{` на следующей строке
+  NextLine,
+}
+
+/// Разрядность, которой соответствуют `usize`/`isize`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum UsizeWidth {
+  /// `uint32`/`int32`, для 32-битных целевых платформ
+  #[value(name = "32")]
+  W32,
+  /// `uint64`/`int64`
+  #[default]
+  #[value(name = "64")]
+  W64,
+}
+
+/// Версия синтаксиса выходного файла.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Syntax {
+  #[default]
+  Proto3,
+  /// Поля вне `oneof` получают метки `required`/`optional`
+  Proto2,
+}
+
+#[derive(Debug, Clone)]
+pub struct Options {
+  /// Версия синтаксиса выходного файла
+  pub syntax: Syntax,
+  /// Прерывать работу, если помеченный тип не может быть переведён в Protobuf 3
+  pub panic_to_unsupported: bool,
+  /// Не собирать помеченные функции и не генерировать сервис
+  pub ignore_rpc: bool,
+  /// Подробный вывод
+  pub verbose: bool,
+  /// Не печатать предупреждения (они всё равно собираются)
+  pub quiet: bool,
+  /// Предупреждать о потенциально небезопасных преобразованиях типов
+  pub strict: bool,
+  /// Выводить значения из `#[proto(example = "...")]` комментариями к полям
+  pub emit_examples: bool,
+  /// Пропускать помеченные типы с атрибутом `#[doc(hidden)]`
+  pub skip_doc_hidden: bool,
+  /// Помечать поля комментариями `// nullable`/`// non-null` по тому, был ли тип Rust `Option<...>`
+  pub annotate_nullability: bool,
+  /// Приводить имена полей к `snake_case`
+  pub normalize_names: bool,
+  /// Активные условия компиляции (`feature="serde"`, `unix`) для раскрытия `#[cfg_attr(...)]`
+  pub cfg: Vec<String>,
+  /// Разбирать все поля структур, даже если часть из них не удалось перевести, чтобы собрать все неизвестные типы
+  pub dump_unknown_types: bool,
+  /// Переводить знаковые целые в `sint32`/`sint64` (zigzag) вместо `int32`/`int64`
+  pub signed_as_zigzag: bool,
+  /// Типы Protobuf для полей с `#[serde(with = "...")]` по имени модуля сериализатора
+  pub serde_with_types: BTreeMap<String, String>,
+  /// Строки комментариев, вставляемые в начало файла сразу после объявления синтаксиса
+  pub prepend: Vec<String>,
+  /// Отбрасывать пути модулей (`crate::`, `super::`, `a::b::`) у ссылок на помеченные типы
+  pub strip_module_paths: bool,
+  /// Как переводить числа произвольной точности
+  pub bignum_as: BignumRepr,
+  /// Как переводить числа половинной точности
+  pub half_as: HalfRepr,
+  /// Разрядность `usize`/`isize`
+  pub usize_as: UsizeWidth,
+  /// Переносить заметки `// TODO`/`// FIXME` у полей в комментарии Protobuf
+  pub preserve_todos: bool,
+  /// Префикс имён безымянных полей кортежных структур
+  pub tuple_field_prefix: String,
+  /// Нумерация безымянных полей кортежных структур
+  pub tuple_field_style: TupleFieldStyle,
+  /// Как переводить `Option<bool>`
+  pub optional_bool_as: OptionalBoolRepr,
+  /// Добавлять в файл комментарий с хешем модели типов
+  pub emit_content_hash: bool,
+  /// Добавлять в конец файла комментарий со списком пропущенных типов и причин
+  pub annotate_skipped: bool,
+  /// Добавлять в перечисления вариант `<ENUM>_UNSPECIFIED = 0`, если его нет
+  pub ensure_unspecified: bool,
+  /// Переносить документирующие комментарии функций в комментарии к методам сервиса
+  pub emit_rpc_docs: bool,
+  /// Шаблоны путей членов рабочего пространства (`services/*`), которые нужно обходить. Пустой список - обходить всё
+  pub crate_filter: Vec<String>,
+  /// Шаблоны путей относительно корня крейта, которые не нужно обходить (`vendor`, `tests/fixtures`, `**/generated`)
+  pub exclude: Vec<String>,
+  /// Не обходить файлы и каталоги, перечисленные в `.gitignore`
+  pub respect_gitignore: bool,
+  /// Строки лицензионного заголовка, выводимые комментариями перед объявлением синтаксиса
+  pub license_header: Vec<String>,
+  /// Заменять помеченную структуру `Money { amount: Decimal, currency: String }` на `google.type.Money`
+  pub money_as_google_type: bool,
+  /// Переводить `geo::Point<f64>`/`geo::Coord<f64>` во вспомогательное сообщение `Point`
+  pub geo_as_message: bool,
+  /// Переводить `Range<T>`/`RangeInclusive<T>` во вспомогательные сообщения с полями `start` и `end`
+  pub range_as_message: bool,
+  /// Файл, в который выносятся вспомогательные типы (`OptionalBool`, `Range*`, `Point`); основной файл его импортирует
+  pub shared_wrappers_file: Option<String>,
+  /// Переводить массивы фиксированной длины `[T; N]` во вспомогательные сообщения с полем `values` и длиной в комментарии
+  pub fixed_arrays_as_message: bool,
+  /// Вкладывать типы, на которые ссылается только одно сообщение, внутрь этого сообщения
+  pub nest_single_use: bool,
+  /// Расположение открывающих фигурных скобок
+  pub brace_style: BraceStyle,
+  /// Шаблон файла с подстановками `{{syntax}}`, `{{package}}`, `{{imports}}`, `{{body}}`; заменяет встроенную шапку целиком
+  pub template: Option<String>,
+  /// Выводить типы в пакеты по путям их модулей (`src/billing/invoices.rs` -> `billing.invoices`), по файлу на пакет
+  pub auto_package_from_modules: bool,
+  /// Выводить типы каждого модуля в отдельный файл (`src/billing/invoices.rs` -> `billing/invoices.proto`) в общем пакете
+  pub split_by_module: bool,
+  /// Имя основного выходного файла, на который ссылаются импорты из файлов других пакетов
+  pub root_file: String,
+  /// Пакет Protobuf; по умолчанию выводится из имени каталога крейта
+  pub package: Option<String>,
+  /// Имя общего сервиса; по умолчанию выводится из имени каталога крейта
+  pub service_name: Option<String>,
+}
+
+impl Default for Options {
+  fn default() -> Self {
+    Self {
+      syntax: Syntax::default(),
+      panic_to_unsupported: false,
+      ignore_rpc: false,
+      verbose: false,
+      quiet: false,
+      strict: false,
+      emit_examples: false,
+      skip_doc_hidden: false,
+      annotate_nullability: false,
+      normalize_names: false,
+      cfg: vec![],
+      dump_unknown_types: false,
+      signed_as_zigzag: false,
+      serde_with_types: BTreeMap::default(),
+      prepend: vec![],
+      strip_module_paths: true,
+      bignum_as: BignumRepr::default(),
+      half_as: HalfRepr::default(),
+      usize_as: UsizeWidth::default(),
+      preserve_todos: false,
+      tuple_field_prefix: "anonymous_value_".into(),
+      tuple_field_style: TupleFieldStyle::default(),
+      optional_bool_as: OptionalBoolRepr::default(),
+      emit_content_hash: false,
+      annotate_skipped: false,
+      ensure_unspecified: false,
+      emit_rpc_docs: false,
+      crate_filter: vec![],
+      exclude: vec![],
+      respect_gitignore: true,
+      license_header: vec![],
+      money_as_google_type: false,
+      geo_as_message: false,
+      range_as_message: false,
+      shared_wrappers_file: None,
+      fixed_arrays_as_message: false,
+      nest_single_use: false,
+      brace_style: BraceStyle::default(),
+      template: None,
+      auto_package_from_modules: false,
+      split_by_module: false,
+      root_file: "generated.proto".into(),
+      package: None,
+      service_name: None,
+    }
+  }
+}
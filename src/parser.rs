@@ -1,241 +1,4090 @@
 use regex::Regex;
-use std::collections::{BTreeMap, BTreeSet};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::btree_map::Entry;
+use ignore::WalkBuilder;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::fs::File;
 use std::io::Read;
-use walkdir::WalkDir;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use crate::attrs::Attribute;
+use crate::descriptor::{self, Message};
+use crate::items::{self, doc_comment, AnnotatedFn, AnnotatedItem, FileItems, ItemKind, SourceField, SourceVariant, VariantShape};
+use crate::options::{BraceStyle, Options, Syntax, TupleFieldStyle, UsizeWidth};
 use crate::types::TypesParser;
-use crate::utils::{MResult, R2Proto3Error};
+use crate::utils::{self, MResult, R2Proto3Error, Timings};
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 // NOTE: ToProtobuf
 pub(crate) struct ProtobufField {
   pub name: String,
+  /// Исходный тип Rust; в модель (и хеш содержимого) не входит
+  #[serde(skip)]
+  pub rust_type: String,
   pub proto3_type: String,
   pub field_num: i32,
+  pub example: Option<String>,
+  pub options: Vec<(String, String)>,
+  pub todos: Vec<String>,
+  /// Имя блока `oneof`, в который входит поле
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub oneof: Option<String>,
+  /// Документирующий комментарий поля
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub doc: Option<String>,
+  /// Поле помечено `#[deprecated]` или комментарием `// deprecated`
+  #[serde(skip_serializing_if = "std::ops::Not::not")]
+  pub deprecated: bool,
+  /// Строка объявления поля в файле Rust; у полей вспомогательных типов её нет. В модель не входит
+  #[serde(skip)]
+  pub source_line: Option<usize>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 // NOTE: ToProtobuf
 pub(crate) struct ProtobufEnumVariant {
   pub name: String,
   pub value: i32,
+  /// Документирующий комментарий варианта
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub doc: Option<String>,
+  /// Вариант помечен `#[deprecated]` или комментарием `// deprecated`
+  #[serde(skip_serializing_if = "std::ops::Not::not")]
+  pub deprecated: bool,
+  /// Строка объявления варианта в файле Rust. В модель не входит
+  #[serde(skip)]
+  pub source_line: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+// NOTE: ToProtobuf
+pub(crate) struct ProtobufRpc {
+  pub name: String,
+  pub request: String,
+  pub response: String,
+  pub client_streaming: bool,
+  pub server_streaming: bool,
+  pub options: Vec<String>,
+  /// Строки документирующего комментария функции (только с `--emit-rpc-docs`)
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub docs: Vec<String>,
+  /// Сервис из пометки `// NOTE: ToProtobuf(service = "Admin")`; без него метод попадает в общий сервис
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub service: Option<String>,
+}
+
+/// Номера и имена полей, удалённых из сообщения: `// reserved: 3, 5-7, "old_name"`.
+#[derive(Debug, Default, PartialEq)]
+struct ReservedFields {
+  /// Диапазоны номеров включительно
+  numbers: Vec<(i32, i32)>,
+  names: Vec<String>,
+}
+
+impl ReservedFields {
+  fn parse(spec: &str) -> MResult<Self> {
+    let mut reserved = Self::default();
+    let number = |n: &str| match n.trim().parse::<i32>() {
+      Ok(n) if (1..=536_870_911).contains(&n) => Ok(n),
+      _ => Err(R2Proto3Error::new(None, format!("`{}` isn't a valid reserved field number", n.trim()))),
+    };
+    for item in spec.split(',').map(|i| i.trim()).filter(|i| !i.is_empty()) {
+      if let Some(name) = item.strip_prefix('"').and_then(|i| i.strip_suffix('"')) {
+        if !TypesParser::is_identifier(name) {
+          return Err(R2Proto3Error::new(None, format!("`{}` isn't a valid reserved field name", name)));
+        }
+        reserved.names.push(name.to_owned());
+      } else if let Some((from, to)) = item.split_once('-').or_else(|| item.split_once(" to ")) {
+        let (from, to) = (number(from)?, number(to)?);
+        if from > to {
+          return Err(R2Proto3Error::new(None, format!("reserved range `{}` is empty", item)));
+        }
+        reserved.numbers.push((from, to));
+      } else {
+        let number = number(item)?;
+        reserved.numbers.push((number, number));
+      }
+    }
+    Ok(reserved)
+  }
+  
+  fn contains_number(&self, number: i32) -> bool {
+    self.numbers.iter().any(|(from, to)| (*from..=*to).contains(&number))
+  }
 }
 
+#[derive(Serialize)]
 // NOTE: ToProtobuf
 pub(crate) enum ProtobufEntityType {
   Message(Vec<ProtobufField>),
   Enum(Vec<ProtobufEnumVariant>),
-  // Rpc,
+}
+
+/// Итог конвертации крейта для использования в виде библиотеки.
+#[derive(Debug, PartialEq, Serialize)]
+// NOTE: ToProtobuf
+pub struct GenerationResult {
+  pub proto: String,
+  pub imports: Vec<String>,
+  pub messages: usize,
+  pub enums: usize,
+  pub services: usize,
+  /// Помеченные типы, не попавшие в файл, и причины
+  pub skipped: Vec<(String, String)>,
+}
+
+/// Разбирает крейт и генерирует файл Protobuf вместе со сведениями о результате.
+pub fn convert(crate_root: &str, options: Options) -> MResult<GenerationResult> {
+  let mut parser = Parser::new(crate_root, options)?;
+  parser.parse()?;
+  Ok(parser.generation_result())
 }
 
 // NOTE: ToProtobuf
-pub(crate) struct Parser<'a> {
-  struct_re: Regex,
-  enum_re: Regex,
+pub struct Parser<'a> {
+  patterns: Patterns,
   pub crate_name: &'a str,
-  panic_to_unsupported: bool,
-  verbose: bool,
+  options: Options,
   types_parser: TypesParser,
-  pub types: BTreeMap<String, ProtobufEntityType>,
+  pub(crate) types: BTreeMap<String, ProtobufEntityType>,
+  /// Методы по сервису и имени метода; с типами они не пересекаются, а в разных сервисах имена могут повторяться
+  pub(crate) rpcs: BTreeMap<(String, String), ProtobufRpc>,
+  pub(crate) imports: BTreeSet<String>,
+  /// Опции уровня сообщения или перечисления (`deprecated = true`) по имени типа
+  pub(crate) type_options: BTreeMap<String, Vec<String>>,
+  /// Документирующие комментарии сообщений и перечислений по имени типа
+  pub(crate) type_docs: BTreeMap<String, String>,
+  /// Помеченные типы, не попавшие в файл, и причины
+  pub(crate) skipped: Vec<(String, String)>,
+  /// Правила `#[serde(rename_all = "...")]` по имени структуры
+  pub(crate) rename_all: BTreeMap<String, String>,
+  /// Списки `// reserved: 3, 5-7, "old_name"` по имени структуры
+  pub(crate) reserved: BTreeMap<String, String>,
+  /// Вспомогательные типы, выносимые в общий файл при `--shared-wrappers-file`
+  pub(crate) shared_types: BTreeMap<String, ProtobufEntityType>,
+  /// Откуда взят каждый помеченный тип (`struct in src/lib.rs`), для сообщений о совпадении имён
+  pub(crate) type_sources: BTreeMap<String, Vec<String>>,
+  /// Путь модуля Rust каждого типа относительно корня крейта (`billing.invoices`); пустой для корневого модуля
+  pub(crate) type_modules: BTreeMap<String, String>,
+  /// Файл относительно корня крейта и строка объявления каждого помеченного типа
+  pub(crate) type_locations: BTreeMap<String, (String, usize)>,
+  /// Все просмотренные файлы `.rs`, включая подключённые через `#[path = "..."]`
+  pub scanned_files: Vec<PathBuf>,
+  pub timings: Timings,
 }
 
 impl<'a> Parser<'a> {
-  pub(crate) fn new(
+  pub fn new(
     crate_name: &'a str,
-    panic_to_unsupported: bool,
-    verbose: bool,
+    options: Options,
   ) -> MResult<Self> {
+    if let Some(package) = &options.package && !Self::is_valid_package(package) {
+      return Err(R2Proto3Error::new(None, format!("`{}` isn't a valid package name: expected lowercase identifiers separated by dots, e.g. `acme.users.v1`", package)));
+    }
+    if let Some(service) = &options.service_name && !TypesParser::is_identifier(service) {
+      return Err(R2Proto3Error::new(None, format!("`{}` isn't a valid service name", service)));
+    }
     Ok(
       Self {
-        struct_re: Regex::new(r##"(// NOTE: ToProtobuf[a-z\n() ]*struct ([a-zA-Z0-9_]*)[ ]?\{([\w\n\s():<>,/'"\-_=#\[\]]*)})|(// NOTE: ToProtobuf[a-z\n() ]*struct ([a-zA-Z0-9_]*)[ ]?*\(([a-zA-Z0-9,<>:_ \n]*)\);)"##)
-          .map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось собрать регулярное выражение для структур данных"))?,
-        enum_re: Regex::new(r##"// NOTE: ToProtobuf[a-z\n() ]*enum ([a-zA-Z0-9_]*)[ ]?\{([\w\n\s():<>'",/\-_=#\[\]]*)}"##)
-          .map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось собрать регулярное выражение для перечислений"))?,
+        patterns: Patterns {
+          path_re: Regex::new(r##"#\[path[ ]*=[ ]*"([^"]+)"\][\s]*(?:pub(?:\([a-z ]+\))?[ ]+)?mod [a-zA-Z0-9_]+[ ]*;"##)
+            .map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось собрать регулярное выражение для путей модулей"))?,
+        },
         crate_name,
-        panic_to_unsupported,
-        verbose,
-        types_parser: TypesParser::new()?,
+        types_parser: TypesParser::new(&options)?,
+        options,
         types: BTreeMap::default(),
+        rpcs: BTreeMap::default(),
+        imports: BTreeSet::default(),
+        type_options: BTreeMap::default(),
+        type_docs: BTreeMap::default(),
+        skipped: vec![],
+        rename_all: BTreeMap::default(),
+        reserved: BTreeMap::default(),
+        shared_types: BTreeMap::default(),
+        type_sources: BTreeMap::default(),
+        type_modules: BTreeMap::default(),
+        type_locations: BTreeMap::default(),
+        scanned_files: vec![],
+        timings: Timings::default(),
       }
     )
   }
 
-  pub(crate) fn parse(&mut self) -> MResult<()> {
+  pub fn parse(&mut self) -> MResult<()> {
     let mut messages = vec![];
     let mut enums = vec![];
+    let mut rpcs = vec![];
     let mut known_types = BTreeSet::new();
-
-    for entry in WalkDir::new(&self.crate_name).follow_links(true) {
-      if let Ok(entry) = entry {
-        if entry.file_type().is_file() && entry.file_name().as_encoded_bytes().ends_with(b"rs") {
-          let mut f = File::open(entry.path()).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось открыть файл"))?;
-          let mut contents = String::new();
-          f.read_to_string(&mut contents).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось считать содержимое файла"))?;
-          
-          // Парсим структуры
-          for (_, [_, struct_name, all_fields]) in self.struct_re.captures_iter(&contents).map(|c| c.extract()) {
-            let fields = all_fields
-              .split("\n")
-              .map(|p| p.trim())
-              .filter(|p| !p.is_empty() && !p.starts_with('#') && !p.starts_with('/'))
-              .map(|s| s.to_owned())
-              .collect::<Vec<String>>();
-            messages.push((struct_name.to_string(), fields));
-            if !known_types.insert(struct_name.to_string()) {
-              println!(r#"Dublicate type: "{}""#, struct_name);
-            };
+    
+    let phase_start = Instant::now();
+    let root = Path::new(self.crate_name);
+    let (crate_filter, exclude, walk_root) = (self.options.crate_filter.clone(), self.options.exclude.clone(), root.to_path_buf());
+    let mut files = WalkBuilder::new(root)
+      .follow_links(true)
+      .hidden(false)
+      .ignore(false)
+      .git_global(false)
+      .git_ignore(self.options.respect_gitignore)
+      .git_exclude(self.options.respect_gitignore)
+      .require_git(false)
+      .sort_by_file_path(|a, b| a.cmp(b))
+      // Члены рабочего пространства, не подходящие под `--crate-filter`, каталоги сборки Cargo и пути из `--exclude` не обходятся вовсе
+      .filter_entry(move |e| {
+        let relative_path = e.path().strip_prefix(&walk_root).unwrap_or(e.path());
+        let is_dir = e.file_type().is_some_and(|t| t.is_dir());
+        e.depth() == 0 || (
+          utils::crate_filter_allows(&crate_filter, relative_path, is_dir)
+            && !(is_dir && utils::is_cargo_target_dir(e.path()))
+            && !utils::path_excluded(&exclude, relative_path)
+        )
+      })
+      .build()
+      .flatten()
+      .filter(|e| e.file_type().is_some_and(|t| t.is_file()) && e.file_name().as_encoded_bytes().ends_with(b"rs"))
+      .map(|e| e.into_path())
+      .collect::<VecDeque<_>>();
+    let mut scanned = BTreeSet::new();
+    
+    self.timings.read += phase_start.elapsed();
+    
+    // Файлы читаются и просматриваются параллельно, волнами: модули `#[path = "..."]`, найденные в одной волне,
+    // просматриваются в следующей. Результаты сливаются в порядке файлов, поэтому вывод не зависит от числа потоков
+    while !files.is_empty() {
+      let wave = files.drain(..).filter(|path| scanned.insert(path.canonicalize().unwrap_or(path.clone()))).collect::<Vec<_>>();
+      let patterns = &self.patterns;
+      let scans = wave.par_iter().map(|path| patterns.scan(path)).collect::<Vec<_>>();
+      
+      for scan in scans {
+        let FileScan { path, modules, items, consts, aliases, fns, warnings, read, extraction } = scan?;
+        self.timings.read += read;
+        self.timings.extraction += extraction;
+        self.scanned_files.push(path.clone());
+        for warning in warnings {
+          self.types_parser.warn(warning);
+        }
+        files.extend(modules);
+        let mut items = self.instantiate_generics(items);
+        
+        // Внутренние типы `#[doc(hidden)]` не входят в публичный контракт
+        if self.options.skip_doc_hidden {
+          items.retain(|item| {
+            if !Self::is_doc_hidden(&Attribute::parse_lines(item.gap.lines(), &self.options.cfg)) { return true }
+            if self.options.verbose { println!("Skipping `#[doc(hidden)]` type `{}`", item.name); }
+            self.skipped.push((item.name.to_owned(), "the type is `#[doc(hidden)]`".into()));
+            false
+          });
+        }
+        
+        // Парсим структуры
+        let relative_path = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        for AnnotatedItem { name: struct_name, gap, fields, line, .. } in items.iter().filter(|i| i.kind == ItemKind::Struct) {
+          let attrs = Attribute::parse_lines(gap.lines(), &self.options.cfg);
+          let type_options = Self::type_options_from_attributes(&attrs);
+          if !type_options.is_empty() { self.type_options.insert(struct_name.to_string(), type_options); }
+          if let Some(doc) = doc_comment(gap.lines()) { self.type_docs.insert(struct_name.to_string(), doc); }
+          if let Some(rule) = Attribute::find(&attrs, "serde", "rename_all").flatten() { self.rename_all.insert(struct_name.to_string(), rule.to_owned()); }
+          if let Some(reserved) = gap.lines().find_map(|l| l.trim().strip_prefix("// reserved:")) { self.reserved.insert(struct_name.to_string(), reserved.trim().to_owned()); }
+          messages.push((struct_name.to_string(), fields.clone()));
+          known_types.insert(struct_name.to_string());
+          self.type_sources.entry(struct_name.to_string()).or_default().push(format!("struct in `{}`", relative_path.display()));
+          self.type_modules.insert(struct_name.to_string(), Self::module_path(&relative_path));
+          self.type_locations.insert(struct_name.to_string(), (relative_path.display().to_string(), *line));
+        }
+        
+        // Псевдонимы типов собираются из всех файлов, помечать их не нужно
+        for (alias, target) in aliases {
+          match self.types_parser.aliases.borrow_mut().entry(alias.to_owned()) {
+            Entry::Vacant(entry) => { entry.insert(target); },
+            Entry::Occupied(entry) if *entry.get() != target => {
+              self.types_parser.warn(format!("type alias `{}` is declared as both `{}` and `{}`, the first one is used", alias, entry.get(), target));
+            },
+            Entry::Occupied(_) => {},
           }
-          
-          // Парсим перечисления
-          for (_, [enum_name, all_variants]) in self.enum_re.captures_iter(&contents).map(|c| c.extract()) {
-            let variants = all_variants
-              .split("\n")
-              .map(|p| p.trim())
-              .filter(|p| !p.is_empty() && !p.starts_with('#') && !p.starts_with('/'))
-              .map(|s| s.to_owned())
-              .collect::<Vec<_>>();
-            enums.push((enum_name.to_string(), variants));
-            if !known_types.insert(enum_name.to_string()) {
-              println!(r#"Dublicate type: "{}""#, enum_name);
+        }
+        
+        // Парсим перечисления
+        for AnnotatedItem { name: enum_name, gap, variants, line, .. } in items.iter().filter(|i| i.kind == ItemKind::Enum) {
+          let type_options = Self::type_options_from_attributes(&Attribute::parse_lines(gap.lines(), &self.options.cfg));
+          if !type_options.is_empty() { self.type_options.insert(enum_name.to_string(), type_options); }
+          if let Some(doc) = doc_comment(gap.lines()) { self.type_docs.insert(enum_name.to_string(), doc); }
+          let mut variants = variants.clone();
+          // Дискриминанты-константы подставляются из объявлений `const NAME: iN = value;` в том же файле.
+          // Пути в другие модули (`other::OK`) не разрешаются, даже если в файле есть константа с тем же именем
+          let module = Self::module_path(&relative_path);
+          for discriminant in variants.iter_mut().filter_map(|v| v.discriminant.as_mut()) {
+            let const_name = match discriminant.rsplit_once("::") {
+              None => Some(discriminant.as_str()),
+              Some(("Self" | "self", const_name)) => Some(const_name),
+              Some((prefix, const_name)) if prefix.strip_prefix("crate").is_some_and(|m| m.trim_start_matches("::").replace("::", ".") == module) => Some(const_name),
+              Some(_) => None,
             };
+            if let Some(value) = const_name.and_then(|c| consts.get(c)) {
+              *discriminant = value.to_owned();
+            }
           }
+          enums.push((enum_name.to_string(), variants));
+          known_types.insert(enum_name.to_string());
+          self.type_sources.entry(enum_name.to_string()).or_default().push(format!("enum in `{}`", relative_path.display()));
+          self.type_modules.insert(enum_name.to_string(), Self::module_path(&relative_path));
+          self.type_locations.insert(enum_name.to_string(), (relative_path.display().to_string(), *line));
+        }
+        
+        // Парсим функции
+        for AnnotatedFn { marker_args, gap: preamble, name: fn_name, args, ret } in fns.iter().filter(|_| !self.options.ignore_rpc) {
+          // Тесты (`#[test]`, `#[tokio::test]`, `#[cfg(test)]`) не становятся методами, даже если помечены
+          let is_test = preamble.lines().filter_map(Attribute::parse).any(|a| {
+            a.path == "test" || a.path.ends_with("::test") || (a.path == "cfg" && a.args.iter().any(|(k, v)| k == "test" && v.is_none()))
+          });
+          if is_test {
+            if self.options.verbose { println!("Skipping test function `{}`", fn_name); }
+            continue
+          }
+          let directives = preamble
+            .split("\n")
+            .filter_map(|d| d.trim().strip_prefix("// proto:"))
+            .map(|d| d.trim().to_owned())
+            .collect::<Vec<_>>();
+          let docs = match doc_comment(preamble.lines()) {
+            Some(doc) if self.options.emit_rpc_docs => doc.lines().map(|l| l.to_owned()).collect(),
+            _ => vec![],
+          };
+          // `// NOTE: ToProtobuf(service = "Admin")` относит метод к отдельному сервису
+          let marker_attrs = Attribute::parse(format!("#[marker{}]", marker_args)).into_iter().collect::<Vec<_>>();
+          let service = Attribute::find(&marker_attrs, "marker", "service").flatten().map(|s| s.to_owned());
+          rpcs.push((fn_name.to_string(), args.clone(), ret.clone(), directives, docs, service));
         }
       }
     }
+    let translation_start = Instant::now();
 
     if known_types.is_empty() {
       println!("There are no data types to translate in the crate. Maybe you forgot to put a comment right before the start of the structure?");
       println!("You should write `// NOTE: ToProtobuf` right before struct/enum/function is declared.");
       
       return Ok(())
-    } else if self.verbose {
+    } else if self.options.verbose {
       println!("Messages = {:#?}", messages);
       println!("Enums = {:#?}", enums);
+      println!("Rpcs = {:#?}", rpcs);
       println!("Unique types: {:?}", known_types);
     }
     
+    // `Money { amount: Decimal, currency: String }` заменяется на `google.type.Money`
+    if self.options.money_as_google_type {
+      messages.retain(|(name, fields)| {
+        if !Self::is_money_pattern(name, fields) { return true }
+        self.types_parser.external_types.borrow_mut().insert(name.to_owned(), ("google.type.Money".into(), "google/type/money.proto".into()));
+        false
+      });
+    }
+    
     for message in messages {
-      match self.parse_struct_fields(&message.1, &known_types) {
+      let rename_all = self.rename_all.get(&message.0).map(|r| r.as_str());
+      match self.parse_struct_fields(&message.1, &known_types, rename_all, self.reserved.get(&message.0).map(|r| r.as_str())) {
         Ok(fields) => {
-          if self.verbose { println!("Parsed fields: {:?}", fields); }
+          if self.options.verbose { println!("Parsed fields: {:?}", fields); }
+          for field in &fields {
+            self.imports.extend(self.types_parser.imports(&field.proto3_type));
+          }
           self.types.insert(message.0.to_owned(), ProtobufEntityType::Message(fields));
         },
         Err(e) => {
-          if self.panic_to_unsupported {
-            return Err(R2Proto3Error::new(Some(Box::new(e)), format!("Warning: the struct `{}` won't be attached to `.proto` file", &message.0)));
+          if self.options.panic_to_unsupported {
+            return Err(R2Proto3Error::new(Some(Box::new(e)), format!("Warning: the struct `{}` won't be attached to `.proto` file", message.0)));
           } else {
-            println!("Warning: the struct `{}` won't be attached to `.proto` file due to error: {}", &message.0, e);
+            self.types_parser.warn(format!("the struct `{}` won't be attached to `.proto` file due to error: {}", message.0, e));
+            self.skipped.push((message.0.to_owned(), e.to_string()));
           }
         },
       }
     }
     
     for r#enum in enums {
-      match self.parse_enum_fields(&r#enum.1) {
-        Ok(variants) => {
-          if self.verbose { println!("Parsed variants: {:?}", variants); }
-          self.types.insert(r#enum.0.to_owned(), ProtobufEntityType::Enum(variants));
+      // Перечисления с данными в вариантах переводятся в сообщения с `oneof`
+      let translated = if r#enum.1.iter().any(|v| v.shape != VariantShape::Unit) {
+        if let Some(unset) = Self::unset_variant(&r#enum.1).filter(|u| u.discriminant.is_none()) {
+          let note = format!("An unset `{}` stands for `{}`", TypesParser::to_snake_case(&r#enum.0), unset.name);
+          let doc = self.type_docs.remove(&r#enum.0).map(|doc| format!("{}\n{}", doc, note)).unwrap_or(note);
+          self.type_docs.insert(r#enum.0.to_owned(), doc);
+        }
+        self.parse_data_enum(&r#enum.0, &r#enum.1, &known_types)
+      } else {
+        self.parse_enum_fields(&r#enum.1)
+          .and_then(|variants| self.ensure_unspecified(&r#enum.0, variants))
+          .map(|variants| vec![(r#enum.0.to_owned(), ProtobufEntityType::Enum(variants))])
+      };
+      match translated {
+        Ok(types) => {
+          for (name, r#type) in types {
+            match &r#type {
+              ProtobufEntityType::Enum(variants) => if self.options.verbose { println!("Parsed variants: {:?}", variants); },
+              ProtobufEntityType::Message(fields) => {
+                if self.options.verbose { println!("Parsed fields: {:?}", fields); }
+                for field in fields {
+                  self.imports.extend(self.types_parser.imports(&field.proto3_type));
+                }
+              },
+            }
+            // Сообщения для вариантов лежат в том же модуле, что и перечисление, и объявлены на строке варианта
+            if let Some(module) = self.type_modules.get(&r#enum.0).filter(|_| name != r#enum.0).cloned() {
+              self.type_modules.insert(name.to_owned(), module);
+            }
+            if let Some((file, _)) = self.type_locations.get(&r#enum.0).filter(|_| name != r#enum.0).cloned()
+              && let Some(variant) = r#enum.1.iter().find(|v| v.shape != VariantShape::Unit && name == format!("{}{}", r#enum.0, v.name))
+            {
+              self.type_locations.insert(name.to_owned(), (file, variant.annotations.line));
+            }
+            self.types.insert(name, r#type);
+          }
         },
         Err(e) => {
-          if self.panic_to_unsupported {
-            return Err(R2Proto3Error::new(Some(Box::new(e)), format!("Warning: the enum `{}` won't be attached to `.proto` file", &r#enum.0)));
+          if self.options.panic_to_unsupported {
+            return Err(R2Proto3Error::new(Some(Box::new(e)), format!("Warning: the enum `{}` won't be attached to `.proto` file", r#enum.0)));
           } else {
-            println!("Warning: the enum `{}` won't be attached to `.proto` file due to error: {}", &r#enum.0, e);
+            self.types_parser.warn(format!("the enum `{}` won't be attached to `.proto` file due to error: {}", r#enum.0, e));
+            self.skipped.push((r#enum.0.to_owned(), e.to_string()));
           }
         },
       }
     }
-
+    
+    for rpc in rpcs {
+      let parsed = match &rpc.5 {
+        Some(service) if !TypesParser::is_identifier(service) => Err(R2Proto3Error::new(None, format!("`{}` isn't a valid service name", service))),
+        _ => self.parse_rpc_signature(&rpc.0, &rpc.1, rpc.2.as_deref(), &rpc.3, &known_types),
+      };
+      match parsed {
+        Ok(parsed) => {
+          if self.options.verbose { println!("Parsed rpc: {:?}", parsed); }
+          let service = rpc.5.clone().unwrap_or_else(|| self.service_name());
+          match self.rpcs.entry((service, parsed.name.to_owned())) {
+            Entry::Vacant(entry) => { entry.insert(ProtobufRpc { docs: rpc.4, service: rpc.5, ..parsed }); },
+            Entry::Occupied(entry) => return Err(R2Proto3Error::new(None, format!(
+              "the function `{}` produces rpc method `{}`, which is already defined in the service `{}`", rpc.0, entry.key().1, entry.key().0,
+            ))),
+          }
+        },
+        Err(e) => {
+          if self.options.panic_to_unsupported {
+            return Err(R2Proto3Error::new(Some(Box::new(e)), format!("Warning: the function `{}` won't be attached to `.proto` file", rpc.0)));
+          } else {
+            self.types_parser.warn(format!("the function `{}` won't be attached to `.proto` file due to error: {}", rpc.0, e));
+            self.skipped.push((rpc.0.to_owned(), e.to_string()));
+          }
+        },
+      }
+    }
+    
+    let synthetic_types = self.types_parser.synthetic_types.take();
+    if let Some(shared_file) = &self.options.shared_wrappers_file && !synthetic_types.is_empty() {
+      self.imports.insert(shared_file.to_owned());
+    }
+    for (name, r#type) in synthetic_types {
+      if self.options.shared_wrappers_file.is_some() {
+        if self.types.contains_key(&name) {
+          self.types_parser.warn(format!("type `{}` is already defined, the generated helper type with the same name will clash with it in the shared wrappers file", name));
+        }
+        self.shared_types.insert(name, r#type);
+        continue
+      }
+      match self.types.entry(name) {
+        Entry::Vacant(entry) => { entry.insert(r#type); },
+        Entry::Occupied(entry) => {
+          self.types_parser.warn(format!("type `{}` is already defined, the generated helper type with the same name won't be attached to `.proto` file", entry.key()));
+        },
+      }
+    }
+    self.timings.translation = translation_start.elapsed();
+    self.validate_names()?;
+    if let Some(cycle) = self.import_cycle() {
+      self.types_parser.warn(format!("generated files import each other, which protoc rejects: {}", cycle.join(" -> ")));
+    }
+    
+    Ok(())
+  }
+  
+  /// Дописывает к документации поля ограничения, которые теряются при переводе типа:
+  /// `char` передаётся строкой из одного символа, `usize`/`isize` - целым выбранной разрядности.
+  fn note_type(&self, doc: Option<String>, rust_type: &str) -> Option<String> {
+    let mut notes = doc.into_iter().collect::<Vec<_>>();
+    if TypesParser::holds(rust_type, "char") {
+      notes.push("single character".to_owned());
+    }
+    if TypesParser::holds(rust_type, "usize") || TypesParser::holds(rust_type, "isize") {
+      notes.push(match self.options.usize_as {
+        UsizeWidth::W32 => "platform-width integer, assumes a 32-bit target".to_owned(),
+        UsizeWidth::W64 => "platform-width integer, assumes a 64-bit target".to_owned(),
+      });
+    }
+    (!notes.is_empty()).then(|| notes.join("\n"))
+  }
+  
+  /// Проверяет, что полные имена сообщений, перечислений и сервиса в пакете не совпадают.
+  fn validate_names(&self) -> MResult<()> {
+    let in_output = |name: &str| self.types.contains_key(name) || self.shared_types.contains_key(name);
+    
+    if let Some((name, sources)) = self.type_sources.iter().find(|(name, sources)| sources.len() > 1 && in_output(name)) {
+      return Err(R2Proto3Error::new(None, format!("`{}.{}` is defined more than once: {}", self.type_package(name), name, sources.join(", "))));
+    }
+    
+    let package = self.package_name();
+    if let Some(service) = self.services().into_keys().find(|service| in_output(service)) {
+      let source = self.type_sources.get(&service).and_then(|s| s.first()).map(|s| s.as_str()).unwrap_or("a generated helper type");
+      return Err(R2Proto3Error::new(None, format!("service `{}.{}` generated from the crate's functions clashes with {}", package, service, source)));
+    }
     Ok(())
   }
   
-  fn parse_struct_fields(&self, fields_str: &Vec<String>, known_types: &BTreeSet<String>) -> MResult<Vec<ProtobufField>> {
+  /// `rename_all` - правило `#[serde(rename_all = "...")]` структуры, применяемое к полям без `#[serde(rename = "...")]`,
+  /// `reserved` - список из комментария `// reserved: ...` структуры.
+  fn parse_struct_fields(
+    &self,
+    fields_str: &[SourceField],
+    known_types: &BTreeSet<String>,
+    rename_all: Option<&str>,
+    reserved: Option<&str>,
+  ) -> MResult<Vec<ProtobufField>> {
     let mut fields = vec![];
     let mut value_cntr = 1i32;
+    let mut first_error = None;
+    let reserved = reserved.map(ReservedFields::parse).transpose()?.unwrap_or_default();
+    
+    // Номера, заданные явно комментарием `// field = N`; автоматическая нумерация их пропускает
+    let mut claimed = BTreeMap::new();
+    let mut explicit_numbers = vec![];
+    for field in fields_str {
+      let number = match field.annotations.explicit_number(&self.options.cfg)? {
+        Some(number) if reserved.contains_number(number) => {
+          return Err(R2Proto3Error::new(None, format!("field number {} is reserved: `{}`", number, field.declaration())))
+        },
+        Some(number) if let Some(first) = claimed.get(&number) => {
+          let msg = format!("field number {} is claimed twice: `{}` and `{}`", number, first, field.declaration());
+          if self.options.panic_to_unsupported { return Err(R2Proto3Error::new(None, msg)) }
+          self.types_parser.warn(format!("{}, the later field gets an automatic number", msg));
+          None
+        },
+        Some(number) => {
+          claimed.insert(number, field.declaration());
+          Some(number)
+        },
+        None => None,
+      };
+      explicit_numbers.push(number);
+    }
     
-    for field in fields_str.iter() {
-      let parts = field.split(':').map(|s| s.to_owned()).collect::<Vec<_>>();
+    for (position, field) in fields_str.iter().enumerate() {
+      let attrs = field.annotations.attributes(&self.options.cfg);
+      let example = Attribute::find(&attrs, "proto", "example").flatten().map(|e| e.to_owned());
+      // У полей кортежных структур имён нет
+      let (name, rust_type) = (field.name.clone(), TypesParser::drop_type_unnecessary_stuff(&field.ty));
+      if TypesParser::is_phantom_data(&rust_type) { continue }
       
-      // В этот момент предполагается, что, раз длина поля структуры данных равна единице, то эта структура объявлена в скобках,
-      // и её параметр анонимен.
-      if parts.len() == 1 {
-        let rust_type = TypesParser::drop_type_unnecessary_stuff(&parts[0]);
-        fields.push(ProtobufField {
-          name: format!("anonymous_value_{}", value_cntr),
-          proto3_type: self.types_parser.rust_type_to_protobuf(&rust_type, known_types, false)?.to_owned(),
-          field_num: value_cntr,
-        });
+      // Обёртка над массивом фиксированной длины (`struct Mat4([f32; 16]);`) сама становится сообщением с полем `values`
+      if self.options.fixed_arrays_as_message && fields_str.len() == 1 && name.is_none()
+        && let Some((inner, len)) = self.types_parser.fixed_array(&rust_type)
+        && inner != "u8"
+      {
+        let repeated = self.types_parser.rust_type_to_protobuf(&format!("Vec<{}>", inner), known_types, false)?;
+        return Ok(vec![TypesParser::fixed_array_values(&inner, &len, repeated)]);
+      }
+      
+      let field_num = match explicit_numbers[position] {
+        Some(number) => number,
+        None => {
+          // See [Language Guide (proto 3) - Assigning Field Numbers](https://protobuf.dev/programming-guides/proto3/#assigning).
+          while claimed.contains_key(&value_cntr) || (19_000..20_000).contains(&value_cntr) || reserved.contains_number(value_cntr) {
+            value_cntr += 1;
+          }
+          if value_cntr > 536_870_911 {
+            return Err(R2Proto3Error::new(None, "very big message! Max field number = 536_870_911"));
+          }
+          value_cntr += 1;
+          value_cntr - 1
+        },
+      };
+      // Имена полей совпадают с именами, под которыми их сериализует `serde`
+      let name = match (name, Attribute::find(&attrs, "serde", "rename").flatten(), rename_all) {
+        (_, Some(renamed), _) if TypesParser::is_identifier(renamed) => renamed.to_owned(),
+        (_, Some(renamed), _) => return Err(R2Proto3Error::new(None, format!("`#[serde(rename = \"{}\")]` isn't a valid Protobuf field name", renamed))),
+        (Some(name), None, Some(rule)) => TypesParser::apply_rename_rule(&name, rule)?,
+        (Some(name), None, None) => name,
+        (None, None, _) => {
+          let suffix = match self.options.tuple_field_style {
+            TupleFieldStyle::Field => field_num as usize,
+            TupleFieldStyle::Index => position,
+          };
+          format!("{}{}", self.options.tuple_field_prefix, suffix)
+        },
+      };
+      let name = if self.options.normalize_names { TypesParser::to_snake_case(&name) } else { name };
+      if reserved.names.contains(&name) {
+        return Err(R2Proto3Error::new(None, format!("field name `{}` is reserved", name)));
+      }
+      if fields.iter().any(|f: &ProtobufField| f.name == name) {
+        return Err(R2Proto3Error::new(None, format!("field name `{}` is used twice", name)));
+      }
+      
+      // Сериализатор из `#[serde(with = "...")]` мы не можем разобрать, поэтому тип берём из файла соответствий
+      let serde_with_type = Attribute::find(&attrs, "serde", "with").flatten().and_then(|with| {
+        let with = with.trim_start_matches("::");
+        self.options.serde_with_types.get(with).or_else(|| self.options.serde_with_types.get(with.rsplit("::").next().unwrap_or(with)))
+      });
+      let explicit_type = Attribute::find(&attrs, "proto", "type").flatten();
+      let proto3_type = match explicit_type.or(serde_with_type.map(|t| t.as_str())) {
+        Some(proto3_type) => Ok(proto3_type.to_owned()),
+        // `#[proto(repeated_bytes)]`: байты по одному в `repeated uint32` вместо `bytes`
+        None if Attribute::find(&attrs, "proto", "repeated_bytes").is_some() => match rust_type.rsplit("::").next() {
+          Some("Vec<u8>") => Ok("repeated uint32".to_owned()),
+          _ => Err(R2Proto3Error::new(None, format!("`#[proto(repeated_bytes)]` is only applicable to `Vec<u8>`, but field `{}` is `{}`", name, rust_type))),
+        },
+        None => self.types_parser.rust_type_to_protobuf(&rust_type, known_types, false),
+      };
+      
+      let doc = self.note_type(field.annotations.doc(), &rust_type);
+      match proto3_type {
+        Ok(proto3_type) => fields.push(ProtobufField {
+          name,
+          rust_type,
+          proto3_type: if Attribute::find(&attrs, "proto", "zigzag").is_some() { TypesParser::to_zigzag(&proto3_type) } else { proto3_type },
+          field_num,
+          example,
+          options: field.annotations.doc_tag_options(),
+          todos: if self.options.preserve_todos { field.annotations.todos() } else { vec![] },
+          oneof: None,
+          doc,
+          deprecated: field.annotations.is_deprecated(&self.options.cfg),
+          source_line: Some(field.annotations.line),
+        }),
+        // При сборе неизвестных типов разбираем все поля, а не останавливаемся на первой ошибке
+        Err(e) if self.options.dump_unknown_types => { first_error.get_or_insert(e); },
+        Err(e) => return Err(e),
+      }
+    }
+    
+    match first_error {
+      Some(e) => Err(e),
+      None => {
+        Self::check_field_numbers(&fields)?;
+        Ok(fields)
+      },
+    }
+  }
+  
+  /// Самопроверка перед выводом: два поля с одним номером дали бы файл, который не собирается.
+  fn check_field_numbers(fields: &[ProtobufField]) -> MResult<()> {
+    let mut assigned = BTreeMap::new();
+    for field in fields {
+      if let Some(first) = assigned.insert(field.field_num, &field.name) {
+        return Err(R2Proto3Error::new(None, format!("fields `{}` and `{}` share field number {}", first, field.name, field.field_num)));
+      }
+    }
+    Ok(())
+  }
+  
+  /// Денежная сумма: структура `Money` ровно из двух полей, `amount` с десятичным числом и `currency` со строкой.
+  fn is_money_pattern(name: &str, fields: &[SourceField]) -> bool {
+    let fields = fields
+      .iter()
+      .filter_map(|f| Some((f.name.clone()?, TypesParser::drop_type_unnecessary_stuff(&f.ty))))
+      .collect::<BTreeMap<_, _>>();
+    name == "Money"
+      && fields.len() == 2
+      && fields.get("amount").is_some_and(|t| ["Decimal", "rust_decimal::Decimal", "BigDecimal", "bigdecimal::BigDecimal"].contains(&t.as_str()))
+      && fields.get("currency").is_some_and(|t| t == "String")
+  }
+}
+
+/// Регулярные выражения для просмотра исходных файлов.
+struct Patterns {
+  path_re: Regex,
+}
+
+/// Всё, что извлекается из одного файла до перевода типов.
+struct FileScan {
+  path: PathBuf,
+  /// Существующие файлы модулей из `#[path = "..."]`
+  modules: Vec<PathBuf>,
+  items: Vec<AnnotatedItem>,
+  /// Целочисленные константы `const NAME: iN = value;`
+  consts: BTreeMap<String, String>,
+  /// Псевдонимы типов `type X = Y;` в порядке объявления
+  aliases: Vec<(String, String)>,
+  fns: Vec<AnnotatedFn>,
+  warnings: Vec<String>,
+  read: Duration,
+  extraction: Duration,
+}
+
+impl Patterns {
+  /// Читает файл и извлекает из него помеченные типы, функции, константы и псевдонимы. Состояние парсера не меняется,
+  /// а предупреждения возвращаются вместе с результатом, поэтому файлы можно просматривать из нескольких потоков.
+  fn scan(&self, path: &Path) -> MResult<FileScan> {
+    let read_start = Instant::now();
+    let mut f = File::open(path).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось открыть файл"))?;
+    let mut contents = String::new();
+    f.read_to_string(&mut contents).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось считать содержимое файла"))?;
+    let read = read_start.elapsed();
+    let extraction_start = Instant::now();
+    let mut warnings = vec![];
+    
+    // Модули, подключённые через `#[path = "..."]`, могут лежать вне корня крейта
+    let mut modules = vec![];
+    for (_, [module_path]) in self.path_re.captures_iter(&contents).map(|c| c.extract()) {
+      let module_path = path.parent().unwrap_or(Path::new("")).join(module_path);
+      if module_path.is_file() {
+        modules.push(module_path);
+      } else {
+        warnings.push(format!("module file `{}` referenced from `{}` doesn't exist", module_path.display(), path.display()));
+      }
+    }
+    
+    // Помеченные типы и функции, константы и псевдонимы ищутся синтаксическим разбором
+    let FileItems { items, fns, consts, aliases } = items::parse_file(&contents).map_err(|e| {
+      let position = e.span().start();
+      let msg = format!("can't parse `{}` as Rust (line {}, column {})", path.display(), position.line, position.column + 1);
+      R2Proto3Error::new(Some(Box::new(e)), msg)
+    })?;
+    let aliases = aliases.into_iter().map(|(alias, target)| (alias, TypesParser::drop_type_unnecessary_stuff(target))).collect();
+    
+    Ok(FileScan { path: path.to_owned(), modules, items, consts, aliases, fns, warnings, read, extraction: extraction_start.elapsed() })
+  }
+}
+
+impl<'a> Parser<'a> {
+  /// Заменяет обобщённые типы их конкретизациями из пометки: `// NOTE: ToProtobuf<User, Order>` над `Page<T>` даёт
+  /// `PageUser` и `PageOrder` с подставленным вместо `T` типом. Ссылки `Page<User>` в полях других типов переводятся
+  /// в `PageUser`. Обобщённый тип без списка конкретизаций пропускается с предупреждением.
+  fn instantiate_generics(&mut self, items: Vec<AnnotatedItem>) -> Vec<AnnotatedItem> {
+    let mut instances = vec![];
+    for item in items {
+      if item.generics.is_empty() {
+        if !item.instantiations.is_empty() {
+          self.types_parser.warn(format!("`{}` has no type parameters, instantiations `<{}>` in its marker are ignored", item.name, item.instantiations));
+        }
+        instances.push(item);
+        continue
       }
-      else if parts.len() >= 2 {
-        let name = TypesParser::clear_type_name(parts[0].to_owned());
-        let rust_type = TypesParser::drop_type_unnecessary_stuff(parts.iter().skip(1).map(|p| p.to_owned()).collect::<Vec<_>>().join(":"));
-        fields.push(ProtobufField {
+      let generic = format!("{}<{}>", item.name, item.generics.join(", "));
+      if item.instantiations.is_empty() {
+        self.types_parser.warn(format!("generic type `{}` won't be attached to `.proto` file: list its instantiations in the marker, e.g. `// NOTE: ToProtobuf<User, Order>`", generic));
+        self.skipped.push((item.name, "generic type without instantiations".into()));
+        continue
+      }
+      let Ok(instantiations) = TypesParser::split_inner_types(&item.instantiations) else {
+        self.types_parser.warn(format!("can't parse instantiations `<{}>` of `{}`", item.instantiations, generic));
+        self.skipped.push((item.name, "generic type without instantiations".into()));
+        continue
+      };
+      for instantiation in instantiations.into_iter().map(|i| i.trim()) {
+        // Несколько параметров задаются кортежем: `<(User, Order)>` для `Pair<A, B>`
+        let args = match instantiation.strip_prefix('(').and_then(|i| i.strip_suffix(')')) {
+          Some(tuple) if item.generics.len() > 1 => TypesParser::split_inner_types(tuple).unwrap_or_default().into_iter().map(|a| a.trim()).collect(),
+          _ => vec![instantiation],
+        };
+        if args.len() != item.generics.len() {
+          self.types_parser.warn(format!("instantiation `{}` of `{}` doesn't match its type parameters and is skipped", instantiation, generic));
+          continue
+        }
+        let name = Self::instance_name(&item.name, &args);
+        self.types_parser.aliases.borrow_mut().insert(TypesParser::without_module_paths(&format!("{}<{}>", item.name, args.join(", "))), name.clone());
+        // Параметры подставляются только в типы полей: имена полей и атрибуты остаются как есть
+        let substitute = |fields: &[SourceField]| fields.iter().map(|f| SourceField { ty: Self::substitute_type_params(&f.ty, &item.generics, &args), ..f.clone() }).collect::<Vec<_>>();
+        instances.push(AnnotatedItem {
+          kind: item.kind,
           name,
-          proto3_type: self.types_parser.rust_type_to_protobuf(&rust_type, known_types, false)?.to_owned(),
-          field_num: value_cntr,
+          gap: item.gap.clone(),
+          fields: substitute(&item.fields),
+          variants: item.variants.iter().map(|v| SourceVariant { fields: substitute(&v.fields), ..v.clone() }).collect(),
+          generics: vec![],
+          instantiations: String::new(),
+          line: item.line,
         });
       }
-      value_cntr += 1;
-      // See [Language Guide (proto 3) - Assigning Field Numbers](https://protobuf.dev/programming-guides/proto3/#assigning).
-      if value_cntr == 19_000 {
-        value_cntr = 20_000;
-      } else if value_cntr == 536_870_912 {
-        return Err(R2Proto3Error::new(None, "very big message! Max field number = 536_870_911"));
+    }
+    instances
+  }
+  
+  /// Имя конкретизации: `Page` с `Vec<crate::User>` -> `PageVecUser`. Пути модулей отбрасываются.
+  fn instance_name(name: &str, args: &[&str]) -> String {
+    let mut result = name.to_owned();
+    for arg in args {
+      let mut rest = *arg;
+      while let Some(start) = rest.find(|c: char| c.is_alphanumeric() || c == '_') {
+        let len = rest[start..].find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len() - start);
+        let (ident, tail) = (&rest[start..start + len], &rest[start + len..]);
+        if !tail.trim_start().starts_with("::") { result += &TypesParser::to_pascal_case(ident); }
+        rest = tail;
+      }
+    }
+    result
+  }
+  
+  /// Подставляет типы вместо параметров в тип поля: `Vec<T>` -> `Vec<User>`.
+  fn substitute_type_params(rust_type: &str, params: &[String], args: &[&str]) -> String {
+    let mut result = String::new();
+    let mut ident = String::new();
+    for sym in rust_type.chars().chain(std::iter::once('\0')) {
+      if sym.is_alphanumeric() || sym == '_' {
+        ident.push(sym);
+        continue
+      }
+      match params.iter().position(|p| *p == ident) {
+        Some(i) => result += args[i],
+        None => result += &ident,
+      }
+      ident.clear();
+      if sym != '\0' { result.push(sym); }
+    }
+    result
+  }
+  
+  fn is_doc_hidden(attrs: &[Attribute]) -> bool {
+    Attribute::find(attrs, "doc", "hidden") == Some(None)
+  }
+  
+  /// Переводит атрибуты между маркером и объявлением типа в опции Protobuf уровня сообщения или перечисления.
+  fn type_options_from_attributes(attrs: &[Attribute]) -> Vec<String> {
+    let mut options = vec![];
+    if attrs.iter().any(|a| a.path == "deprecated") {
+      options.push("deprecated = true".to_owned());
+    }
+    options
+  }
+  
+  /// При `--ensure-unspecified` добавляет в начало перечисления вариант `<ENUM>_UNSPECIFIED = 0`, если первый вариант
+  /// не является таким нулевым значением. Если 0 уже занят, значения остальных вариантов сдвигаются на единицу.
+  fn ensure_unspecified(&self, enum_name: &str, mut variants: Vec<ProtobufEnumVariant>) -> MResult<Vec<ProtobufEnumVariant>> {
+    if !self.options.ensure_unspecified { return Ok(variants) }
+    if let Some(first) = variants.first() && first.value == 0 {
+      let name = first.name.to_lowercase();
+      if name.contains("unspecified") || name.contains("unknown") { return Ok(variants) }
+    }
+    
+    if variants.iter().any(|v| v.value == 0) {
+      for variant in variants.iter_mut() {
+        variant.value = variant.value.checked_add(1)
+          .ok_or_else(|| R2Proto3Error::new(None, format!("can't shift value of variant `{}` to insert an unspecified variant", variant.name)))?;
+      }
+      self.types_parser.warn(format!("values of the enum `{}` are shifted by one to insert an unspecified variant: this changes its wire format", enum_name));
+    }
+    variants.insert(0, ProtobufEnumVariant { name: format!("{}_UNSPECIFIED", TypesParser::to_screaming_snake_case(enum_name)), value: 0, doc: None, deprecated: false, source_line: None });
+    Ok(variants)
+  }
+  
+  /// Единственный вариант без данных среди вариантов с данными (`None` в `enum E { None, Some(Foo) }`). Он не попадает
+  /// в `oneof`: его обозначает незаполненный `oneof`, о чём говорит комментарий к сообщению.
+  fn unset_variant(variants: &[SourceVariant]) -> Option<&SourceVariant> {
+    let units = variants.iter().filter(|v| v.shape == VariantShape::Unit).collect::<Vec<_>>();
+    match units.as_slice() {
+      [unit] if variants.len() > 1 => Some(unit),
+      _ => None,
+    }
+  }
+  
+  /// Переводит перечисление с данными в сообщение с блоком `oneof`, где каждому варианту соответствует поле.
+  /// Варианты без данных становятся полями `google.protobuf.Empty`, варианты с несколькими значениями или
+  /// именованными полями - отдельными сообщениями `<Enum><Variant>`.
+  fn parse_data_enum(&self, enum_name: &str, variants: &[SourceVariant], known_types: &BTreeSet<String>) -> MResult<Vec<(String, ProtobufEntityType)>> {
+    let mut types = vec![];
+    let mut fields = vec![];
+    let unset = Self::unset_variant(variants);
+    
+    for variant in variants {
+      let name = &variant.name;
+      if let Some(discriminant) = &variant.discriminant {
+        return Err(R2Proto3Error::new(None, format!("explicit discriminants aren't supported in enums with data - in variant `{} = {}`", name, discriminant)));
       }
+      let (rust_type, proto3_type) = match variant.shape {
+        VariantShape::Unit => {
+          if unset.is_some_and(|u| u.name == *name) { continue }
+          let proto3_type = self.types_parser.rust_type_to_protobuf("()", known_types, false)?;
+          ("()".to_owned(), proto3_type)
+        },
+        shape => {
+          let single = match variant.fields.as_slice() {
+            [field] if shape == VariantShape::Tuple => {
+              let rust_type = TypesParser::drop_type_unnecessary_stuff(&field.ty);
+              let proto3_type = self.types_parser.rust_type_to_protobuf(&rust_type, known_types, false)?;
+              Some((rust_type, proto3_type))
+            },
+            _ => None,
+          };
+          match single {
+            // Поля `oneof` не могут быть `repeated`, `optional` или `map`, такие значения оборачиваются в сообщение
+            Some((rust_type, proto3_type)) if !proto3_type.contains(' ') => (rust_type, proto3_type),
+            _ => {
+              let message = format!("{}{}", enum_name, name);
+              if known_types.contains(&message) {
+                return Err(R2Proto3Error::new(None, format!("can't generate message `{}` for variant `{}`: the type with the same name already exists", message, name)));
+              }
+              types.push((message.to_owned(), ProtobufEntityType::Message(self.parse_struct_fields(&variant.fields, known_types, None, None)?)));
+              (format!("{}::{}", enum_name, name), message)
+            },
+          }
+        },
+      };
+      let doc = self.note_type(variant.annotations.doc(), &rust_type);
+      fields.push(ProtobufField {
+        name: TypesParser::to_snake_case(name),
+        rust_type,
+        proto3_type,
+        field_num: fields.len() as i32 + 1,
+        example: None,
+        options: variant.annotations.doc_tag_options(),
+        todos: if self.options.preserve_todos { variant.annotations.todos() } else { vec![] },
+        oneof: Some(TypesParser::to_snake_case(enum_name)),
+        doc,
+        deprecated: variant.annotations.is_deprecated(&self.options.cfg),
+        source_line: Some(variant.annotations.line),
+      });
     }
     
-    Ok(fields)
+    Self::check_field_numbers(&fields)?;
+    types.insert(0, (enum_name.to_owned(), ProtobufEntityType::Message(fields)));
+    Ok(types)
   }
   
-  fn parse_enum_fields(&self, variants_str: &Vec<String>) -> MResult<Vec<ProtobufEnumVariant>> {
-    let mut variants = vec![];
-    let mut variant_id_cntr = 0;
+  fn parse_enum_fields(&self, variants_str: &[SourceVariant]) -> MResult<Vec<ProtobufEnumVariant>> {
+    let mut variants: Vec<ProtobufEnumVariant> = vec![];
+    let mut next_value = 0i64;
     
-    for variant in variants_str.iter() {
-      let variant = TypesParser::drop_type_unnecessary_stuff(variant);
+    for source in variants_str.iter() {
+      // Явный дискриминант `Variant = 5`; следующие варианты продолжают счёт от него, как и в Rust
+      let name = source.name.to_owned();
+      let value = match &source.discriminant {
+        Some(discriminant) => match TypesParser::parse_int_literal(discriminant) {
+          Some(value) => value,
+          None => return Err(R2Proto3Error::new(None, format!(
+            "discriminant `{}` of variant `{}` is neither an integer literal nor an integer const declared in the same file",
+            discriminant,
+            name,
+          ))),
+        },
+        None => next_value,
+      };
+      next_value = value + 1;
       
-      if variant.contains('(') {
-        return Err(R2Proto3Error::new(None, format!("current version of `r2proto3` isn't supporting enums with values in them - in variant `{}`", variant)));
+      // Значения перечислений в Protobuf хранятся как `int32`
+      let value = i32::try_from(value).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), format!(
+        "value {} of variant `{}` doesn't fit into `int32` ({}..={})",
+        value,
+        name,
+        i32::MIN,
+        i32::MAX,
+      )))?;
+      if let Some(alias) = variants.iter().find(|v| v.value == value) && self.options.strict {
+        return Err(R2Proto3Error::new(None, format!("variants `{}` and `{}` share the value {}, which requires `allow_alias`", alias.name, name, value)));
       }
-      
-      variants.push(ProtobufEnumVariant {
-        name: variant.to_owned(),
-        value: variant_id_cntr,
-      });
-      variant_id_cntr += 1;
+      variants.push(ProtobufEnumVariant { name, value, doc: source.annotations.doc(), deprecated: source.annotations.is_deprecated(&self.options.cfg), source_line: Some(source.annotations.line) });
     }
     
     Ok(variants)
   }
   
-  pub(crate) fn generate(&self) -> String {
-    let mut contents = r#"syntax = "proto3";"#.to_owned() + "\n";
+  /// Отчёт о неизвестных типах, отсортированный по убыванию частоты.
+  pub fn unknown_types_report(&self) -> String {
+    let unknown_types = self.types_parser.unknown_types.borrow();
+    let mut unknown_types = unknown_types.iter().collect::<Vec<_>>();
+    unknown_types.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
     
-    for (type_name, r#type) in &self.types {
-      match &r#type {
-        ProtobufEntityType::Message(msg) => {
-          contents += "\n";
-          contents += &format!("message {} {{", type_name);
-          for field in msg {
+    let mut report = "Unknown types:\n".to_owned();
+    for (rust_type, count) in unknown_types {
+      report += &format!("  {:>5}  {}\n", count, rust_type);
+    }
+    report
+  }
+  
+  /// Все предупреждения, собранные при разборе, в том числе не напечатанные из-за `quiet`.
+  pub fn warnings(&self) -> Vec<String> {
+    self.types_parser.warnings.borrow().clone()
+  }
+  
+  /// SHA-256 модели типов. Не зависит от форматирования вывода, поэтому по нему можно понять, изменится ли файл после перегенерации.
+  pub(crate) fn model_hash(&self) -> String {
+    let model = json!({ "types": self.types, "imports": self.imports, "type_options": self.type_options, "reserved": self.reserved }).to_string();
+    Sha256::digest(model.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+  }
+  
+  /// Список файлов, которые импортирует сгенерированный `.proto`: JSON-массив или по одному на строке.
+  pub fn import_manifest(&self, as_json: bool) -> String {
+    if as_json {
+      json!(self.imports).to_string()
+    } else {
+      self.imports.iter().map(|i| format!("{}\n", i)).collect()
+    }
+  }
+  
+  /// Отчёт о номерах полей всех сообщений: JSON-массив или CSV с заголовком.
+  pub fn tag_report(&self, as_json: bool) -> String {
+    let rows = self.types.iter().flat_map(|(message, r#type)| match r#type {
+      ProtobufEntityType::Message(fields) => fields.iter().map(|f| (message, f)).collect::<Vec<_>>(),
+      _ => vec![],
+    });
+    if as_json {
+      return json!(rows.map(|(message, f)| json!({
+        "message": message,
+        "field": f.name,
+        "rust_type": f.rust_type,
+        "proto_type": f.proto3_type,
+        "number": f.field_num,
+      })).collect::<Vec<_>>()).to_string()
+    }
+    
+    let quote = |value: &str| if value.contains([',', '"', '\n']) { format!("\"{}\"", value.replace('"', "\"\"")) } else { value.to_owned() };
+    let mut report = "message,field,rust_type,proto_type,number\n".to_owned();
+    for (message, f) in rows {
+      report += &format!("{},{},{},{},{}\n", quote(message), quote(&f.name), quote(&f.rust_type), quote(&f.proto3_type), f.field_num);
+    }
+    report
+  }
+  
+  /// Сгенерированный файл вместе с моделью типов, импортами и предупреждениями в виде одного JSON-объекта.
+  pub fn generate_json_bundle(&self) -> String {
+    let mut messages = vec![];
+    let mut enums = vec![];
+    for (name, r#type) in &self.types {
+      match r#type {
+        ProtobufEntityType::Message(fields) => messages.push(json!({ "name": name, "fields": fields })),
+        ProtobufEntityType::Enum(variants) => enums.push(json!({ "name": name, "variants": variants })),
+      }
+    }
+    let rpcs = self.rpcs.values().map(|rpc| json!(rpc)).collect::<Vec<_>>();
+    
+    json!({
+      "proto": self.generate(),
+      "messages": messages,
+      "enums": enums,
+      "rpcs": rpcs,
+      "imports": self.imports,
+      "warnings": *self.types_parser.warnings.borrow(),
+    }).to_string()
+  }
+  
+  /// Сгенерированный файл вместе с количеством сообщений, перечислений и сервисов.
+  pub fn generation_result(&self) -> GenerationResult {
+    let count = |f: fn(&ProtobufEntityType) -> bool| self.types.values().filter(|t| f(t)).count();
+    GenerationResult {
+      proto: self.generate(),
+      imports: self.imports.iter().cloned().collect(),
+      messages: count(|t| matches!(t, ProtobufEntityType::Message(_))),
+      enums: count(|t| matches!(t, ProtobufEntityType::Enum(_))),
+      services: self.services().len(),
+      skipped: self.skipped.clone(),
+    }
+  }
+  
+  fn parse_rpc_signature(&self, fn_name: &str, args: &[String], ret: Option<&str>, directives: &[String], known_types: &BTreeSet<String>) -> MResult<ProtobufRpc> {
+    let [request] = args else {
+      return Err(R2Proto3Error::new(None, format!("rpc function should take exactly one request argument, got {}", args.len())));
+    };
+    let request = TypesParser::drop_type_unnecessary_stuff(request);
+    let Some(ret) = ret else {
+      return Err(R2Proto3Error::new(None, "rpc function should return a response message"));
+    };
+    let mut response = TypesParser::drop_type_unnecessary_stuff(ret);
+    
+    // `impl Future<Output = T> + Send` отвечает `T`, как и `async fn`
+    if let Some(bound) = response.strip_prefix("impl ").and_then(|r| r.split(" + ").next())
+      && let Some((path, output)) = bound.trim().strip_suffix('>').and_then(|b| b.split_once('<'))
+      && path.rsplit("::").next() == Some("Future")
+      && let Some((_, output)) = output.split_once('=')
+    {
+      response = TypesParser::drop_type_unnecessary_stuff(output);
+    }
+    // Ошибки gRPC передаются статусом вне сообщения, поэтому `Result<Resp, Status>` отвечает просто `Resp`
+    if let Some(inner) = response.strip_prefix("Result<").and_then(|r| r.strip_suffix('>')) {
+      response = TypesParser::drop_type_unnecessary_stuff(TypesParser::split_inner_types(inner)?[0]);
+    }
+    // Обёртки обработчиков `tonic`
+    let unwrap_tonic = |r#type: String, wrapper: &str| {
+      r#type
+        .strip_prefix("tonic::").unwrap_or(&r#type)
+        .strip_prefix(wrapper).and_then(|t| t.strip_prefix('<')).and_then(|t| t.strip_suffix('>'))
+        .map(TypesParser::drop_type_unnecessary_stuff)
+        .unwrap_or(r#type)
+    };
+    let request = unwrap_tonic(request, "Request");
+    let response = unwrap_tonic(response, "Response");
+    
+    let mut options = vec![];
+    let (mut client_streaming, mut server_streaming) = (false, false);
+    for directive in directives {
+      match directive.as_str() {
+        "idempotent" => options.push("idempotency_level = IDEMPOTENT".to_owned()),
+        "client_streaming" => client_streaming = true,
+        "server_streaming" => server_streaming = true,
+        "bidi" => (client_streaming, server_streaming) = (true, true),
+        _ => return Err(R2Proto3Error::new(None, format!("unknown rpc directive `// proto: {}`", directive))),
+      }
+    }
+    
+    // Для потоков с собственной обёрткой (`EventStream<Event>`) сообщением считается её параметр
+    let stream_item = |r#type: String| match r#type.split_once('<') {
+      Some((_, inner)) if r#type.ends_with('>') => TypesParser::drop_type_unnecessary_stuff(&inner[..inner.len() - 1]),
+      _ => r#type,
+    };
+    
+    // Запрос и ответ должны быть помеченными сообщениями
+    let resolve_message = |r#type: String, role: &str| {
+      if known_types.contains(&r#type) { return Ok(r#type) }
+      match r#type.rsplit_once("::") {
+        Some((_, name)) if self.options.strip_module_paths && known_types.contains(name) => Ok(name.to_owned()),
+        _ => Err(R2Proto3Error::new(None, format!("{} type `{}` isn't an annotated message", role, r#type))),
+      }
+    };
+    let request = resolve_message(if client_streaming { stream_item(request) } else { request }, "request")?;
+    let response = resolve_message(if server_streaming { stream_item(response) } else { response }, "response")?;
+    
+    Ok(ProtobufRpc {
+      name: TypesParser::to_pascal_case(fn_name),
+      request,
+      response,
+      client_streaming,
+      server_streaming,
+      options,
+      docs: vec![],
+      service: None,
+    })
+  }
+  
+  /// Имя каталога крейта (или его родителя, если указан каталог `src`).
+  fn crate_dir_name(&self) -> Option<String> {
+    let root = Path::new(self.crate_name);
+    let root = root.canonicalize().unwrap_or(root.to_path_buf());
+    match root.file_name().and_then(|n| n.to_str()) {
+      Some("src") => root.parent().and_then(|p| p.file_name()).and_then(|n| n.to_str()).map(|n| n.to_owned()),
+      name => name.map(|n| n.to_owned()),
+    }
+  }
+  
+  /// Общий сервис: из `--service-name` или из имени каталога крейта.
+  fn service_name(&self) -> String {
+    match &self.options.service_name {
+      Some(service) => service.to_owned(),
+      None => TypesParser::to_pascal_case(self.crate_dir_name().unwrap_or("Service".into())),
+    }
+  }
+  
+  /// Методы по сервисам. И сервисы, и методы в каждом из них упорядочены по имени, чтобы вывод не зависел от порядка функций в коде.
+  fn services(&self) -> BTreeMap<String, Vec<&ProtobufRpc>> {
+    let mut services = BTreeMap::<String, Vec<&ProtobufRpc>>::new();
+    for ((service, _), rpc) in &self.rpcs {
+      services.entry(service.to_owned()).or_default().push(rpc);
+    }
+    services
+  }
+  
+  /// Пакет из `--package` или из имени каталога крейта; если оно не подходит для пакета, то `generated`.
+  pub(crate) fn package_name(&self) -> String {
+    match &self.options.package {
+      Some(package) => package.to_owned(),
+      None => self.crate_dir_name()
+        .map(|n| n.to_lowercase().replace(['-', ' ', '.'], "_"))
+        .filter(|n| Self::is_valid_package(n))
+        .unwrap_or("generated".into()),
+    }
+  }
+  
+  /// Путь модуля по пути файла относительно корня крейта: `src/billing/invoices.rs` и `src/billing/invoices/mod.rs` -> `billing.invoices`.
+  /// Всё до каталога `src` (члены рабочего пространства) отбрасывается, `lib.rs`/`main.rs` соответствуют корневому модулю.
+  fn module_path(path: &Path) -> String {
+    let mut parts = path.with_extension("").iter().map(|p| p.to_string_lossy().into_owned()).collect::<Vec<_>>();
+    if let Some(src) = parts.iter().position(|p| p == "src") {
+      parts.drain(..=src);
+    }
+    if matches!(parts.last().map(|p| p.as_str()), Some("lib" | "main" | "mod")) {
+      parts.pop();
+    }
+    parts.iter().map(|p| p.to_lowercase().replace(['-', ' '], "_")).collect::<Vec<_>>().join(".")
+  }
+  
+  /// Модуль, в файл которого попадает тип при `--auto-package-from-modules` или `--split-by-module`.
+  /// Пустая строка - основной выходной файл: для корневого модуля и когда вывод не делится по модулям.
+  fn type_module(&self, name: &str) -> &str {
+    match self.type_modules.get(name) {
+      Some(module) if self.options.split_by_module && !module.is_empty() => module,
+      Some(module) if self.options.auto_package_from_modules && !module.is_empty() && Self::is_valid_package(module) => module,
+      _ => "",
+    }
+  }
+  
+  /// Пакет файла модуля: с `--auto-package-from-modules` - путь модуля, иначе (и для корневого модуля) - общий пакет.
+  fn module_package(&self, module: &str) -> String {
+    if self.options.auto_package_from_modules && !module.is_empty() {
+      module.to_owned()
+    } else {
+      self.package_name()
+    }
+  }
+  
+  fn type_package(&self, name: &str) -> String {
+    self.module_package(self.type_module(name))
+  }
+  
+  /// Файл модуля для импорта: `billing.invoices` -> `billing/invoices.proto`, корневой модуль - основной выходной файл.
+  fn module_file(&self, module: &str) -> String {
+    if module.is_empty() {
+      self.options.root_file.to_owned()
+    } else {
+      format!("{}.proto", module.replace('.', "/"))
+    }
+  }
+  
+  /// Ссылка на тип из файла пакета `package`: вложенные типы - `Parent.Child`, типы других пакетов - `.billing.invoices.Invoice`.
+  fn type_reference(&self, name: &str, package: &str, parents: &BTreeMap<String, String>) -> Option<String> {
+    let foreign = self.options.auto_package_from_modules && self.types.contains_key(name) && self.type_package(name) != package;
+    match (foreign, parents.contains_key(name)) {
+      (true, _) => Some(format!(".{}.{}", self.type_package(name), Self::qualified_name(name, parents))),
+      (false, true) => Some(Self::qualified_name(name, parents)),
+      (false, false) => None,
+    }
+  }
+  
+  /// Имя пакета Protobuf: идентификаторы из строчных латинских букв, цифр и `_`, разделённые точками.
+  fn is_valid_package(package: &str) -> bool {
+    package.split('.').all(|part| {
+      part.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    })
+  }
+  
+  /// Открывающая скобка блока, начинающегося с отступом `indent`.
+  fn open_brace(&self, indent: &str) -> String {
+    match self.options.brace_style {
+      BraceStyle::SameLine => " {".into(),
+      BraceStyle::NextLine => format!("\n{}{{", indent),
+    }
+  }
+  
+  /// Сообщение или перечисление вместе с вложенными в него типами, каждая строка с отступом `indent`.
+  fn render_type(&self, type_name: &str, r#type: &ProtobufEntityType, indent: &str, package: &str, parents: &BTreeMap<String, String>) -> String {
+    let mut contents = String::new();
+    for line in self.type_docs.get(type_name).into_iter().flat_map(|d| d.lines()) {
+      contents += format!("{}// {}", indent, line).trim_end();
+      contents += "\n";
+    }
+    match r#type {
+      ProtobufEntityType::Message(msg) => {
+        contents += &format!("{}message {}{}", indent, type_name, self.open_brace(indent));
+        for option in self.type_options.get(type_name).into_iter().flatten() {
+          contents += &format!("\n{}  option {};", indent, option);
+        }
+        // Список уже проверен при разборе полей
+        if let Some(Ok(reserved)) = self.reserved.get(type_name).map(|r| ReservedFields::parse(r)) {
+          if !reserved.numbers.is_empty() {
+            let numbers = reserved.numbers
+              .iter()
+              .map(|(from, to)| if from == to { from.to_string() } else { format!("{} to {}", from, to) })
+              .collect::<Vec<_>>();
+            contents += &format!("\n{}  reserved {};", indent, numbers.join(", "));
+          }
+          if !reserved.names.is_empty() {
+            contents += &format!("\n{}  reserved {};", indent, reserved.names.iter().map(|n| format!("\"{}\"", n)).collect::<Vec<_>>().join(", "));
+          }
+        }
+        let mut oneof = None;
+        for field in msg {
+          if field.oneof.as_deref() != oneof {
+            if oneof.is_some() { contents += &format!("\n{}  }}", indent); }
+            if let Some(name) = &field.oneof { contents += &format!("\n{}  oneof {}{}", indent, name, self.open_brace(&format!("{}  ", indent))); }
+            oneof = field.oneof.as_deref();
+          }
+          let field_indent = if oneof.is_some() { format!("{}    ", indent) } else { format!("{}  ", indent) };
+          for line in field.doc.iter().flat_map(|d| d.lines()) {
             contents += "\n";
-            contents += &format!("  {} {} = {};", field.proto3_type, field.name, field.field_num);
+            contents += format!("{}// {}", field_indent, line).trim_end();
           }
-          contents += "\n}\n";
-        },
-        ProtobufEntityType::Enum(r#enum) => {
+          for todo in &field.todos {
+            contents += &format!("\n{}// {}", field_indent, todo);
+          }
+          // Ссылки на вложенные типы записываются полным именем: `Parent.Child`
+          let proto3_type = TypesParser::map_type_names(&field.proto3_type, |name| self.type_reference(name, package, parents));
           contents += "\n";
-          contents += &format!("enum {} {{", type_name);
-          for variant in r#enum {
+          // В proto2 у каждого поля вне `oneof`, кроме `repeated` и `map`, должна быть метка
+          let label = match self.options.syntax {
+            Syntax::Proto2 if oneof.is_none() && !["optional ", "repeated ", "map<"].iter().any(|p| proto3_type.starts_with(p)) => {
+              if self.types_parser.is_optional(&field.rust_type) { "optional " } else { "required " }
+            },
+            _ => "",
+          };
+          contents += &format!("{}{}{} {} = {}", field_indent, label, proto3_type, field.name, field.field_num);
+          let mut options = field.options.iter().map(|(k, v)| format!("{} = {}", k, v)).collect::<Vec<_>>();
+          if field.deprecated && !field.options.iter().any(|(k, _)| k == "deprecated") {
+            options.push("deprecated = true".to_owned());
+          }
+          if !options.is_empty() {
+            contents += &format!(" [{}]", options.join(", "));
+          }
+          contents += ";";
+          let mut comments = vec![];
+          if self.options.annotate_nullability {
+            comments.push(if self.types_parser.is_optional(&field.rust_type) { "nullable".to_owned() } else { "non-null".to_owned() });
+          }
+          if self.options.emit_examples && let Some(example) = &field.example {
+            comments.push(format!("example: {}", example));
+          }
+          if !comments.is_empty() {
+            contents += &format!(" // {}", comments.join(", "));
+          }
+        }
+        if oneof.is_some() { contents += &format!("\n{}  }}", indent); }
+        for (child, _) in parents.iter().filter(|(_, parent)| *parent == type_name) {
+          if let Some(child_type) = self.types.get(child) {
+            contents += if contents.ends_with('{') { "\n" } else { "\n\n" };
+            contents += &self.render_type(child, child_type, &format!("{}  ", indent), package, parents);
+          }
+        }
+        contents += &format!("\n{}}}", indent);
+      },
+      ProtobufEntityType::Enum(r#enum) => {
+        contents += &format!("{}enum {}{}", indent, type_name, self.open_brace(indent));
+        if r#enum.iter().enumerate().any(|(i, v)| r#enum[..i].iter().any(|p| p.value == v.value)) {
+          contents += &format!("\n{}  option allow_alias = true;", indent);
+        }
+        for option in self.type_options.get(type_name).into_iter().flatten() {
+          contents += &format!("\n{}  option {};", indent, option);
+        }
+        for variant in r#enum {
+          for line in variant.doc.iter().flat_map(|d| d.lines()) {
             contents += "\n";
-            contents += &format!("  {} = {};", variant.name, variant.value);
+            contents += format!("{}  // {}", indent, line).trim_end();
           }
-          contents += "\n}\n";
-        },
-        // _ => unimplemented!(),
-      }
-    }
+          contents += "\n";
+          contents += &format!("{}  {} = {}{};", indent, variant.name, variant.value, if variant.deprecated { " [deprecated = true]" } else { "" });
+        }
+        contents += &format!("\n{}}}", indent);
+      },
+    }
+    contents
+  }
+  
+  /// Типы, на которые ссылается ровно одно сообщение и ничто больше, вместе с этим сообщением (`--nest-single-use`).
+  /// Циклические ссылки не вкладываются, иначе типы оказались бы внутри самих себя.
+  fn single_use_parents(&self) -> BTreeMap<String, String> {
+    let mut referrers: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (type_name, r#type) in &self.types {
+      match r#type {
+        ProtobufEntityType::Message(fields) => for field in fields {
+          for name in field.proto3_type.split([' ', '<', '>', ',']).filter(|n| self.types.contains_key(*n)) {
+            referrers.entry(name).or_default().insert(type_name);
+          }
+        },
+        ProtobufEntityType::Enum(_) => {},
+      }
+    }
+    // Запросы и ответы методов используются вне сообщений
+    for rpc in self.rpcs.values() {
+      for name in [&rpc.request, &rpc.response] {
+        referrers.entry(name).or_default().insert("");
+      }
+    }
+    
+    let mut parents = referrers
+      .into_iter()
+      .filter_map(|(child, parents)| match parents.into_iter().collect::<Vec<_>>().as_slice() {
+        [parent] if !parent.is_empty() && *parent != child => Some((child.to_owned(), parent.to_string())),
+        _ => None,
+      })
+      .collect::<BTreeMap<_, _>>();
+    let cyclic = parents
+      .keys()
+      .filter(|child| {
+        let mut current = *child;
+        for _ in 0..parents.len() {
+          match parents.get(current) {
+            Some(parent) if parent == *child => return true,
+            Some(parent) => current = parent,
+            None => return false,
+          }
+        }
+        false
+      })
+      .cloned()
+      .collect::<Vec<_>>();
+    for child in cyclic {
+      parents.remove(&child);
+    }
+    parents
+  }
+  
+  /// `Child` -> `Grandparent.Parent.Child`
+  fn qualified_name(name: &str, parents: &BTreeMap<String, String>) -> String {
+    let mut path = vec![name];
+    while let Some(parent) = parents.get(*path.last().unwrap()) {
+      path.push(parent);
+    }
+    path.reverse();
+    path.join(".")
+  }
+  
+  fn syntax_line(&self) -> &'static str {
+    match self.options.syntax {
+      Syntax::Proto3 => r#"syntax = "proto3";"#,
+      Syntax::Proto2 => r#"syntax = "proto2";"#,
+    }
+  }
+  
+  /// Лицензионный заголовок, синтаксис и пакет.
+  fn file_header(&self, package: &str) -> String {
+    let mut contents = String::new();
+    if !self.options.license_header.is_empty() {
+      for line in &self.options.license_header {
+        contents += format!("// {}", line).trim_end();
+        contents += "\n";
+      }
+      contents += "\n";
+    }
+    contents += self.syntax_line();
+    contents += "\n";
+    contents += &format!("package {};\n", package);
+    contents
+  }
+  
+  /// Файл со вспомогательными типами для `--shared-wrappers-file`. `None`, если вспомогательных типов нет.
+  pub fn generate_shared_wrappers(&self) -> Option<String> {
+    if self.shared_types.is_empty() {
+      return None
+    }
+    let mut contents = self.file_header(&self.package_name());
+    
+    let imports = self.shared_types
+      .values()
+      .flat_map(|t| match t {
+        ProtobufEntityType::Message(fields) => fields.iter().flat_map(|f| self.types_parser.imports(&f.proto3_type)).collect(),
+        _ => vec![],
+      })
+      .collect::<BTreeSet<_>>();
+    if !imports.is_empty() {
+      contents += "\n";
+      for import in imports {
+        contents += &format!("import \"{}\";\n", import);
+      }
+    }
+    
+    for (type_name, r#type) in &self.shared_types {
+      contents += "\n";
+      contents += &self.render_type(type_name, r#type, "", &self.package_name(), &BTreeMap::default());
+      contents += "\n";
+    }
+    Some(contents)
+  }
+  
+  pub fn generate(&self) -> String {
+    self.generate_module("")
+  }
+  
+  /// Файлы модулей для `--auto-package-from-modules` и `--split-by-module` по путям относительно основного файла
+  /// (`billing/invoices.proto`). Типы корневого модуля и сервис остаются в основном файле, см. `generate`.
+  pub fn generate_module_files(&self) -> BTreeMap<String, String> {
+    self.types
+      .keys()
+      .map(|name| self.type_module(name))
+      .filter(|module| !module.is_empty())
+      .collect::<BTreeSet<_>>()
+      .into_iter()
+      .map(|module| (self.module_file(module), self.generate_module(module)))
+      .collect()
+  }
+  
+  /// Модули, на типы которых ссылаются типы модуля `module`.
+  fn module_dependencies(&self, module: &str) -> BTreeSet<&str> {
+    self.referenced_types(module)
+      .into_iter()
+      .filter(|name| self.types.contains_key(name))
+      .map(|name| self.type_module(&name))
+      .filter(|dependency| *dependency != module)
+      .collect()
+  }
+  
+  /// Имена типов, упомянутых в полях сообщений и методах сервиса модуля `module`.
+  fn referenced_types(&self, module: &str) -> Vec<String> {
+    let mut referenced = vec![];
+    for r#type in self.types.iter().filter(|(name, _)| self.type_module(name) == module).map(|(_, t)| t) {
+      match r#type {
+        ProtobufEntityType::Message(fields) => for field in fields {
+          referenced.extend(field.proto3_type.split([' ', '<', '>', ',']).map(|n| n.to_owned()));
+        },
+        ProtobufEntityType::Enum(_) => {},
+      }
+    }
+    // Сервисы лежат в файле корневого модуля
+    if module.is_empty() {
+      for rpc in self.rpcs.values() {
+        referenced.extend([rpc.request.to_owned(), rpc.response.to_owned()]);
+      }
+    }
+    referenced
+  }
+  
+  /// Цикл импортов между файлами модулей (`a.proto` -> `b.proto` -> `a.proto`), если он есть: `protoc` такие файлы не принимает.
+  fn import_cycle(&self) -> Option<Vec<String>> {
+    fn visit<'p>(parser: &'p Parser, module: &'p str, path: &mut Vec<&'p str>, done: &mut BTreeSet<&'p str>) -> Option<Vec<String>> {
+      if let Some(pos) = path.iter().position(|m| *m == module) {
+        return Some(path[pos..].iter().chain(std::iter::once(&module)).map(|m| parser.module_file(m)).collect())
+      }
+      if !done.insert(module) { return None }
+      path.push(module);
+      let cycle = parser.module_dependencies(module).into_iter().find_map(|dependency| visit(parser, dependency, path, done));
+      path.pop();
+      cycle
+    }
+    
+    if !self.options.auto_package_from_modules && !self.options.split_by_module {
+      return None
+    }
+    let modules = std::iter::once("").chain(self.types.keys().map(|name| self.type_module(name))).collect::<BTreeSet<_>>();
+    let mut done = BTreeSet::new();
+    modules.into_iter().find_map(|module| visit(self, module, &mut vec![], &mut done))
+  }
+  
+  /// Импорты файла модуля: известные типы Google, файлы других модулей и общий файл вспомогательных типов.
+  fn module_imports(&self, module: &str) -> BTreeSet<String> {
+    if !self.options.auto_package_from_modules && !self.options.split_by_module {
+      return self.imports.clone()
+    }
+    let mut imports = BTreeSet::new();
+    for r#type in self.types.iter().filter(|(name, _)| self.type_module(name) == module).map(|(_, t)| t) {
+      if let ProtobufEntityType::Message(fields) = r#type {
+        imports.extend(fields.iter().flat_map(|f| self.types_parser.imports(&f.proto3_type)));
+      }
+    }
+    imports.extend(self.module_dependencies(module).into_iter().map(|dependency| self.module_file(dependency)));
+    for name in self.referenced_types(module) {
+      if self.shared_types.contains_key(&name) && let Some(shared_file) = &self.options.shared_wrappers_file {
+        imports.insert(shared_file.to_owned());
+      }
+    }
+    imports
+  }
+  
+  fn generate_module(&self, module: &str) -> String {
+    let package = self.module_package(module);
+    let package = package.as_str();
+    let body = self.generate_body(module);
+    let imports = self.module_imports(module);
+    if let Some(template) = &self.options.template {
+      let imports = imports.iter().map(|i| format!("import \"{}\";\n", i)).collect::<String>();
+      return template
+        .replace("{{syntax}}", self.syntax_line())
+        .replace("{{package}}", &format!("package {};", package))
+        .replace("{{imports}}", imports.trim_end())
+        .replace("{{body}}", body.trim_matches('\n'))
+    }
+    
+    let mut contents = self.file_header(package);
+    if self.options.emit_content_hash {
+      contents += &format!("// content-hash: {}\n", self.model_hash());
+    }
+    
+    if !self.options.prepend.is_empty() {
+      contents += "\n";
+      for line in self.options.prepend.iter().flat_map(|p| p.lines()) {
+        if line.trim_start().starts_with("//") {
+          contents += line;
+        } else {
+          contents += &format!("// {}", line);
+        }
+        contents += "\n";
+      }
+    }
+    
+    if !imports.is_empty() {
+      contents += "\n";
+      for import in &imports {
+        contents += &format!("import \"{}\";\n", import);
+      }
+    }
+    
+    contents + &body
+  }
+  
+  /// Типы модуля `module`, сервис и список пропущенных типов - всё, что идёт после импортов.
+  /// Сервис и список пропущенных типов выводятся только в основной файл.
+  fn generate_body(&self, module: &str) -> String {
+    let package = self.module_package(module);
+    let package = package.as_str();
+    let mut contents = String::new();
+    let mut parents = if self.options.nest_single_use { self.single_use_parents() } else { BTreeMap::default() };
+    // Типы не вкладываются в сообщения из других файлов
+    parents.retain(|child, parent| self.type_module(child) == self.type_module(parent));
+    for (type_name, r#type) in self.types.iter().filter(|(name, _)| self.type_module(name) == module) {
+      if !parents.contains_key(type_name) {
+        contents += "\n";
+        contents += &self.render_type(type_name, r#type, "", package, &parents);
+        contents += "\n";
+      }
+    }
+    if !module.is_empty() {
+      return contents
+    }
+    
+    for (service, rpcs) in self.services() {
+      contents += "\n";
+      contents += &format!("service {}{}", service, self.open_brace(""));
+      for rpc in rpcs {
+        for doc in &rpc.docs {
+          contents += format!("\n  // {}", doc).trim_end();
+        }
+        contents += "\n";
+        contents += &format!(
+          "  rpc {} ({}{}) returns ({}{})",
+          rpc.name,
+          if rpc.client_streaming { "stream " } else { "" },
+          self.type_reference(&rpc.request, package, &parents).unwrap_or(rpc.request.to_owned()),
+          if rpc.server_streaming { "stream " } else { "" },
+          self.type_reference(&rpc.response, package, &parents).unwrap_or(rpc.response.to_owned()),
+        );
+        if rpc.options.is_empty() {
+          contents += ";";
+        } else {
+          contents += &self.open_brace("  ");
+          for option in &rpc.options {
+            contents += "\n";
+            contents += &format!("    option {};", option);
+          }
+          contents += "\n  }";
+        }
+      }
+      contents += "\n}\n";
+    }
+    
+    if self.options.annotate_skipped && !self.skipped.is_empty() {
+      contents += "\n// Skipped types:\n";
+      for (name, reason) in &self.skipped {
+        contents += &format!("//   {}: {}\n", name, reason.lines().collect::<Vec<_>>().join(" "));
+      }
+    }
     
     contents
   }
+  
+  /// Дескрипторы (`FileDescriptorSet`) всех генерируемых файлов: основного, файлов модулей и общего файла вспомогательных типов.
+  ///
+  /// С `source_info` в каждый файл добавляется `SourceCodeInfo`: `span` типов, полей, вариантов и методов указывает
+  /// на их строки в сгенерированном `.proto`, а документация попадает в `leading_comments`. Места объявления
+  /// в исходниках Rust записываются отдельно, см. `source_map`. Из опций полей в дескриптор попадает только `deprecated`.
+  pub fn descriptor_set(&self, source_info: bool) -> Vec<u8> {
+    let mut set = Message::default();
+    let modules = std::iter::once("").chain(self.types.keys().map(|name| self.type_module(name))).collect::<BTreeSet<_>>();
+    for module in modules {
+      let mut parents = if self.options.nest_single_use { self.single_use_parents() } else { BTreeMap::default() };
+      parents.retain(|child, parent| self.type_module(child) == self.type_module(parent));
+      let types = self.types.iter().filter(|(name, _)| self.type_module(name) == module && !parents.contains_key(*name)).collect::<Vec<_>>();
+      let services = if module.is_empty() { self.services() } else { BTreeMap::default() };
+      let spans = if source_info { proto_spans(&self.generate_module(module)) } else { BTreeMap::default() };
+      let builder = FileDescriptorBuilder { parser: self, parents, source_info, spans, locations: Message::default() };
+      set.message(1, &builder.file(&self.module_file(module), &self.module_package(module), &types, &self.module_imports(module), &services));
+    }
+    if let Some(shared_file) = &self.options.shared_wrappers_file && !self.shared_types.is_empty() {
+      let imports = self.shared_types
+        .values()
+        .flat_map(|t| match t {
+          ProtobufEntityType::Message(fields) => fields.iter().flat_map(|f| self.types_parser.imports(&f.proto3_type)).collect(),
+          _ => vec![],
+        })
+        .collect::<BTreeSet<_>>();
+      let spans = match self.generate_shared_wrappers() {
+        Some(contents) if source_info => proto_spans(&contents),
+        _ => BTreeMap::default(),
+      };
+      let builder = FileDescriptorBuilder { parser: self, parents: BTreeMap::default(), source_info, spans, locations: Message::default() };
+      set.message(1, &builder.file(shared_file, &self.package_name(), &self.shared_types.iter().collect::<Vec<_>>(), &imports, &BTreeMap::default()));
+    }
+    set.into_bytes()
+  }
+  
+  /// Места объявления типов, полей и вариантов в исходниках Rust: JSON-объект `{"accounts.User.id": "src/lib.rs:6"}`.
+  /// Ключи - полные имена Protobuf; варианты перечисления записываются внутри перечисления: `accounts.Role.ROLE_ADMIN`.
+  pub fn source_map(&self) -> String {
+    let mut parents = if self.options.nest_single_use { self.single_use_parents() } else { BTreeMap::default() };
+    parents.retain(|child, parent| self.type_module(child) == self.type_module(parent));
+    let mut map = BTreeMap::new();
+    for (name, r#type) in &self.types {
+      let Some((file, line)) = self.type_locations.get(name) else { continue };
+      let full_name = format!("{}.{}", self.type_package(name), Parser::qualified_name(name, &parents));
+      let members = match r#type {
+        ProtobufEntityType::Message(fields) => fields.iter().map(|f| (&f.name, f.source_line)).collect::<Vec<_>>(),
+        ProtobufEntityType::Enum(variants) => variants.iter().map(|v| (&v.name, v.source_line)).collect(),
+      };
+      for (member, member_line) in members {
+        if let Some(member_line) = member_line {
+          map.insert(format!("{}.{}", full_name, member), format!("{}:{}", file, member_line));
+        }
+      }
+      map.insert(full_name, format!("{}:{}", file, line));
+    }
+    json!(map).to_string()
+  }
+}
+
+/// Места элементов в тексте сгенерированного файла для `span` в `SourceCodeInfo`: путь из имён (`["User", "id"]`)
+/// и `span` - строка, начальный и конечный столбцы (у блоков - ещё и строка конца), считая с нуля.
+fn proto_spans(contents: &str) -> BTreeMap<Vec<String>, Vec<i32>> {
+  let mut spans = BTreeMap::new();
+  // Открытые блоки: имя и начало; у `oneof` имени нет, его поля принадлежат сообщению
+  let mut blocks: Vec<Option<(String, i32, i32)>> = vec![];
+  let scope = |blocks: &[Option<(String, i32, i32)>]| blocks.iter().flatten().map(|(name, _, _)| name.to_owned()).collect::<Vec<_>>();
+  for (line, text) in contents.lines().enumerate() {
+    let line = line as i32;
+    let statement = text.split("//").next().unwrap_or_default().trim_end();
+    let trimmed = statement.trim_start();
+    let column = (statement.len() - trimmed.len()) as i32;
+    let mut words = trimmed.split_whitespace();
+    match words.next() {
+      None | Some("{") => {},
+      Some("}") => if let Some(Some((name, start_line, start_column))) = blocks.pop() {
+        let mut path = scope(&blocks);
+        path.push(name);
+        spans.insert(path, vec![start_line, start_column, line, column + 1]);
+      },
+      Some("oneof") => blocks.push(None),
+      Some("message" | "enum" | "service" | "rpc") => {
+        let name = words.next().unwrap_or_default().trim_end_matches('{').to_owned();
+        if trimmed.ends_with(';') {
+          let mut path = scope(&blocks);
+          path.push(name);
+          spans.insert(path, vec![line, column, statement.len() as i32]);
+        } else {
+          blocks.push(Some((name, line, column)));
+        }
+      },
+      Some("option" | "reserved") => {},
+      Some(_) if !blocks.is_empty() => if let Some((declaration, _)) = trimmed.split_once(" = ") {
+        let mut path = scope(&blocks);
+        path.push(declaration.split_whitespace().last().unwrap_or_default().to_owned());
+        spans.insert(path, vec![line, column, statement.len() as i32]);
+      },
+      Some(_) => {},
+    }
+  }
+  spans
+}
+
+/// Сборка `FileDescriptorProto` одного файла вместе с его `SourceCodeInfo`.
+struct FileDescriptorBuilder<'p, 'a> {
+  parser: &'p Parser<'a>,
+  /// Вложенные типы и их родители (`--nest-single-use`)
+  parents: BTreeMap<String, String>,
+  source_info: bool,
+  /// Места элементов в тексте файла, см. `proto_spans`
+  spans: BTreeMap<Vec<String>, Vec<i32>>,
+  /// `SourceCodeInfo`, заполняемый по мере обхода типов
+  locations: Message,
+}
+
+impl FileDescriptorBuilder<'_, '_> {
+  fn file(
+    mut self,
+    file_name: &str,
+    package: &str,
+    types: &[(&String, &ProtobufEntityType)],
+    dependencies: &BTreeSet<String>,
+    services: &BTreeMap<String, Vec<&ProtobufRpc>>,
+  ) -> Message {
+    let mut file = Message::default();
+    file.string(1, file_name).string(2, package);
+    for dependency in dependencies {
+      file.string(3, dependency);
+    }
+    let (mut messages, mut enums) = (0, 0);
+    for (name, r#type) in types {
+      match r#type {
+        ProtobufEntityType::Message(fields) => {
+          file.message(4, &self.message(name, fields, vec![4, messages]));
+          messages += 1;
+        },
+        ProtobufEntityType::Enum(variants) => {
+          file.message(5, &self.enumeration(name, variants, vec![5, enums]));
+          enums += 1;
+        },
+      }
+    }
+    for (i, (service, rpcs)) in services.iter().enumerate() {
+      let mut descriptor = Message::default();
+      descriptor.string(1, service);
+      for (k, rpc) in rpcs.iter().enumerate() {
+        let mut method = Message::default();
+        method.string(1, &rpc.name).string(2, &self.type_name(&rpc.request)).string(3, &self.type_name(&rpc.response));
+        if rpc.options.iter().any(|o| o == "idempotency_level = IDEMPOTENT") {
+          let mut options = Message::default();
+          options.varint(34, descriptor::IDEMPOTENT);
+          method.message(4, &options);
+        }
+        if rpc.client_streaming { method.bool(5, true); }
+        if rpc.server_streaming { method.bool(6, true); }
+        descriptor.message(2, &method);
+        let docs = rpc.docs.join("\n");
+        self.location(&[6, i as i32, 2, k as i32], &[service.to_owned(), rpc.name.to_owned()], Some(docs.as_str()).filter(|d| !d.is_empty()));
+      }
+      file.message(6, &descriptor);
+      self.location(&[6, i as i32], std::slice::from_ref(service), None);
+    }
+    if self.source_info {
+      file.message(9, &self.locations);
+    }
+    file.string(12, match self.parser.options.syntax {
+      Syntax::Proto3 => "proto3",
+      Syntax::Proto2 => "proto2",
+    });
+    file
+  }
+  
+  /// `DescriptorProto` сообщения вместе с вложенными в него типами и сообщениями `<Поле>Entry` для полей `map`.
+  fn message(&mut self, name: &str, fields: &[ProtobufField], path: Vec<i32>) -> Message {
+    let parser = self.parser;
+    let mut message = Message::default();
+    message.string(1, name);
+    let scope = self.scope(name);
+    self.location(&path, &scope, parser.type_docs.get(name).map(|d| d.as_str()));
+    
+    let mut nested = vec![];
+    let mut nested_enums = vec![];
+    let children = self.parents.iter().filter(|(_, parent)| *parent == name).map(|(child, _)| child.to_owned()).collect::<Vec<_>>();
+    for (child, r#type) in children.iter().filter_map(|child| parser.types.get(child).map(|t| (child, t))) {
+      match r#type {
+        ProtobufEntityType::Message(fields) => nested.push(self.message(child, fields, [&path[..], &[3, nested.len() as i32]].concat())),
+        ProtobufEntityType::Enum(variants) => nested_enums.push(self.enumeration(child, variants, [&path[..], &[4, nested_enums.len() as i32]].concat())),
+      }
+    }
+    
+    let mut oneofs = vec![];
+    for oneof in fields.iter().filter_map(|f| f.oneof.as_ref()) {
+      if !oneofs.contains(oneof) { oneofs.push(oneof.to_owned()); }
+    }
+    // Поля proto3 `optional` входят в синтетические `oneof` `_<поле>`, которые идут после настоящих
+    let mut synthetic_oneofs = vec![];
+    for (k, field) in fields.iter().enumerate() {
+      let mut descriptor = Message::default();
+      descriptor.string(1, &field.name).int32(3, field.field_num).string(10, &descriptor::json_name(&field.name));
+      let map = field.proto3_type.strip_prefix("map<").and_then(|t| t.strip_suffix('>')).and_then(|t| t.split_once(", "));
+      if let Some((key_type, value_type)) = map {
+        let entry = format!("{}Entry", TypesParser::to_pascal_case(&field.name));
+        let mut entry_message = Message::default();
+        entry_message.string(1, &entry);
+        for (number, (entry_field, entry_type)) in [("key", key_type), ("value", value_type)].into_iter().enumerate() {
+          let mut entry_descriptor = Message::default();
+          entry_descriptor.string(1, entry_field).int32(3, number as i32 + 1).varint(4, descriptor::LABEL_OPTIONAL).string(10, entry_field);
+          self.field_type(&mut entry_descriptor, entry_type);
+          entry_message.message(2, &entry_descriptor);
+        }
+        let mut options = Message::default();
+        options.bool(7, true);
+        entry_message.message(7, &options);
+        nested.push(entry_message);
+        descriptor.varint(4, descriptor::LABEL_REPEATED).varint(5, descriptor::TYPE_MESSAGE).string(6, &format!("{}.{}", self.type_name(name), entry));
+      } else {
+        let syntax2 = parser.options.syntax == Syntax::Proto2;
+        let (label, value_type) = match (field.proto3_type.strip_prefix("repeated "), field.proto3_type.strip_prefix("optional ")) {
+          (Some(value_type), _) => (descriptor::LABEL_REPEATED, value_type),
+          (_, Some(value_type)) => {
+            if !syntax2 && field.oneof.is_none() {
+              descriptor.bool(17, true).varint(9, (oneofs.len() + synthetic_oneofs.len()) as u64);
+              synthetic_oneofs.push(format!("_{}", field.name));
+            }
+            (descriptor::LABEL_OPTIONAL, value_type)
+          },
+          // Как и в тексте, в proto2 поля вне `oneof` без `Option` обязательны
+          _ if syntax2 && field.oneof.is_none() && !parser.types_parser.is_optional(&field.rust_type) => (descriptor::LABEL_REQUIRED, field.proto3_type.as_str()),
+          _ => (descriptor::LABEL_OPTIONAL, field.proto3_type.as_str()),
+        };
+        descriptor.varint(4, label);
+        self.field_type(&mut descriptor, value_type);
+      }
+      if let Some(index) = field.oneof.as_ref().and_then(|oneof| oneofs.iter().position(|o| o == oneof)) {
+        descriptor.varint(9, index as u64);
+      }
+      if field.deprecated || field.options.iter().any(|(k, v)| k == "deprecated" && v == "true") {
+        let mut options = Message::default();
+        options.bool(3, true);
+        descriptor.message(8, &options);
+      }
+      message.message(2, &descriptor);
+      self.location(&[&path[..], &[2, k as i32]].concat(), &[&scope[..], std::slice::from_ref(&field.name)].concat(), field.doc.as_deref());
+    }
+    
+    for nested in &nested {
+      message.message(3, nested);
+    }
+    for nested in &nested_enums {
+      message.message(4, nested);
+    }
+    for oneof in oneofs.iter().chain(&synthetic_oneofs) {
+      let mut descriptor = Message::default();
+      descriptor.string(1, oneof);
+      message.message(8, &descriptor);
+    }
+    if parser.type_options.get(name).is_some_and(|o| o.iter().any(|o| o == "deprecated = true")) {
+      let mut options = Message::default();
+      options.bool(3, true);
+      message.message(7, &options);
+    }
+    // Список уже проверен при разборе полей
+    if let Some(Ok(reserved)) = parser.reserved.get(name).map(|r| ReservedFields::parse(r)) {
+      for (from, to) in reserved.numbers {
+        // Конец диапазона в дескрипторе не включается
+        let mut range = Message::default();
+        range.int32(1, from).int32(2, to + 1);
+        message.message(9, &range);
+      }
+      for reserved_name in &reserved.names {
+        message.string(10, reserved_name);
+      }
+    }
+    message
+  }
+  
+  fn enumeration(&mut self, name: &str, variants: &[ProtobufEnumVariant], path: Vec<i32>) -> Message {
+    let parser = self.parser;
+    let mut r#enum = Message::default();
+    r#enum.string(1, name);
+    let scope = self.scope(name);
+    self.location(&path, &scope, parser.type_docs.get(name).map(|d| d.as_str()));
+    for (k, variant) in variants.iter().enumerate() {
+      let mut descriptor = Message::default();
+      descriptor.string(1, &variant.name).int32(2, variant.value);
+      if variant.deprecated {
+        let mut options = Message::default();
+        options.bool(1, true);
+        descriptor.message(3, &options);
+      }
+      r#enum.message(2, &descriptor);
+      self.location(&[&path[..], &[2, k as i32]].concat(), &[&scope[..], std::slice::from_ref(&variant.name)].concat(), variant.doc.as_deref());
+    }
+    let allow_alias = variants.iter().enumerate().any(|(i, v)| variants[..i].iter().any(|p| p.value == v.value));
+    let deprecated = parser.type_options.get(name).is_some_and(|o| o.iter().any(|o| o == "deprecated = true"));
+    if allow_alias || deprecated {
+      let mut options = Message::default();
+      if allow_alias { options.bool(2, true); }
+      if deprecated { options.bool(3, true); }
+      r#enum.message(3, &options);
+    }
+    r#enum
+  }
+  
+  /// Тип поля: номер скалярного типа или ссылка на сообщение или перечисление.
+  fn field_type(&self, descriptor: &mut Message, proto3_type: &str) {
+    match descriptor::scalar_type(proto3_type) {
+      Some(scalar) => descriptor.varint(5, scalar),
+      None => {
+        let parser = self.parser;
+        let is_enum = matches!(parser.types.get(proto3_type).or(parser.shared_types.get(proto3_type)), Some(ProtobufEntityType::Enum(_)));
+        descriptor.varint(5, if is_enum { descriptor::TYPE_ENUM } else { descriptor::TYPE_MESSAGE }).string(6, &self.type_name(proto3_type))
+      },
+    };
+  }
+  
+  /// Полное имя типа: `.billing.Invoice`, `.acme.Order.Status` для вложенного типа, `.google.protobuf.Timestamp`.
+  fn type_name(&self, name: &str) -> String {
+    let parser = self.parser;
+    if parser.types.contains_key(name) {
+      format!(".{}.{}", parser.type_package(name), Parser::qualified_name(name, &self.parents))
+    } else if parser.shared_types.contains_key(name) {
+      format!(".{}.{}", parser.package_name(), name)
+    } else if name.contains('.') {
+      format!(".{}", name.trim_start_matches('.'))
+    } else {
+      name.to_owned()
+    }
+  }
+  
+  /// Имена типа и его родителей для поиска в `spans`: `["Order", "Status"]` для вложенного типа.
+  fn scope(&self, name: &str) -> Vec<String> {
+    Parser::qualified_name(name, &self.parents).split('.').map(|n| n.to_owned()).collect()
+  }
+  
+  /// Добавляет в `SourceCodeInfo` место элемента по пути `path` с документацией. `scope` - имена элемента и его родителей
+  /// в тексте файла; элемент, который там не найден, пропускается: `span` обязателен.
+  fn location(&mut self, path: &[i32], scope: &[String], doc: Option<&str>) {
+    let Some(span) = self.spans.get(scope).filter(|_| self.source_info) else { return };
+    let mut location = Message::default();
+    location.packed(1, path).packed(2, span);
+    if let Some(doc) = doc {
+      location.string(3, &doc.lines().map(|l| format!("{}\n", format!(" {}", l).trim_end())).collect::<String>());
+    }
+    self.locations.message(1, &location);
+  }
+}
+
+
+#[cfg(test)]
+mod parser_tests {
+  use super::*;
+  use crate::options::OptionalBoolRepr;
+  use std::path::PathBuf;
+  
+  /// Собирает временный крейт из переданных файлов и возвращает путь к нему.
+  fn fixture_crate(name: &str, files: &[(&str, &str)]) -> String {
+    let root: PathBuf = std::env::temp_dir().join(format!("r2proto3_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    for (path, contents) in files {
+      let path = root.join(path);
+      std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+      std::fs::write(path, contents).unwrap();
+    }
+    root.to_string_lossy().into_owned()
+  }
+  
+  fn generate(name: &str, files: &[(&str, &str)], options: Options) -> String {
+    let root = fixture_crate(name, files);
+    let mut parser = Parser::new(&root, options).unwrap();
+    parser.parse().unwrap();
+    parser.generate()
+  }
+  
+  #[test]
+  fn idempotent_rpc_test() {
+    let proto = generate("idempotent_rpc", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct GetUserRequest {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+struct GetUserResponse {
+  name: String,
+}
+
+// NOTE: ToProtobuf
+// proto: idempotent
+pub fn get_user(req: GetUserRequest) -> GetUserResponse {
+  todo!()
+}
+"#)], Options::default());
+    assert!(proto.contains("  rpc GetUser (GetUserRequest) returns (GetUserResponse) {\n    option idempotency_level = IDEMPOTENT;\n  }"), "{}", proto);
+  }
+  
+  #[test]
+  fn streaming_directives_test() {
+    let proto = generate("streaming_directives", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Event {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+// proto: server_streaming
+fn watch(req: Event) -> EventStream<Event> {}
+
+// NOTE: ToProtobuf
+// proto: client_streaming
+fn upload(events: Incoming<Event>) -> Event {}
+
+// NOTE: ToProtobuf
+// proto: bidi
+fn chat(events: Incoming<Event>) -> EventStream<Event> {}
+"#)], Options::default());
+    assert!(proto.contains("  rpc Watch (Event) returns (stream Event);\n"), "{}", proto);
+    assert!(proto.contains("  rpc Upload (stream Event) returns (Event);\n"), "{}", proto);
+    assert!(proto.contains("  rpc Chat (stream Event) returns (stream Event);\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn rpc_result_return_test() {
+    let proto = generate("rpc_result_return", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Req {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+struct Resp {
+  ok: bool,
+}
+
+// NOTE: ToProtobuf
+fn f(r: Req) -> Result<Resp, Status> {}
+
+// NOTE: ToProtobuf
+fn get(&self, request: tonic::Request<Req>) -> Result<tonic::Response<Resp>, tonic::Status> {}
+"#)], Options::default());
+    assert!(proto.contains("  rpc F (Req) returns (Resp);\n"), "{}", proto);
+    assert!(proto.contains("  rpc Get (Req) returns (Resp);\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn services_test() {
+    let root = fixture_crate("services", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Req {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+fn watch(r: Req) -> Req {}
+
+// NOTE: ToProtobuf(service = "Admin")
+fn ban_user(r: Req) -> Req {}
+
+// NOTE: ToProtobuf
+fn archive(r: Req) -> Req {}
+
+// NOTE: ToProtobuf(service = "Admin")
+fn audit(r: Req) -> Req {}
+
+// NOTE: ToProtobuf(service = "not valid")
+fn broken(r: Req) -> Req {}
+"#)]);
+    let mut parser = Parser::new(&root, Options { service_name: Some("Shop".into()), quiet: true, ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    let proto = parser.generate();
+    assert!(proto.ends_with(concat!(
+      "service Admin {\n  rpc Audit (Req) returns (Req);\n  rpc BanUser (Req) returns (Req);\n}\n",
+      "\nservice Shop {\n  rpc Archive (Req) returns (Req);\n  rpc Watch (Req) returns (Req);\n}\n",
+    )), "{}", proto);
+    assert_eq!(parser.generation_result().services, 2);
+    assert_eq!(parser.skipped, vec![("broken".to_owned(), "`not valid` isn't a valid service name".to_owned())]);
+    
+    let error = Parser::new(&root, Options { service_name: Some("Bad-Name".into()), ..Default::default() }).err().unwrap();
+    assert_eq!(error.to_string(), "`Bad-Name` isn't a valid service name");
+  }
+  
+  #[test]
+  fn rpc_docs_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Req {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+/// Returns the user by id.
+///
+/// Fails with `NOT_FOUND` if there is no such user.
+fn get_user(r: Req) -> Req {}
+
+// NOTE: ToProtobuf
+fn ping(r: Req) -> Req {}
+"#)];
+    let proto = generate("rpc_docs", &files, Options { emit_rpc_docs: true, ..Default::default() });
+    assert!(proto.contains("{\n  // Returns the user by id.\n  //\n  // Fails with `NOT_FOUND` if there is no such user.\n  rpc GetUser (Req) returns (Req);\n  rpc Ping (Req) returns (Req);\n}"), "{}", proto);
+    
+    let proto = generate("rpc_docs_off", &files, Options::default());
+    assert!(!proto.contains("Returns the user"), "{}", proto);
+  }
+  
+  #[test]
+  fn duplicate_rpc_name_test() {
+    let parse = |name: &str, contents: &str| {
+      let root = fixture_crate(name, &[("src/lib.rs", contents)]);
+      let mut parser = Parser::new(&root, Options::default()).unwrap();
+      parser.parse().unwrap_err().to_string()
+    };
+    let error = parse("duplicate_rpc_name", "// NOTE: ToProtobuf\nstruct Req {\n  id: u64,\n}\n\n// NOTE: ToProtobuf\nfn get_user(r: Req) -> Req {}\n\n// NOTE: ToProtobuf\nfn getUser(r: Req) -> Req {}\n");
+    assert!(error.contains("the function `getUser` produces rpc method `GetUser`, which is already defined in the service"), "{}", error);
+    
+    let error = parse("duplicate_admin_rpc_name", "// NOTE: ToProtobuf\nstruct Req {\n  id: u64,\n}\n\n// NOTE: ToProtobuf(service = \"Admin\")\nfn delete(r: Req) -> Req {}\n\n// NOTE: ToProtobuf(service = \"Admin\")\nfn Delete(r: Req) -> Req {}\n");
+    assert!(error.contains("produces rpc method `Delete`, which is already defined in the service `Admin`"), "{}", error);
+  }
+  
+  #[test]
+  fn rpc_names_test() {
+    // Методы не пересекаются с типами, а одинаковые методы разных сервисов не мешают друг другу
+    let proto = generate("rpc_names", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Ping {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+fn ping(r: Ping) -> Ping {}
+
+// NOTE: ToProtobuf
+fn delete(r: Ping) -> Ping {}
+
+// NOTE: ToProtobuf(service = "Admin")
+fn delete(r: Ping) -> Ping {}
+"#)], Options { service_name: Some("Users".into()), ..Default::default() });
+    assert!(proto.contains("message Ping {\n  uint64 id = 1;\n}"), "{}", proto);
+    assert!(proto.contains("service Admin {\n  rpc Delete (Ping) returns (Ping);\n}"), "{}", proto);
+    assert!(proto.contains("service Users {\n  rpc Delete (Ping) returns (Ping);\n  rpc Ping (Ping) returns (Ping);\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn char_fields_test() {
+    let proto = generate("char_fields", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Keymap {
+  /// Key that opens the menu.
+  menu: char,
+  separators: Vec<char>,
+}
+
+// NOTE: ToProtobuf
+enum Command {
+  Key(char),
+  Quit,
+}
+"#)], Options::default());
+    assert!(proto.contains("message Keymap {\n  // Key that opens the menu.\n  // single character\n  string menu = 1;\n  // single character\n  repeated string separators = 2;\n}"), "{}", proto);
+    assert!(proto.contains("    // single character\n    string key = 1;\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn usize_fields_test() {
+    let files = [("src/lib.rs", "// NOTE: ToProtobuf\nstruct Selection {\n  indices: Vec<usize>,\n  offset: isize,\n}\n")];
+    let proto = generate("usize_fields", &files, Options::default());
+    assert!(proto.contains("  // platform-width integer, assumes a 64-bit target\n  repeated uint64 indices = 1;\n  // platform-width integer, assumes a 64-bit target\n  int64 offset = 2;\n"), "{}", proto);
+    let proto = generate("usize_fields_32", &files, Options { usize_as: UsizeWidth::W32, ..Default::default() });
+    assert!(proto.contains("  // platform-width integer, assumes a 32-bit target\n  repeated uint32 indices = 1;\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn auto_package_from_modules_test() {
+    let root = fixture_crate("auto_package_from_modules", &[
+      ("src/lib.rs", "mod billing;\n\n// NOTE: ToProtobuf\nstruct Customer {\n  last_invoice: Invoice,\n}\n\n// NOTE: ToProtobuf\nfn charge(c: Customer) -> billing::invoices::Invoice {}\n"),
+      ("src/billing/mod.rs", "pub mod invoices;\n\n// NOTE: ToProtobuf\nenum Currency {\n  Eur,\n  Usd,\n}\n"),
+      ("src/billing/invoices.rs", "// NOTE: ToProtobuf\nstruct Invoice {\n  currency: super::Currency,\n  lines: Vec<InvoiceLine>,\n}\n\n// NOTE: ToProtobuf\nstruct InvoiceLine {\n  at: chrono::DateTime<Utc>,\n}\n"),
+    ]);
+    let mut parser = Parser::new(&root, Options { auto_package_from_modules: true, package: Some("shop".into()), root_file: "shop.proto".into(), ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    
+    let proto = parser.generate();
+    assert!(proto.contains("package shop;\n\nimport \"billing/invoices.proto\";\n\nmessage Customer {\n  .billing.invoices.Invoice last_invoice = 1;\n}"), "{}", proto);
+    assert!(proto.contains("rpc Charge (Customer) returns (.billing.invoices.Invoice);"), "{}", proto);
+    assert!(!proto.contains("message Invoice"), "{}", proto);
+    
+    let packages = parser.generate_module_files();
+    assert_eq!(packages.keys().collect::<Vec<_>>(), vec!["billing.proto", "billing/invoices.proto"]);
+    assert!(packages["billing.proto"].contains("package billing;\n\nenum Currency {"), "{}", packages["billing.proto"]);
+    let invoices = &packages["billing/invoices.proto"];
+    assert!(invoices.contains("package billing.invoices;\n\nimport \"billing.proto\";\nimport \"google/protobuf/timestamp.proto\";\n"), "{}", invoices);
+    assert!(invoices.contains("message Invoice {\n  .billing.Currency currency = 1;\n  repeated InvoiceLine lines = 2;\n}"), "{}", invoices);
+    assert!(!invoices.contains("service"), "{}", invoices);
+  }
+  
+  #[test]
+  fn split_by_module_test() {
+    let root = fixture_crate("split_by_module", &[
+      ("src/lib.rs", "mod billing;\n\n// NOTE: ToProtobuf\nstruct Customer {\n  invoices: Vec<Invoice>,\n}\n\n// NOTE: ToProtobuf\nfn charge(c: Customer) -> billing::invoices::Invoice {}\n"),
+      ("src/billing/mod.rs", "pub mod invoices;\n\n// NOTE: ToProtobuf\nenum Currency {\n  Eur,\n  Usd,\n}\n"),
+      ("src/billing/invoices.rs", "// NOTE: ToProtobuf\nstruct Invoice {\n  currency: super::Currency,\n  totals: HashMap<String, Money>,\n}\n\n// NOTE: ToProtobuf\nstruct Money {\n  cents: u64,\n}\n"),
+    ]);
+    let mut parser = Parser::new(&root, Options { split_by_module: true, package: Some("shop".into()), root_file: "shop.proto".into(), ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    assert!(parser.warnings().is_empty(), "{:?}", parser.warnings());
+    
+    let proto = parser.generate();
+    assert!(proto.contains("package shop;\n\nimport \"billing/invoices.proto\";\n\nmessage Customer {\n  repeated Invoice invoices = 1;\n}"), "{}", proto);
+    assert!(proto.contains("rpc Charge (Customer) returns (Invoice);"), "{}", proto);
+    assert!(!proto.contains("message Invoice"), "{}", proto);
+    
+    let files = parser.generate_module_files();
+    assert_eq!(files.keys().collect::<Vec<_>>(), vec!["billing.proto", "billing/invoices.proto"]);
+    assert!(files["billing.proto"].contains("package shop;\n\nenum Currency {"), "{}", files["billing.proto"]);
+    let invoices = &files["billing/invoices.proto"];
+    assert!(invoices.contains("package shop;\n\nimport \"billing.proto\";\n\nmessage Invoice {\n  Currency currency = 1;\n  map<string, Money> totals = 2;\n}"), "{}", invoices);
+    
+    // Файлы, ссылающиеся друг на друга, `protoc` не соберёт
+    let root = fixture_crate("split_by_module_cycle", &[
+      ("src/lib.rs", "mod a;\nmod b;\n"),
+      ("src/a.rs", "// NOTE: ToProtobuf\nstruct A {\n  b: Option<B>,\n}\n"),
+      ("src/b.rs", "// NOTE: ToProtobuf\nstruct B {\n  a: Option<Box<A>>,\n}\n"),
+    ]);
+    let mut parser = Parser::new(&root, Options { split_by_module: true, quiet: true, ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    assert_eq!(parser.warnings(), vec!["generated files import each other, which protoc rejects: a.proto -> b.proto -> a.proto".to_owned()]);
+  }
+  
+  #[test]
+  fn skipped_paths_test() {
+    let annotated = |name: &str| format!("// NOTE: ToProtobuf\nstruct {} {{\n  id: u64,\n}}\n", name);
+    let files = [
+      ("Cargo.toml", "[package]\nname = \"app\"\n".to_owned()),
+      (".gitignore", "/generated\n*.bak.rs\n".to_owned()),
+      ("src/lib.rs", annotated("Kept")),
+      ("src/target/mod.rs", annotated("ModuleNamedTarget")),
+      ("target/debug/build/out.rs", annotated("BuildOutput")),
+      ("generated/types.rs", annotated("Ignored")),
+      ("src/old.bak.rs", annotated("Backup")),
+      ("vendor/dep/src/lib.rs", annotated("Vendored")),
+      ("tests/fixtures/input.rs", annotated("Fixture")),
+    ];
+    let files = files.iter().map(|(p, c)| (*p, c.as_str())).collect::<Vec<_>>();
+    let options = || Options { exclude: vec!["vendor".into(), "tests/fixtures".into()], ..Default::default() };
+    
+    let proto = generate("skipped_paths", &files, options());
+    assert!(proto.contains("message Kept {") && proto.contains("message ModuleNamedTarget {"), "{}", proto);
+    for skipped in ["BuildOutput", "Ignored", "Backup", "Vendored", "Fixture"] {
+      assert!(!proto.contains(&format!("message {} {{", skipped)), "{}", proto);
+    }
+    
+    let proto = generate("skipped_paths_no_gitignore", &files, Options { respect_gitignore: false, ..options() });
+    assert!(proto.contains("message Ignored {") && proto.contains("message Backup {"), "{}", proto);
+    assert!(!proto.contains("message BuildOutput {") && !proto.contains("message Vendored {"), "{}", proto);
+  }
+  
+  #[test]
+  fn parallel_scan_test() {
+    let mut files = (0..40)
+      .map(|i| (format!("src/m{}.rs", i), format!("// NOTE: ToProtobuf\nstruct M{} {{\n  next: Option<Box<M{}>>,\n}}\n\npub type Id{} = u{};\n", i, (i + 1) % 40, i, if i % 2 == 0 { 32 } else { 64 })))
+      .collect::<Vec<_>>();
+    files.push(("src/lib.rs".into(), "#[path = \"../extra/shared.rs\"]\nmod shared;\n\n// NOTE: ToProtobuf\nstruct Root {\n  ids: Vec<Id7>,\n  shared: Shared,\n}\n".into()));
+    files.push(("extra/shared.rs".into(), "// NOTE: ToProtobuf\nstruct Shared {\n  id: Id8,\n}\n".into()));
+    let files = files.iter().map(|(p, c)| (p.as_str(), c.as_str())).collect::<Vec<_>>();
+    
+    let parallel = generate("parallel_scan", &files, Options::default());
+    let sequential = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap().install(|| generate("parallel_scan", &files, Options::default()));
+    assert_eq!(parallel, sequential);
+    assert!(parallel.contains("message Root {\n  repeated uint64 ids = 1;\n  Shared shared = 2;\n}"), "{}", parallel);
+    assert!(parallel.contains("message Shared {\n  uint32 id = 1;\n}"), "{}", parallel);
+    assert_eq!(parallel.matches("message M").count(), 40);
+  }
+  
+  #[test]
+  fn type_aliases_test() {
+    let proto = generate("type_aliases", &[
+      ("src/lib.rs", "mod ids;\n\n// NOTE: ToProtobuf\nstruct User {\n  id: UserId,\n  manager: Option<ids::ManagerId>,\n  teams: TeamIds,\n}\n"),
+      ("src/ids.rs", "pub type UserId = u64;\npub(crate) type ManagerId = UserId;\ntype TeamIds = Vec<u32>;\n\nimpl Iterator for Ids {\n  type Item = String;\n}\n"),
+    ], Options::default());
+    assert!(proto.contains("message User {\n  uint64 id = 1;\n  optional uint64 manager = 2;\n  repeated uint32 teams = 3;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn tuple_alias_message_test() {
+    let proto = generate("tuple_alias_message", &[("src/lib.rs", r#"
+pub type Coord = (f64, f64);
+
+// NOTE: ToProtobuf
+struct Route {
+  start: Coord,
+  stops: Vec<Coord>,
+}
+"#)], Options::default());
+    assert!(proto.contains("message Coord {\n  double anonymous_value_1 = 1;\n  double anonymous_value_2 = 2;\n}"), "{}", proto);
+    assert!(proto.contains("message Route {\n  Coord start = 1;\n  repeated Coord stops = 2;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn generic_instantiations_test() {
+    let root = fixture_crate("generic_instantiations", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf<User, crate::Order>
+/// A page of results.
+struct Page<T> {
+  items: Vec<T>, // T values
+  total: u64,
+}
+
+// NOTE: ToProtobuf
+struct User {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+struct Order {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+struct Listing {
+  users: Page<User>,
+  orders: Page<Order>,
+  more_orders: crate::Page<crate::Order>,
+}
+
+// NOTE: ToProtobuf<(String, u32), String>
+struct Pair<K, V> {
+  /// Keyed by K.
+  key: K,
+  #[serde(rename = "V")]
+  value: V,
+}
+
+// NOTE: ToProtobuf
+struct Bare<T> {
+  value: T,
+}
+"#)]);
+    let mut parser = Parser::new(&root, Options::default()).unwrap();
+    parser.parse().unwrap();
+    let proto = parser.generate();
+    assert!(proto.contains("// A page of results.\nmessage PageUser {\n  repeated User items = 1;\n  uint64 total = 2;\n}"), "{}", proto);
+    assert!(proto.contains("message PageOrder {\n  repeated Order items = 1;"), "{}", proto);
+    assert!(proto.contains("message Listing {\n  PageUser users = 1;\n  PageOrder orders = 2;\n  PageOrder more_orders = 3;\n}"), "{}", proto);
+    // Параметры подставляются только в типы полей, но не в документацию и атрибуты
+    assert!(proto.contains("message PairStringU32 {\n  // Keyed by K.\n  string key = 1;\n  uint32 V = 2;\n}"), "{}", proto);
+    assert!(!proto.contains("Bare") && !proto.contains("message Page {"), "{}", proto);
+    assert!(parser.skipped.contains(&("Bare".to_owned(), "generic type without instantiations".to_owned())));
+    let warnings = parser.warnings();
+    assert!(warnings.iter().any(|w| w.contains("generic type `Bare<T>`")), "{:?}", warnings);
+    assert!(warnings.iter().any(|w| w.contains("instantiation `String` of `Pair<K, V>`")), "{:?}", warnings);
+  }
+  
+  #[test]
+  fn unique_names_test() {
+    let root = fixture_crate("unique_names", &[
+      ("src/lib.rs", "mod status;\n\n// NOTE: ToProtobuf\nstruct Status {\n  code: u32,\n}\n"),
+      ("src/status.rs", "// NOTE: ToProtobuf\nenum Status {\n  Active,\n  Blocked,\n}\n"),
+    ]);
+    let mut parser = Parser::new(&root, Options { package: Some("acme.users".into()), ..Default::default() }).unwrap();
+    let error = parser.parse().unwrap_err().to_string();
+    assert!(error.contains("`acme.users.Status` is defined more than once: "), "{}", error);
+    assert!(error.contains("struct in `src/lib.rs`") && error.contains("enum in `src/status.rs`"), "{}", error);
+    
+    // Одноимённые типы в разных пакетах не конфликтуют
+    let generated = ["acme.users", "acme.billing"].map(|package| {
+      let root = fixture_crate(&format!("unique_names_{}", package.replace('.', "_")), &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct Status {\n  code: u32,\n}\n")]);
+      let mut parser = Parser::new(&root, Options { package: Some(package.into()), ..Default::default() }).unwrap();
+      parser.parse().unwrap();
+      parser.generate()
+    });
+    assert!(generated[0].contains("package acme.users;") && generated[0].contains("message Status {"));
+    assert!(generated[1].contains("package acme.billing;") && generated[1].contains("message Status {"));
+    
+    let root = fixture_crate("unique_names_service", &[]);
+    let service = Parser::new(&root, Options::default()).unwrap().service_name();
+    std::fs::create_dir_all(Path::new(&root).join("src")).unwrap();
+    std::fs::write(
+      Path::new(&root).join("src/lib.rs"),
+      format!("// NOTE: ToProtobuf\nstruct {} {{\n  id: u64,\n}}\n\n// NOTE: ToProtobuf\nfn ping(r: {0}) -> {0} {{}}\n", service),
+    ).unwrap();
+    let error = Parser::new(&root, Options::default()).unwrap().parse().unwrap_err().to_string();
+    assert!(error.contains(&format!(".{}` generated from the crate's functions clashes with struct in `src/lib.rs`", service)), "{}", error);
+  }
+  
+  #[test]
+  fn rpc_unknown_types_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Req {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+fn known(r: crate::Req) -> Req {}
+
+// NOTE: ToProtobuf
+fn unknown(r: Req) -> Resp {}
+"#)];
+    let root = fixture_crate("rpc_unknown_types", &files);
+    let mut parser = Parser::new(&root, Options { quiet: true, ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    let proto = parser.generate();
+    assert!(proto.contains("  rpc Known (Req) returns (Req);\n}"), "{}", proto);
+    assert_eq!(parser.skipped, vec![("unknown".to_owned(), "response type `Resp` isn't an annotated message".to_owned())]);
+    
+    let mut parser = Parser::new(&root, Options { panic_to_unsupported: true, ..Default::default() }).unwrap();
+    assert!(parser.parse().is_err());
+  }
+  
+  #[test]
+  fn ignore_rpc_test() {
+    let proto = generate("ignore_rpc", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct Req {\n  id: u64,\n}\n\n// NOTE: ToProtobuf\nfn get(r: Req) -> Req {}\n")], Options { ignore_rpc: true, ..Default::default() });
+    assert!(proto.contains("message Req {"), "{}", proto);
+    assert!(!proto.contains("service"), "{}", proto);
+  }
+  
+  #[test]
+  fn test_functions_skipped_test() {
+    let proto = generate("test_functions_skipped", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Req {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+fn get(r: Req) -> Req {}
+
+// NOTE: ToProtobuf
+#[test]
+fn get_test(r: Req) -> Req {}
+
+// NOTE: ToProtobuf
+#[tokio::test(flavor = "multi_thread")]
+async fn get_async_test(r: Req) -> Req {}
+
+// NOTE: ToProtobuf
+#[cfg(test)]
+fn fixture(r: Req) -> Req {}
+"#)], Options::default());
+    assert!(proto.contains("service"), "{}", proto);
+    assert!(proto.contains("  rpc Get (Req) returns (Req);\n}"), "{}", proto);
+    assert!(!proto.contains("GetTest") && !proto.contains("GetAsyncTest") && !proto.contains("Fixture"), "{}", proto);
+  }
+  
+  #[test]
+  fn async_rpc_test() {
+    let proto = generate("async_rpc", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Req {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+struct Resp {
+  ok: bool,
+}
+
+// NOTE: ToProtobuf
+pub async fn get_user(r: Req) -> Result<Resp, Status> {}
+
+// NOTE: ToProtobuf
+async fn ping(&self, r: Req) -> Resp {}
+
+// NOTE: ToProtobuf
+fn delete_user(r: Req) -> impl std::future::Future<Output = Result<tonic::Response<Resp>, Status>> + Send {}
+"#)], Options::default());
+    assert!(proto.contains("  rpc DeleteUser (Req) returns (Resp);\n  rpc GetUser (Req) returns (Resp);\n  rpc Ping (Req) returns (Resp);\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn phantom_data_skipped_test() {
+    let proto = generate("phantom_data", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct UserId {
+  _marker: PhantomData<User>,
+  id: u64,
+  _other: std::marker::PhantomData<Order>,
+  tag: String,
+}
+"#)], Options::default());
+    assert!(proto.contains("message UserId {\n  uint64 id = 1;\n  string tag = 2;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn well_known_imports_test() {
+    let proto = generate("well_known_imports", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Event {
+  at: jiff::Timestamp,
+  seen: Vec<jiff::Zoned>,
+  took: jiff::Span,
+}
+"#)], Options { package: Some("events".into()), ..Default::default() });
+    assert!(proto.starts_with("syntax = \"proto3\";\npackage events;\n\nimport \"google/protobuf/duration.proto\";\nimport \"google/protobuf/timestamp.proto\";\n\nmessage Event {"), "{}", proto);
+    assert!(proto.contains("  google.protobuf.Timestamp at = 1;\n  repeated google.protobuf.Timestamp seen = 2;\n  google.protobuf.Duration took = 3;"), "{}", proto);
+  }
+  
+  #[test]
+  fn user_duration_test() {
+    let proto = generate("user_duration", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Duration {
+  days: u32,
+}
+
+// NOTE: ToProtobuf
+struct Trip {
+  length: Duration,
+  timeout: std::time::Duration,
+}
+"#)], Options::default());
+    assert!(proto.contains("  Duration length = 1;\n  google.protobuf.Duration timeout = 2;\n"), "{}", proto);
+    assert!(proto.contains("import \"google/protobuf/duration.proto\";\n"), "{}", proto);
+    
+    let proto = generate("user_duration_only", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Duration {
+  days: u32,
+}
+
+// NOTE: ToProtobuf
+struct Trip {
+  length: Duration,
+}
+"#)], Options::default());
+    assert!(proto.contains("message Trip {\n  Duration length = 1;\n}"), "{}", proto);
+    assert!(!proto.contains("google/protobuf"), "{}", proto);
+  }
+  
+  #[test]
+  fn syntax_tree_items_test() {
+    let root = fixture_crate("syntax_tree_items", &[
+      ("src/lib.rs", r#"
+// NOTE: ToProtobuf
+#[cfg_attr(feature = "serde", serde(bound(serialize = "[[u8; 2]; 2]: Serialize")))]
+pub struct Index where Self: Sized {
+  by_name: HashMap<
+    String,
+    u64,
+  >,
+}
+
+fn fixture() -> &'static str {
+  "// NOTE: ToProtobuf
+struct Fake {
+  a: u8,
+}
+"
+}
+"#),
+    ]);
+    let mut parser = Parser::new(&root, Options::default()).unwrap();
+    parser.parse().unwrap();
+    let proto = parser.generate();
+    assert!(proto.contains("message Index {\n  map<string, uint64> by_name = 1;\n}"), "{}", proto);
+    assert!(!proto.contains("Fake"), "{}", proto);
+
+    let root = fixture_crate("syntax_tree_items_broken", &[
+      ("src/lib.rs", "// NOTE: ToProtobuf\nstruct Broken {\n  id: u64,\n}\n\nfn oops( {\n"),
+    ]);
+    let mut parser = Parser::new(&root, Options::default()).unwrap();
+    let err = parser.parse().unwrap_err().to_string();
+    assert!(err.contains("can't parse") && err.contains("lib.rs"), "{}", err);
+  }
+  
+  #[test]
+  fn skip_doc_hidden_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+#[doc(hidden)]
+pub struct Internal {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+#[derive(Debug)]
+#[doc(hidden)]
+pub enum InternalKind {
+  A,
+}
+
+// NOTE: ToProtobuf
+/// Public.
+pub struct Public {
+  id: u64,
+}
+"#)];
+    let proto = generate("skip_doc_hidden_off", &files, Options::default());
+    assert!(proto.contains("message Internal {") && proto.contains("enum InternalKind {") && proto.contains("message Public {"), "{}", proto);
+    
+    let root = fixture_crate("skip_doc_hidden_on", &files);
+    let mut parser = Parser::new(&root, Options { skip_doc_hidden: true, ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    let proto = parser.generate();
+    assert!(!proto.contains("Internal") && proto.contains("message Public {"), "{}", proto);
+    assert_eq!(parser.skipped.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), vec!["Internal", "InternalKind"]);
+  }
+  
+  #[test]
+  fn annotate_nullability_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Foo {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+struct Bar {
+  foo: Option<Foo>,
+  plain: Foo,
+  #[proto(example = "7")]
+  count: std::option::Option<u32>,
+  tags: Option<Vec<String>>,
+}
+"#)];
+    let proto = generate("annotate_nullability", &files, Options { annotate_nullability: true, emit_examples: true, ..Default::default() });
+    assert!(proto.contains("  optional Foo foo = 1; // nullable\n  Foo plain = 2; // non-null\n  optional uint32 count = 3; // nullable, example: 7\n  repeated string tags = 4; // nullable\n"), "{}", proto);
+    assert!(proto.contains("  uint64 id = 1; // non-null\n"), "{}", proto);
+    
+    let proto = generate("annotate_nullability_off", &files, Options::default());
+    assert!(!proto.contains("nullable") && !proto.contains("non-null"), "{}", proto);
+  }
+  
+  #[test]
+  fn serde_rename_test() {
+    let proto = generate("serde_rename", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct User {
+  user_id: u64,
+  #[serde(rename = "mail")]
+  email_address: String,
+  display_name: Option<String>,
+}
+
+// NOTE: ToProtobuf
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct Env {
+  log_level: String,
+}
+
+// NOTE: ToProtobuf
+struct Plain {
+  #[serde(rename = "kind")]
+  r#type: String,
+}
+"#)], Options::default());
+    assert!(proto.contains("message User {\n  uint64 userId = 1;\n  string mail = 2;\n  optional string displayName = 3;\n}"), "{}", proto);
+    assert!(proto.contains("message Env {\n  string LOG_LEVEL = 1;\n}"), "{}", proto);
+    assert!(proto.contains("message Plain {\n  string kind = 1;\n}"), "{}", proto);
+    
+    let parse = |name: &str, contents: &str| {
+      let root = fixture_crate(name, &[("src/lib.rs", contents)]);
+      let mut parser = Parser::new(&root, Options { panic_to_unsupported: true, ..Default::default() }).unwrap();
+      parser.parse().unwrap_err()
+    };
+    let error = parse("serde_rename_kebab", "// NOTE: ToProtobuf\n#[serde(rename_all = \"kebab-case\")]\nstruct Kebab {\n  user_id: u64,\n}\n");
+    assert!(error.to_string().contains("kebab-case"), "{}", error);
+    let error = parse("serde_rename_clash", "// NOTE: ToProtobuf\nstruct Clash {\n  a: u64,\n  #[serde(rename = \"a\")]\n  b: u64,\n}\n");
+    assert!(error.to_string().contains("field name `a` is used twice"), "{}", error);
+  }
+  
+  #[test]
+  fn normalize_names_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+#[allow(non_snake_case)]
+struct Server {
+  HTTPServer: String,
+  userId: u64, // field = 5
+  port: u32,
+}
+"#)];
+    let proto = generate("normalize_names", &files, Options { normalize_names: true, ..Default::default() });
+    assert!(proto.contains("message Server {\n  string http_server = 1;\n  uint64 user_id = 5;\n  uint32 port = 2;\n}"), "{}", proto);
+    
+    let proto = generate("normalize_names_off", &files, Options::default());
+    assert!(proto.contains("  string HTTPServer = 1;\n  uint64 userId = 5;\n"), "{}", proto);
+    
+    let root = fixture_crate("normalize_names_clash", &[("src/lib.rs", "// NOTE: ToProtobuf\n#[allow(non_snake_case)]\nstruct Clash {\n  userId: u64,\n  user_id: u64,\n}\n")]);
+    let mut parser = Parser::new(&root, Options { normalize_names: true, panic_to_unsupported: true, ..Default::default() }).unwrap();
+    assert!(parser.parse().unwrap_err().to_string().contains("field name `user_id` is used twice"));
+  }
+  
+  #[test]
+  fn fixed_arrays_as_message_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Mat4([f32; 16]);
+
+// NOTE: ToProtobuf
+struct Transform {
+  matrix: Mat4,
+  raw: [f32; 16],
+  hash: [u8; 32],
+}
+"#)];
+    let proto = generate("fixed_arrays_as_message", &files, Options { fixed_arrays_as_message: true, ..Default::default() });
+    assert!(proto.contains("message Mat4 {\n  // fixed length: 16\n  repeated float values = 1;\n}"), "{}", proto);
+    assert!(proto.contains("message FloatArray16 {\n  // fixed length: 16\n  repeated float values = 1;\n}"), "{}", proto);
+    assert!(proto.contains("message Transform {\n  Mat4 matrix = 1;\n  FloatArray16 raw = 2;\n  bytes hash = 3;\n}"), "{}", proto);
+    
+    let proto = generate("fixed_arrays_as_message_off", &files, Options::default());
+    assert!(proto.contains("message Mat4 {\n  repeated float anonymous_value_1 = 1;\n}"), "{}", proto);
+    assert!(proto.contains("  repeated float raw = 2;\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn proto2_syntax_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+enum Status {
+  Active = 1,
+  Banned = 2,
+}
+
+// NOTE: ToProtobuf
+struct Profile {
+  bio: String,
+}
+
+// NOTE: ToProtobuf
+struct User {
+  id: u64,
+  nick: Option<String>,
+  status: Status,
+  profile: Option<Profile>,
+  tags: Vec<String>,
+  attrs: HashMap<String, u32>,
+}
+"#)];
+    let proto = generate("proto2_syntax", &files, Options { syntax: Syntax::Proto2, ..Default::default() });
+    assert!(proto.starts_with("syntax = \"proto2\";\n"), "{}", proto);
+    assert!(proto.contains(concat!(
+      "message User {\n",
+      "  required uint64 id = 1;\n",
+      "  optional string nick = 2;\n",
+      "  required Status status = 3;\n",
+      "  optional Profile profile = 4;\n",
+      "  repeated string tags = 5;\n",
+      "  map<string, uint32> attrs = 6;\n",
+      "}",
+    )), "{}", proto);
+    assert!(proto.contains("enum Status {\n  Active = 1;\n  Banned = 2;\n}"), "{}", proto);
+    
+    let proto = generate("proto3_syntax", &files, Options::default());
+    assert!(proto.starts_with("syntax = \"proto3\";\n") && proto.contains("  uint64 id = 1;\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn duplicate_field_numbers_test() {
+    let field = |name: &str, field_num| ProtobufField {
+      name: name.into(),
+      rust_type: "u32".into(),
+      proto3_type: "uint32".into(),
+      field_num,
+      example: None,
+      options: vec![],
+      todos: vec![],
+      oneof: None,
+      doc: None,
+      deprecated: false,
+      source_line: None,
+    };
+    assert_eq!(Parser::check_field_numbers(&[field("a", 1), field("b", 2)]), Ok(()));
+    assert_eq!(
+      Parser::check_field_numbers(&[field("a", 1), field("b", 2), field("c", 1)]).unwrap_err().to_string(),
+      "fields `a` and `c` share field number 1",
+    );
+  }
+  
+  #[test]
+  fn inline_field_numbers_test() {
+    let proto = generate("inline_field_numbers", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Order {
+  id: u64, // = 7
+  #[proto(tag = 3)]
+  total: u32,
+  note: String,
+}
+"#)], Options::default());
+    assert!(proto.contains("message Order {\n  uint64 id = 7;\n  uint32 total = 3;\n  string note = 1;\n}"), "{}", proto);
+    
+    let root = fixture_crate("inline_field_numbers_clash", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct Clash {\n  a: u64, // = 2\n  #[proto(tag = 2)]\n  b: u64,\n}\n")]);
+    let mut parser = Parser::new(&root, Options { panic_to_unsupported: true, ..Default::default() }).unwrap();
+    assert!(parser.parse().unwrap_err().to_string().contains("field number 2 is claimed twice: `a: u64` and `b: u64`"));
+    
+    let root = fixture_crate("inline_field_numbers_invalid", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct Invalid {\n  a: u64, // = 19500\n}\n")]);
+    let mut parser = Parser::new(&root, Options { panic_to_unsupported: true, ..Default::default() }).unwrap();
+    assert!(parser.parse().unwrap_err().to_string().contains("`19500` isn't a valid field number"));
+  }
+  
+  #[test]
+  fn reserved_fields_test() {
+    let proto = generate("reserved_fields", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+// reserved: 3, 5-7, "old_name", "legacy"
+struct Order {
+  id: u64,
+  a: u32,
+  b: u32,
+  c: u32,
+}
+"#)], Options::default());
+    assert!(proto.contains(concat!(
+      "message Order {\n",
+      "  reserved 3, 5 to 7;\n",
+      "  reserved \"old_name\", \"legacy\";\n",
+      "  uint64 id = 1;\n",
+      "  uint32 a = 2;\n",
+      "  uint32 b = 4;\n",
+      "  uint32 c = 8;\n",
+      "}",
+    )), "{}", proto);
+    
+    assert_eq!(ReservedFields::parse("2, 10 to 12"), Ok(ReservedFields { numbers: vec![(2, 2), (10, 12)], names: vec![] }));
+    assert!(ReservedFields::parse("7-5").is_err());
+    assert!(ReservedFields::parse("x").is_err());
+    
+    let parse = |name: &str, contents: &str| {
+      let root = fixture_crate(name, &[("src/lib.rs", contents)]);
+      let mut parser = Parser::new(&root, Options { panic_to_unsupported: true, ..Default::default() }).unwrap();
+      parser.parse().unwrap_err().to_string()
+    };
+    let error = parse("reserved_number_reused", "// NOTE: ToProtobuf\n// reserved: 2\nstruct A {\n  a: u64, // = 2\n}\n");
+    assert!(error.contains("field number 2 is reserved"), "{}", error);
+    let error = parse("reserved_name_reused", "// NOTE: ToProtobuf\n// reserved: \"old\"\nstruct A {\n  old: u64,\n}\n");
+    assert!(error.contains("field name `old` is reserved"), "{}", error);
+  }
+  
+  #[test]
+  fn recursive_boxed_types_test() {
+    let proto = generate("recursive_boxed_types", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Node {
+  value: i64,
+  left: Option<Box<Node>>,
+  children: Box<Vec<Node>>,
+  parent: Option<std::rc::Rc<Node>>,
+  shared: Vec<std::sync::Arc<Node>>,
+}
+"#)], Options::default());
+    assert!(proto.contains("message Node {\n  int64 value = 1;\n  optional Node left = 2;\n  repeated Node children = 3;\n  optional Node parent = 4;\n  repeated Node shared = 5;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn deprecated_test() {
+    let proto = generate("deprecated", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct User {
+  id: u64,
+  #[deprecated(note = "use `email`")]
+  login: String,
+  /// @proto json_name=mail
+  email: String, // deprecated: moving to `contacts`
+}
+
+// NOTE: ToProtobuf
+enum Role {
+  Admin,
+  // deprecated
+  Moderator,
+  #[deprecated]
+  Guest = 5,
+}
+"#)], Options::default());
+    assert!(proto.contains("message User {\n  uint64 id = 1;\n  string login = 2 [deprecated = true];\n  string email = 3 [json_name = \"mail\", deprecated = true];\n}"), "{}", proto);
+    assert!(proto.contains("enum Role {\n  Admin = 0;\n  Moderator = 1 [deprecated = true];\n  Guest = 5 [deprecated = true];\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn template_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Event {
+  at: chrono::DateTime<chrono::Utc>,
+}
+"#)];
+    let template = "// Generated, do not edit.\n{{syntax}}\n\n{{imports}}\n\n{{package}}\noption go_package = \"acme/events\";\n\n{{body}}\n";
+    let proto = generate("template", &files, Options { template: Some(template.into()), package: Some("acme.events".into()), ..Default::default() });
+    assert_eq!(proto, r#"// Generated, do not edit.
+syntax = "proto3";
+
+import "google/protobuf/timestamp.proto";
+
+package acme.events;
+option go_package = "acme/events";
+
+message Event {
+  google.protobuf.Timestamp at = 1;
+}
+"#);
+  }
+  
+  #[test]
+  fn cfg_attr_serde_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+#[cfg_attr(feature = "serde", serde(rename_all = "camelCase"))]
+struct User {
+  user_id: u64,
+  #[cfg_attr(feature = "serde", serde(rename = "mail"))]
+  email: String,
+  #[cfg_attr(feature = "json", serde(rename = "ignored"))]
+  nick: String,
+}
+"#)];
+    let proto = generate("cfg_attr_serde", &files, Options { cfg: vec![r#"feature="serde""#.into()], ..Default::default() });
+    assert!(proto.contains("message User {\n  uint64 userId = 1;\n  string mail = 2;\n  string nick = 3;\n}"), "{}", proto);
+    
+    let proto = generate("cfg_attr_serde_inactive", &files, Options::default());
+    assert!(proto.contains("message User {\n  uint64 user_id = 1;\n  string email = 2;\n  string nick = 3;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn fixed_size_arrays_test() {
+    let proto = generate("fixed_size_arrays", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct Sealed {\n  hash: [u8; 32],\n  nonce: [u8; 16],\n  scale: [f32; 3],\n}\n\n// NOTE: ToProtobuf\nstruct Digest([u8; 32]);\n")], Options::default());
+    assert!(proto.contains("message Sealed {\n  bytes hash = 1;\n  bytes nonce = 2;\n  repeated float scale = 3;\n}"), "{}", proto);
+    assert!(proto.contains("message Digest {\n  bytes anonymous_value_1 = 1;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn arrays_of_messages_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Order {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+struct Basket {
+  recent: [Order; 5],
+  pinned: [crate::Order; 2],
+}
+"#)];
+    let proto = generate("arrays_of_messages", &files, Options::default());
+    assert!(proto.contains("message Basket {\n  repeated Order recent = 1;\n  repeated Order pinned = 2;\n}"), "{}", proto);
+    
+    let proto = generate("arrays_of_messages_as_message", &files, Options { fixed_arrays_as_message: true, ..Default::default() });
+    assert!(proto.contains("  OrderArray5 recent = 1;\n  OrderArray2 pinned = 2;\n}"), "{}", proto);
+    assert!(proto.contains("message OrderArray5 {\n  // fixed length: 5\n  repeated Order values = 1;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn chrono_imports_test() {
+    let proto = generate("chrono_imports", &[
+      ("src/lib.rs", "mod audit;\n// NOTE: ToProtobuf\nstruct Event {\n  at: chrono::DateTime<Utc>,\n  local: DateTime<Local>,\n}\n"),
+      ("src/audit.rs", "// NOTE: ToProtobuf\nstruct Audit {\n  created: NaiveDateTime,\n}\n"),
+    ], Options::default());
+    assert_eq!(proto.matches("import \"google/protobuf/timestamp.proto\";").count(), 1, "{}", proto);
+    assert!(proto.contains("  google.protobuf.Timestamp at = 1;\n  google.protobuf.Timestamp local = 2;"), "{}", proto);
+    assert!(proto.contains("  google.protobuf.Timestamp created = 1;"), "{}", proto);
+  }
+  
+  #[test]
+  fn duration_imports_test() {
+    let proto = generate("duration_imports", &[
+      ("src/lib.rs", "mod jobs;\n// NOTE: ToProtobuf\nstruct Job {\n  started: DateTime<Utc>,\n  timeout: std::time::Duration,\n}\n"),
+      ("src/jobs.rs", "// NOTE: ToProtobuf\nstruct Retry {\n  at: chrono::NaiveDateTime,\n  delay: Duration,\n}\n"),
+    ], Options::default());
+    assert_eq!(proto.matches("import \"google/protobuf/duration.proto\";").count(), 1, "{}", proto);
+    assert_eq!(proto.matches("import \"google/protobuf/timestamp.proto\";").count(), 1, "{}", proto);
+    assert!(proto.contains("  google.protobuf.Timestamp started = 1;\n  google.protobuf.Duration timeout = 2;"), "{}", proto);
+    assert!(proto.contains("  google.protobuf.Timestamp at = 1;\n  google.protobuf.Duration delay = 2;"), "{}", proto);
+  }
+  
+  #[test]
+  fn field_examples_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct User {
+  #[proto(example = "jane@example.com")]
+  email: String,
+  #[proto(
+    example = "42",
+  )]
+  age: u32,
+}
+"#)];
+    let proto = generate("field_examples", &files, Options { emit_examples: true, ..Default::default() });
+    assert!(proto.contains("  string email = 1; // example: jane@example.com\n  uint32 age = 2; // example: 42\n"), "{}", proto);
+    
+    let proto = generate("field_examples_off", &files, Options::default());
+    assert!(proto.contains("  string email = 1;\n  uint32 age = 2;\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn enum_forward_reference_test() {
+    let proto = generate("enum_forward_reference", &[
+      ("src/a.rs", r#"
+// NOTE: ToProtobuf
+struct Order {
+  status: Status,
+  history: Vec<Status>,
+}
+"#),
+      ("src/z.rs", r#"
+// NOTE: ToProtobuf
+enum Status {
+  Created,
+  Paid,
+}
+"#),
+    ], Options { panic_to_unsupported: true, ..Default::default() });
+    assert!(proto.contains("message Order {\n  Status status = 1;\n  repeated Status history = 2;\n}"), "{}", proto);
+    assert!(proto.contains("enum Status {\n  Created = 0;\n  Paid = 1;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn dump_unknown_types_test() {
+    let root = fixture_crate("dump_unknown_types", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Order {
+  id: uuid::Uuid,
+  items: Vec<Item>,
+  owner: uuid::Uuid,
+}
+
+// NOTE: ToProtobuf
+struct Refund {
+  order: uuid::Uuid,
+  amount: u64,
+}
+"#)]);
+    let mut parser = Parser::new(&root, Options { dump_unknown_types: true, ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    assert_eq!(parser.unknown_types_report(), "Unknown types:\n      3  uuid::Uuid\n      1  Item\n");
+  }
+  
+  #[test]
+  fn zigzag_field_override_test() {
+    let proto = generate("zigzag_field_override", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Delta {
+  #[proto(zigzag)]
+  dx: i32,
+  dy: i64,
+}
+"#)], Options::default());
+    assert!(proto.contains("  sint32 dx = 1;\n  int64 dy = 2;\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn serde_with_mapping_test() {
+    let root = fixture_crate("serde_with_mapping", &[
+      ("mapping.txt", "# serde `with` modules\nts_seconds = int64\n"),
+      ("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Session {
+  #[serde(with = "chrono::serde::ts_seconds")]
+  started: DateTime<Utc>,
+  #[serde(with = "ts_seconds")]
+  finished: DateTime<Utc>,
+}
+"#),
+    ]);
+    let serde_with_types = crate::utils::load_mapping_file(std::path::Path::new(&root).join("mapping.txt")).unwrap();
+    let mut parser = Parser::new(&root, Options { serde_with_types, panic_to_unsupported: true, ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    assert!(parser.generate().contains("  int64 started = 1;\n  int64 finished = 2;\n"), "{}", parser.generate());
+  }
+  
+  #[test]
+  fn doc_tag_options_test() {
+    let proto = generate("doc_tag_options", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct User {
+  /// Identifier of the user.
+  /// @proto json_name=user_id deprecated
+  id: u64,
+}
+"#)], Options::default());
+    assert!(proto.contains("  uint64 id = 1 [json_name = \"user_id\", deprecated = true];\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn prepended_lines_test() {
+    let proto = generate("prepended_lines", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct Empty {}\n")], Options {
+      prepend: vec!["@generated".into(), "// plugin: validate".into(), "first\nsecond".into()],
+      package: Some("prepended".into()),
+      ..Default::default()
+    });
+    assert!(proto.starts_with("syntax = \"proto3\";\npackage prepended;\n\n// @generated\n// plugin: validate\n// first\n// second\n\nmessage Empty {"), "{}", proto);
+  }
+  
+  #[test]
+  fn field_mask_test() {
+    let proto = generate("field_mask", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct UpdateUserRequest {
+  #[proto(type = "google.protobuf.FieldMask")]
+  update_mask: Vec<String>,
+  paths: FieldMask,
+}
+"#)], Options::default());
+    assert!(proto.contains("import \"google/protobuf/field_mask.proto\";\n"), "{}", proto);
+    assert!(proto.contains("  google.protobuf.FieldMask update_mask = 1;\n  google.protobuf.FieldMask paths = 2;\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn user_field_mask_test() {
+    let proto = generate("user_field_mask", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct FieldMask {
+  fields: Vec<String>,
+}
+
+// NOTE: ToProtobuf
+struct PatchRequest {
+  mask: FieldMask,
+  update_mask: prost_types::FieldMask,
+}
+"#)], Options::default());
+    assert!(proto.contains("message PatchRequest {\n  FieldMask mask = 1;\n  google.protobuf.FieldMask update_mask = 2;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn enum_allow_alias_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+enum Level {
+  Low = 1,
+  Normal,
+  Default = 2,
+  High = 0x10,
+}
+"#)];
+    let proto = generate("enum_allow_alias", &files, Options::default());
+    assert!(proto.contains("enum Level {\n  option allow_alias = true;\n  Low = 1;\n  Normal = 2;\n  Default = 2;\n  High = 16;\n}"), "{}", proto);
+    
+    let root = fixture_crate("enum_allow_alias_strict", &files);
+    let mut parser = Parser::new(&root, Options { strict: true, panic_to_unsupported: true, ..Default::default() }).unwrap();
+    assert!(parser.parse().is_err());
+  }
+  
+  #[test]
+  fn path_attribute_modules_test() {
+    let root = fixture_crate("path_attribute_modules", &[
+      ("shared/types.rs", "// NOTE: ToProtobuf\nstruct Shared {\n  id: u64,\n}\n"),
+      ("app/src/lib.rs", "#[path = \"../../shared/types.rs\"]\npub mod types;\n\n// NOTE: ToProtobuf\nstruct Local {\n  shared: Shared,\n}\n"),
+    ]);
+    let app_root = format!("{}/app", root);
+    let mut parser = Parser::new(&app_root, Options { panic_to_unsupported: true, ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    let proto = parser.generate();
+    assert!(proto.contains("message Local {\n  Shared shared = 1;\n}"), "{}", proto);
+    assert!(proto.contains("message Shared {\n  uint64 id = 1;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn json_bundle_test() {
+    let root = fixture_crate("json_bundle", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Event {
+  at: jiff::Timestamp,
+}
+
+// NOTE: ToProtobuf
+enum Kind {
+  Created,
+}
+
+// NOTE: ToProtobuf
+struct Broken {
+  id: uuid::Uuid,
+}
+"#)]);
+    let mut parser = Parser::new(&root, Options::default()).unwrap();
+    parser.parse().unwrap();
+    let bundle: serde_json::Value = serde_json::from_str(&parser.generate_json_bundle()).unwrap();
+    assert_eq!(bundle["proto"], parser.generate());
+    assert_eq!(bundle["messages"][0]["name"], "Event");
+    assert_eq!(bundle["messages"][0]["fields"][0]["proto3_type"], "google.protobuf.Timestamp");
+    assert_eq!(bundle["enums"][0]["variants"][0]["name"], "Created");
+    assert_eq!(bundle["imports"], json!(["google/protobuf/timestamp.proto"]));
+    assert!(bundle["warnings"][0].as_str().unwrap().contains("Broken"), "{}", bundle);
+  }
+  
+  #[test]
+  fn preserve_todos_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Invoice {
+  // TODO: switch to Decimal
+  amount: f64,
+  // regular comment
+  currency: String, // FIXME: use ISO 4217 enum
+}
+"#)];
+    let proto = generate("preserve_todos", &files, Options { preserve_todos: true, ..Default::default() });
+    assert!(proto.contains("message Invoice {\n  // TODO: switch to Decimal\n  double amount = 1;\n  // FIXME: use ISO 4217 enum\n  string currency = 2;\n}"), "{}", proto);
+    
+    let proto = generate("preserve_todos_off", &files, Options::default());
+    assert!(proto.contains("message Invoice {\n  double amount = 1;\n  string currency = 2;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn tuple_field_naming_test() {
+    let files = [("src/lib.rs", "// NOTE: ToProtobuf\nstruct Point(f64, f64);\n")];
+    let proto = generate("tuple_field_naming", &files, Options::default());
+    assert!(proto.contains("message Point {\n  double anonymous_value_1 = 1;\n  double anonymous_value_2 = 2;\n}"), "{}", proto);
+    
+    let proto = generate("tuple_field_naming_field", &files, Options { tuple_field_prefix: "field_".into(), ..Default::default() });
+    assert!(proto.contains("message Point {\n  double field_1 = 1;\n  double field_2 = 2;\n}"), "{}", proto);
+    
+    let proto = generate("tuple_field_naming_index", &files, Options { tuple_field_prefix: "_".into(), tuple_field_style: TupleFieldStyle::Index, ..Default::default() });
+    assert!(proto.contains("message Point {\n  double _0 = 1;\n  double _1 = 2;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn optional_bool_enum_test() {
+    let proto = generate("optional_bool_enum", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Flags {
+  a: Option<bool>,
+  b: Option<bool>,
+}
+"#)], Options { optional_bool_as: OptionalBoolRepr::Enum, ..Default::default() });
+    assert!(proto.contains("message Flags {\n  OptionalBool a = 1;\n  OptionalBool b = 2;\n}"), "{}", proto);
+    assert_eq!(proto.matches("enum OptionalBool {").count(), 1, "{}", proto);
+    assert!(proto.contains("enum OptionalBool {\n  OPTIONAL_BOOL_UNSET = 0;\n  OPTIONAL_BOOL_FALSE = 1;\n  OPTIONAL_BOOL_TRUE = 2;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn multiline_attributes_test() {
+    let proto = generate("multiline_attributes", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+#[derive(
+  Clone,
+  Debug,
+  serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub(crate) struct Wrapped {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+#[derive(Clone, Copy)]
+#[repr(u8)]
+pub enum Color {
+  Red,
+}
+"#)], Options::default());
+    assert!(proto.contains("message Wrapped {\n  uint64 id = 1;\n}"), "{}", proto);
+    assert!(proto.contains("enum Color {\n  Red = 0;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn import_manifest_test() {
+    let root = fixture_crate("import_manifest", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Event {
+  at: jiff::Timestamp,
+  id: u64,
+}
+"#)]);
+    let mut parser = Parser::new(&root, Options::default()).unwrap();
+    parser.parse().unwrap();
+    assert_eq!(parser.import_manifest(false), "google/protobuf/timestamp.proto\n");
+    assert_eq!(parser.import_manifest(true), r#"["google/protobuf/timestamp.proto"]"#);
+  }
+  
+  #[test]
+  fn raw_identifier_fields_test() {
+    let proto = generate("raw_identifier_fields", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Token {
+  pub r#type: String,
+  r#ref: u32,
+}
+"#)], Options::default());
+    assert!(proto.contains("message Token {\n  string type = 1;\n  uint32 ref = 2;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn model_hash_test() {
+    let model_hash = |name: &str, contents: &str| {
+      let root = fixture_crate(name, &[("src/lib.rs", contents)]);
+      let mut parser = Parser::new(&root, Options { emit_content_hash: true, ..Default::default() }).unwrap();
+      parser.parse().unwrap();
+      assert!(parser.generate().contains(&format!("// content-hash: {}\n", parser.model_hash())));
+      parser.model_hash()
+    };
+    let first = model_hash("model_hash_1", "// NOTE: ToProtobuf\nstruct User {\n  id: u64,\n}\n");
+    let second = model_hash("model_hash_2", "// NOTE: ToProtobuf\nstruct User {\n  id:   u64, // same model\n}\n");
+    let changed = model_hash("model_hash_3", "// NOTE: ToProtobuf\nstruct User {\n  id: u64,\n  name: String,\n}\n");
+    assert_eq!(first.len(), 64);
+    assert_eq!(first, second);
+    assert_ne!(first, changed);
+  }
+  
+  #[test]
+  fn const_discriminants_test() {
+    let proto = generate("const_discriminants", &[("src/lib.rs", r#"
+pub const BASE: u8 = 0x10;
+const NEXT: i32 = 32;
+
+// NOTE: ToProtobuf
+enum Code {
+  Ok = 0,
+  Base = BASE,
+  Next = Self::NEXT,
+  After,
+}
+"#)], Options::default());
+    assert!(proto.contains("enum Code {\n  Ok = 0;\n  Base = 16;\n  Next = 32;\n  After = 33;\n}"), "{}", proto);
+    
+    let proto = generate("const_discriminants_crate_path", &[
+      ("src/lib.rs", "mod codes;\n\nconst OK: i32 = 1;\n"),
+      ("src/codes.rs", "const OK: i32 = 7;\n\n// NOTE: ToProtobuf\nenum Code {\n  Unknown = 0,\n  Ok = crate::codes::OK,\n}\n"),
+    ], Options::default());
+    assert!(proto.contains("enum Code {\n  Unknown = 0;\n  Ok = 7;\n}"), "{}", proto);
+    
+    // `other::OK` не подменяется одноимённой константой этого файла
+    let root = fixture_crate("const_discriminants_unresolved", &[("src/lib.rs", "const OK: i32 = 1;\n\n// NOTE: ToProtobuf\nenum Code {\n  Ok = other::OK,\n}\n")]);
+    let mut parser = Parser::new(&root, Options { panic_to_unsupported: true, ..Default::default() }).unwrap();
+    let error = parser.parse().unwrap_err();
+    assert!(format!("{}", error).contains("discriminant `other::OK` of variant `Ok`"), "{}", error);
+  }
+  
+  #[test]
+  fn enum_value_out_of_int32_test() {
+    let root = fixture_crate("enum_value_out_of_int32", &[("src/lib.rs", "// NOTE: ToProtobuf\n#[repr(u32)]\nenum Flags {\n  Low = 1,\n  High = 3_000_000_000,\n}\n")]);
+    let mut parser = Parser::new(&root, Options { panic_to_unsupported: true, ..Default::default() }).unwrap();
+    let error = parser.parse().unwrap_err();
+    assert!(format!("{}", error).contains("value 3000000000 of variant `High` doesn't fit into `int32`"), "{}", error);
+    
+    let proto = generate("enum_value_int32_bounds", &[("src/lib.rs", "// NOTE: ToProtobuf\nenum Bounds {\n  Min = -2147483648,\n  Max = 2147483647,\n}\n")], Options::default());
+    assert!(proto.contains("enum Bounds {\n  Min = -2147483648;\n  Max = 2147483647;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn map_value_declared_later_test() {
+    let files = [
+      ("src/lib.rs", r#"
+mod b;
+
+// NOTE: ToProtobuf
+pub struct Registry {
+  pub by_name: std::collections::HashMap<String, Foo>,
+  pub by_id: BTreeMap<u64, crate::b::Bar>,
+}
+
+// NOTE: ToProtobuf
+pub struct Foo {
+  pub value: String,
+}
+"#),
+      ("src/b.rs", "// NOTE: ToProtobuf\npub struct Bar {\n  pub id: u64,\n}\n"),
+    ];
+    let proto = generate("map_value_declared_later", &files, Options::default());
+    assert!(proto.contains("  map<string, Foo> by_name = 1;"), "{}", proto);
+    assert!(proto.contains("  map<uint64, Bar> by_id = 2;"), "{}", proto);
+    
+    let proto = generate("map_value_declared_later_package", &files, Options { package: Some("shop".into()), ..Default::default() });
+    assert!(proto.contains("package shop;\n"), "{}", proto);
+    assert!(proto.contains("  map<string, Foo> by_name = 1;"), "{}", proto);
+    assert!(proto.contains("  map<uint64, Bar> by_id = 2;"), "{}", proto);
+    
+    let root = fixture_crate("map_value_declared_later_modules", &files);
+    let mut parser = Parser::new(&root, Options { auto_package_from_modules: true, package: Some("shop".into()), ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    let proto = parser.generate();
+    assert!(proto.contains("  map<string, Foo> by_name = 1;\n  map<uint64, .b.Bar> by_id = 2;"), "{}", proto);
+    let packages = parser.generate_module_files();
+    assert!(packages["b.proto"].contains("package b;\n\nmessage Bar {"), "{}", packages["b.proto"]);
+  }
+  
+  #[test]
+  fn repeated_bytes_override_test() {
+    let proto = generate("repeated_bytes_override", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Frame {
+  payload: Vec<u8>,
+  #[proto(repeated_bytes)]
+  pixels: std::vec::Vec<u8>,
+}
+"#)], Options::default());
+    assert!(proto.contains("  bytes payload = 1;\n  repeated uint32 pixels = 2;"), "{}", proto);
+    
+    let root = fixture_crate("repeated_bytes_misuse", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct Frame {\n  #[proto(repeated_bytes)]\n  name: String,\n}\n")]);
+    let mut parser = Parser::new(&root, Options { panic_to_unsupported: true, ..Default::default() }).unwrap();
+    let error = parser.parse().unwrap_err();
+    assert!(format!("{}", error).contains("only applicable to `Vec<u8>`"), "{}", error);
+  }
+  
+  #[test]
+  fn annotate_skipped_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Good {
+  name: String,
+}
+
+// NOTE: ToProtobuf
+struct Bad {
+  handle: std::fs::File,
+}
+"#)];
+    let proto = generate("annotate_skipped", &files, Options { quiet: true, annotate_skipped: true, ..Default::default() });
+    assert!(proto.contains("message Good {"), "{}", proto);
+    assert!(!proto.contains("message Bad"), "{}", proto);
+    assert!(proto.ends_with("\n// Skipped types:\n//   Bad: unknown type - `std::fs::File`\n"), "{}", proto);
+    
+    let proto = generate("annotate_skipped_off", &files, Options { quiet: true, ..Default::default() });
+    assert!(!proto.contains("Skipped types"), "{}", proto);
+  }
+  
+  #[test]
+  fn ensure_unspecified_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+enum OrderStatus {
+  Created,
+  Paid,
+}
+
+// NOTE: ToProtobuf
+enum Color {
+  Red = 1,
+  Green = 2,
+}
+
+// NOTE: ToProtobuf
+enum Kind {
+  Unspecified,
+  Plain,
+}
+"#)];
+    let proto = generate("ensure_unspecified", &files, Options { quiet: true, ensure_unspecified: true, ..Default::default() });
+    assert!(proto.contains("enum OrderStatus {\n  ORDER_STATUS_UNSPECIFIED = 0;\n  Created = 1;\n  Paid = 2;\n}"), "{}", proto);
+    assert!(proto.contains("enum Color {\n  COLOR_UNSPECIFIED = 0;\n  Red = 1;\n  Green = 2;\n}"), "{}", proto);
+    assert!(proto.contains("enum Kind {\n  Unspecified = 0;\n  Plain = 1;\n}"), "{}", proto);
+    
+    let proto = generate("ensure_unspecified_off", &files, Options::default());
+    assert!(proto.contains("enum OrderStatus {\n  Created = 0;\n  Paid = 1;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn deprecated_types_test() {
+    let proto = generate("deprecated_types", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+#[derive(Debug)]
+#[deprecated(note = "use UserV2")]
+pub struct User {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+#[deprecated]
+enum Role {
+  Admin,
+}
+
+// NOTE: ToProtobuf
+struct UserV2 {
+  id: u64,
+}
+"#)], Options::default());
+    assert!(proto.contains("message User {\n  option deprecated = true;\n  uint64 id = 1;\n}"), "{}", proto);
+    assert!(proto.contains("enum Role {\n  option deprecated = true;\n  Admin = 0;\n}"), "{}", proto);
+    assert!(proto.contains("message UserV2 {\n  uint64 id = 1;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn generation_result_test() {
+    let root = fixture_crate("generation_result", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Event {
+  at: jiff::Timestamp,
+  kind: Kind,
+}
+
+// NOTE: ToProtobuf
+enum Kind {
+  Created,
+}
+
+// NOTE: ToProtobuf
+struct Broken {
+  handle: std::fs::File,
+}
+
+// NOTE: ToProtobuf
+fn publish(event: Event) -> Event {}
+"#)]);
+    let result = convert(&root, Options { quiet: true, ..Default::default() }).unwrap();
+    assert!(result.proto.contains("message Event {"), "{}", result.proto);
+    assert_eq!(result.imports, vec!["google/protobuf/timestamp.proto".to_owned()]);
+    assert_eq!((result.messages, result.enums, result.services), (1, 1, 1));
+    assert_eq!(result.skipped, vec![("Broken".to_owned(), "unknown type - `std::fs::File`".to_owned())]);
+  }
+  
+  #[test]
+  fn generate_proto_test() {
+    let root = fixture_crate("generate_proto", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct User {\n  id: u64,\n}\n")]);
+    let proto = crate::generate_proto(std::path::Path::new(&root), Options { quiet: true, ..Default::default() }).unwrap();
+    assert!(proto.contains("message User {\n  uint64 id = 1;\n}"), "{}", proto);
+    let err = crate::generate_proto(std::path::Path::new(&root), Options { package: Some("Bad-Name".into()), ..Default::default() }).unwrap_err();
+    assert!(err.to_string().contains("isn't a valid package name"), "{}", err);
+  }
+  
+  #[test]
+  fn crate_filter_test() {
+    let files = [
+      ("services/users/src/lib.rs", "// NOTE: ToProtobuf\nstruct User {\n  id: u64,\n}\n"),
+      ("services/orders/src/lib.rs", "// NOTE: ToProtobuf\nstruct Order {\n  id: u64,\n}\n"),
+      ("tools/codegen/src/lib.rs", "// NOTE: ToProtobuf\nstruct Template {\n  id: u64,\n}\n"),
+      ("build.rs", "// NOTE: ToProtobuf\nstruct Script {\n  id: u64,\n}\n"),
+    ];
+    let proto = generate("crate_filter", &files, Options { crate_filter: vec!["services/*".into()], ..Default::default() });
+    assert!(proto.contains("message User {") && proto.contains("message Order {"), "{}", proto);
+    assert!(!proto.contains("message Template") && !proto.contains("message Script"), "{}", proto);
+    
+    let proto = generate("crate_filter_single", &files, Options { crate_filter: vec!["services/u*".into(), "tools".into()], ..Default::default() });
+    assert!(proto.contains("message User {") && proto.contains("message Template {"), "{}", proto);
+    assert!(!proto.contains("message Order") && !proto.contains("message Script"), "{}", proto);
+    
+    let proto = generate("crate_filter_none", &files, Options::default());
+    assert!(proto.contains("message Template {") && proto.contains("message Script {"), "{}", proto);
+  }
+  
+  #[test]
+  fn license_header_test() {
+    let license_header = "Copyright (c) Example Corp.\n\nLicensed under the Apache License, Version 2.0\n".lines().map(|l| l.to_owned()).collect();
+    let proto = generate("license_header", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct A {\n  a: u8,\n}\n")], Options { license_header, ..Default::default() });
+    assert!(proto.starts_with("// Copyright (c) Example Corp.\n//\n// Licensed under the Apache License, Version 2.0\n\nsyntax = \"proto3\";\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn money_as_google_type_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+pub struct Money {
+  pub amount: rust_decimal::Decimal,
+  pub currency: String,
+}
+
+// NOTE: ToProtobuf
+pub struct Invoice {
+  pub total: Money,
+  pub lines: Vec<crate::Money>,
+}
+"#)];
+    let proto = generate("money_as_google_type", &files, Options { money_as_google_type: true, ..Default::default() });
+    assert!(proto.contains("import \"google/type/money.proto\";\n"), "{}", proto);
+    assert!(proto.contains("message Invoice {\n  google.type.Money total = 1;\n  repeated google.type.Money lines = 2;\n}"), "{}", proto);
+    assert!(!proto.contains("message Money"), "{}", proto);
+    
+    let proto = generate("money_as_message", &files, Options::default());
+    assert!(proto.contains("message Money {\n  string amount = 1;\n  string currency = 2;\n}"), "{}", proto);
+    assert!(proto.contains("  Money total = 1;"), "{}", proto);
+    assert!(!proto.contains("import"), "{}", proto);
+  }
+  
+  #[test]
+  fn tag_report_test() {
+    let root = fixture_crate("tag_report", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct User {
+  id: u16,
+  tags: HashMap<String, u32>,
+}
+
+// NOTE: ToProtobuf
+enum Role {
+  Admin,
+}
+"#)]);
+    let mut parser = Parser::new(&root, Options::default()).unwrap();
+    parser.parse().unwrap();
+    assert!(parser.generate().contains("message User {\n  uint32 id = 1;\n  map<string, uint32> tags = 2;\n}"));
+    assert_eq!(
+      parser.tag_report(false),
+      "message,field,rust_type,proto_type,number\nUser,id,u16,uint32,1\nUser,tags,\"HashMap<String, u32>\",\"map<string, uint32>\",2\n",
+    );
+    assert_eq!(
+      serde_json::from_str::<serde_json::Value>(&parser.tag_report(true)).unwrap(),
+      json!([
+        { "message": "User", "field": "id", "rust_type": "u16", "proto_type": "uint32", "number": 1 },
+        { "message": "User", "field": "tags", "rust_type": "HashMap<String, u32>", "proto_type": "map<string, uint32>", "number": 2 },
+      ]),
+    );
+  }
+  
+  #[test]
+  fn data_enum_oneof_test() {
+    let proto = generate("data_enum_oneof", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+enum Command {
+  Quit,
+  Move {
+    x: i32,
+    y: i32, // pixels
+  },
+  Write(String),
+  ChangeColor(u8, u8, u8), Batch(Vec<Command>),
+}
+
+// NOTE: ToProtobuf
+struct Script {
+  commands: Vec<Command>,
+}
+"#)], Options::default());
+    assert!(!proto.contains("google/protobuf/empty.proto"), "{}", proto);
+    assert!(proto.contains(concat!(
+      "// An unset `command` stands for `Quit`\n",
+      "message Command {\n",
+      "  oneof command {\n",
+      "    CommandMove move = 1;\n",
+      "    string write = 2;\n",
+      "    CommandChangeColor change_color = 3;\n",
+      "    CommandBatch batch = 4;\n",
+      "  }\n",
+      "}\n",
+    )), "{}", proto);
+    assert!(proto.contains("message CommandMove {\n  int32 x = 1;\n  int32 y = 2;\n}"), "{}", proto);
+    assert!(proto.contains("message CommandChangeColor {\n  uint32 anonymous_value_1 = 1;\n  uint32 anonymous_value_2 = 2;\n  uint32 anonymous_value_3 = 3;\n}"), "{}", proto);
+    assert!(proto.contains("message CommandBatch {\n  repeated Command anonymous_value_1 = 1;\n}"), "{}", proto);
+    assert!(proto.contains("message Script {\n  repeated Command commands = 1;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn data_variant_docs_test() {
+    let proto = generate("data_variant_docs", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+enum Command {
+  /// Moves the cursor.
+  Move { x: i32 },
+  /// Writes a line.
+  /// Multiline docs are kept.
+  #[allow(dead_code)]
+  #[cfg_attr(feature = "json", serde(skip_serializing_if = "String::is_empty"))]
+  Write(String),
+  Stop(bool),
+}
+"#)], Options::default());
+    assert!(proto.contains(concat!(
+      "  oneof command {\n",
+      "    // Moves the cursor.\n",
+      "    CommandMove move = 1;\n",
+      "    // Writes a line.\n",
+      "    // Multiline docs are kept.\n",
+      "    string write = 2;\n",
+      "    bool stop = 3;\n",
+      "  }\n",
+    )), "{}", proto);
+  }
+  
+  #[test]
+  fn unit_variant_as_unset_oneof_test() {
+    let proto = generate("unit_variant_as_unset_oneof", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Foo {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+/// Optional foo.
+enum E {
+  None,
+  Some(Foo),
+}
+
+// NOTE: ToProtobuf
+enum Signal {
+  Stop,
+  Pause,
+  Seek(u64),
+}
+"#)], Options::default());
+    assert!(proto.contains("// Optional foo.\n// An unset `e` stands for `None`\nmessage E {\n  oneof e {\n    Foo some = 1;\n  }\n}"), "{}", proto);
+    // Несколько вариантов без данных незаполненным `oneof` не различить
+    assert!(proto.contains("message Signal {\n  oneof signal {\n    google.protobuf.Empty stop = 1;\n    google.protobuf.Empty pause = 2;\n    uint64 seek = 3;\n  }\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn geo_as_message_test() {
+    let proto = generate("geo_as_message", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct Route {\n  start: geo::Point<f64>,\n  stops: Vec<geo::Coord<f64>>,\n}\n")], Options { geo_as_message: true, ..Default::default() });
+    assert!(proto.contains("message Point {\n  double x = 1;\n  double y = 2;\n}"), "{}", proto);
+    assert!(proto.contains("message Route {\n  Point start = 1;\n  repeated Point stops = 2;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn nest_single_use_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Order {
+  address: Address,
+  lines: Vec<Line>,
+  status: Status,
+}
+
+// NOTE: ToProtobuf
+struct Line {
+  sku: String,
+  price: Price,
+}
+
+// NOTE: ToProtobuf
+struct Price {
+  cents: u64,
+}
+
+// NOTE: ToProtobuf
+struct Address {
+  city: String,
+}
+
+// NOTE: ToProtobuf
+struct Customer {
+  address: Address,
+}
+
+// NOTE: ToProtobuf
+enum Status {
+  New,
+}
+
+// NOTE: ToProtobuf
+struct Node {
+  next: Option<Link>,
+}
+
+// NOTE: ToProtobuf
+struct Link {
+  node: Node,
+}
+"#)];
+    let proto = generate("nest_single_use", &files, Options { nest_single_use: true, ..Default::default() });
+    assert!(proto.contains(concat!(
+      "\nmessage Order {\n",
+      "  Address address = 1;\n",
+      "  repeated Order.Line lines = 2;\n",
+      "  Order.Status status = 3;\n",
+      "\n",
+      "  message Line {\n",
+      "    string sku = 1;\n",
+      "    Order.Line.Price price = 2;\n",
+      "\n",
+      "    message Price {\n",
+      "      uint64 cents = 1;\n",
+      "    }\n",
+      "  }\n",
+      "\n",
+      "  enum Status {\n",
+      "    New = 0;\n",
+      "  }\n",
+      "}\n",
+    )), "{}", proto);
+    assert!(proto.contains("\nmessage Address {\n  string city = 1;\n}\n"), "{}", proto);
+    // Циклические ссылки остаются на верхнем уровне
+    assert!(proto.contains("\nmessage Node {\n  optional Link next = 1;\n}\n"), "{}", proto);
+    assert!(proto.contains("\nmessage Link {\n  Node node = 1;\n}\n"), "{}", proto);
+    assert!(!proto.contains("\nmessage Line") && !proto.contains("\nmessage Price"), "{}", proto);
+    
+    let proto = generate("nest_single_use_off", &files, Options::default());
+    assert!(proto.contains("\nmessage Line {\n  string sku = 1;\n  Price price = 2;\n}\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn package_test() {
+    let files = [("src/lib.rs", "// NOTE: ToProtobuf\nstruct A {\n  a: u8,\n}\n")];
+    let proto = generate("package", &files, Options { package: Some("acme.users.v1".into()), ..Default::default() });
+    assert!(proto.starts_with("syntax = \"proto3\";\npackage acme.users.v1;\n"), "{}", proto);
+    
+    let root = fixture_crate("package_default", &files);
+    let mut parser = Parser::new(&root, Options::default()).unwrap();
+    parser.parse().unwrap();
+    assert_eq!(parser.package_name(), format!("r2proto3_package_default_{}", std::process::id()));
+    
+    let root = fixture_crate("package-Dashed", &files);
+    let parser = Parser::new(&root, Options::default()).unwrap();
+    assert_eq!(parser.package_name(), format!("r2proto3_package_dashed_{}", std::process::id()));
+    
+    let root = format!("{}/src", fixture_crate("package_src", &files));
+    let parser = Parser::new(&root, Options::default()).unwrap();
+    assert_eq!(parser.package_name(), format!("r2proto3_package_src_{}", std::process::id()));
+    
+    for invalid in ["Acme", "acme..v1", "1acme", "acme-users"] {
+      assert!(Parser::new(&root, Options { package: Some(invalid.into()), ..Default::default() }).is_err(), "{}", invalid);
+    }
+  }
+  
+  #[test]
+  fn doc_comments_test() {
+    let proto = generate("doc_comments", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+/// A registered user.
+///
+/// Created on sign-up.
+#[derive(Debug)]
+struct User {
+  /// Unique identifier.
+  /// @proto deprecated
+  id: u64,
+  // Not a doc comment
+  name: String,
+  //// Not a doc comment either
+  role: Role,
+}
+
+// NOTE: ToProtobuf
+/// What a user is allowed to do.
+enum Role {
+  /// Full access.
+  Admin,
+  Guest,
+}
+"#)], Options::default());
+    assert!(proto.contains(concat!(
+      "\n// A registered user.\n",
+      "//\n",
+      "// Created on sign-up.\n",
+      "message User {\n",
+      "  // Unique identifier.\n",
+      "  uint64 id = 1 [deprecated = true];\n",
+      "  string name = 2;\n",
+      "  Role role = 3;\n",
+      "}\n",
+    )), "{}", proto);
+    assert!(proto.contains("\n// What a user is allowed to do.\nenum Role {\n  // Full access.\n  Admin = 0;\n  Guest = 1;\n}\n"), "{}", proto);
+  }
+  
+  #[test]
+  fn explicit_field_numbers_test() {
+    let proto = generate("explicit_field_numbers", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct User {
+  id: u64, // field = 2
+  name: String,
+  // field: 1
+  email: String,
+  age: u32,
+}
+"#)], Options::default());
+    assert!(proto.contains("message User {\n  uint64 id = 2;\n  string name = 3;\n  string email = 1;\n  uint32 age = 4;\n}"), "{}", proto);
+    
+    let files = [("src/lib.rs", "// NOTE: ToProtobuf\nstruct User {\n  id: u64, // field = 1\n  name: String, // field = 1\n}\n")];
+    let proto = generate("explicit_field_numbers_duplicate", &files, Options { quiet: true, ..Default::default() });
+    assert!(proto.contains("message User {\n  uint64 id = 1;\n  string name = 2;\n}"), "{}", proto);
+    
+    let root = fixture_crate("explicit_field_numbers_strict", &files);
+    let mut parser = Parser::new(&root, Options { panic_to_unsupported: true, ..Default::default() }).unwrap();
+    assert!(parser.parse().is_err());
+    
+    let root = fixture_crate("explicit_field_numbers_reserved", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct User {\n  id: u64, // field = 19001\n}\n")]);
+    let mut parser = Parser::new(&root, Options { panic_to_unsupported: true, ..Default::default() }).unwrap();
+    assert!(parser.parse().is_err());
+  }
+  
+  #[test]
+  fn range_as_message_test() {
+    let proto = generate("range_as_message", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct Page {\n  rows: Range<u32>,\n  cols: std::ops::Range<u32>,\n}\n")], Options { range_as_message: true, ..Default::default() });
+    assert!(proto.contains("message RangeUint32 {\n  uint32 start = 1;\n  uint32 end = 2;\n}"), "{}", proto);
+    assert!(proto.contains("message Page {\n  RangeUint32 rows = 1;\n  RangeUint32 cols = 2;\n}"), "{}", proto);
+  }
+  
+  #[test]
+  fn shared_wrappers_file_test() {
+    let root = fixture_crate("shared_wrappers_file", &[
+      ("src/lib.rs", "mod flags;\n// NOTE: ToProtobuf\nstruct Page {\n  rows: Range<u32>,\n  visible: Option<bool>,\n}\n"),
+      ("src/flags.rs", "// NOTE: ToProtobuf\nstruct Flag {\n  enabled: Option<bool>,\n  span: std::ops::Range<u32>,\n}\n"),
+    ]);
+    let options = Options {
+      range_as_message: true,
+      optional_bool_as: OptionalBoolRepr::Enum,
+      shared_wrappers_file: Some("common.proto".into()),
+      package: Some("pages".into()),
+      ..Default::default()
+    };
+    let mut parser = Parser::new(&root, options).unwrap();
+    parser.parse().unwrap();
+    let proto = parser.generate();
+    assert!(proto.contains("import \"common.proto\";\n"), "{}", proto);
+    assert!(!proto.contains("message RangeUint32") && !proto.contains("enum OptionalBool"), "{}", proto);
+    assert!(proto.contains("  RangeUint32 rows = 1;\n  OptionalBool visible = 2;"), "{}", proto);
+    
+    let shared = parser.generate_shared_wrappers().unwrap();
+    assert!(shared.starts_with("syntax = \"proto3\";\npackage pages;\n"), "{}", shared);
+    assert_eq!(shared.matches("message RangeUint32 {").count(), 1, "{}", shared);
+    assert_eq!(shared.matches("enum OptionalBool {").count(), 1, "{}", shared);
+    
+    let root = fixture_crate("shared_wrappers_file_unused", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct Plain {\n  id: u32,\n}\n")]);
+    let mut parser = Parser::new(&root, Options { shared_wrappers_file: Some("common.proto".into()), ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    assert!(!parser.generate().contains("common.proto"));
+    assert_eq!(parser.generate_shared_wrappers(), None);
+  }
+  
+  #[test]
+  fn brace_style_test() {
+    let files = [("src/lib.rs", r#"
+// NOTE: ToProtobuf
+struct Req {
+  id: u64,
+}
+
+// NOTE: ToProtobuf
+enum Command {
+  Stop,
+  Move(Req),
+}
+
+// NOTE: ToProtobuf
+// proto: idempotent
+fn get(r: Req) -> Req {}
+"#)];
+    let proto = generate("brace_style_same_line", &files, Options::default());
+    assert!(proto.contains("message Req {\n  uint64 id = 1;\n}"), "{}", proto);
+    
+    let proto = generate("brace_style_next_line", &files, Options { brace_style: BraceStyle::NextLine, ..Default::default() });
+    assert!(proto.contains("\nmessage Req\n{\n  uint64 id = 1;\n}\n"), "{}", proto);
+    assert!(proto.contains("\nmessage Command\n{\n  oneof command\n  {\n    Req move = 1;\n  }\n}\n"), "{}", proto);
+    assert!(proto.contains("\n{\n  rpc Get (Req) returns (Req)\n  {\n    option idempotency_level = IDEMPOTENT;\n  }\n}\n"), "{}", proto);
+    assert!(proto.lines().filter(|l| l.contains('{')).all(|l| l.trim() == "{"), "{}", proto);
+  }
+  
+  #[test]
+  fn timings_report_test() {
+    let root = fixture_crate("timings", &[("src/lib.rs", "// NOTE: ToProtobuf\nstruct Empty {}\n")]);
+    let mut parser = Parser::new(&root, Options::default()).unwrap();
+    parser.parse().unwrap();
+    let report = parser.timings.to_string();
+    assert!(report.starts_with("Timings:"), "{}", report);
+    for phase in ["file walk & read", "item extraction", "translation", "generation"] {
+      assert!(report.contains(phase), "{}", report);
+    }
+  }
+  
+  #[test]
+  fn descriptor_set_test() {
+    use crate::descriptor::{decode, Value};
+    
+    let root = fixture_crate("descriptor_set", &[("src/lib.rs", r#"
+// NOTE: ToProtobuf
+/// A registered user.
+struct User {
+  /// Unique id.
+  id: u64,
+  nickname: Option<String>,
+  roles: HashMap<String, Role>,
+}
+
+// NOTE: ToProtobuf
+enum Role {
+  Admin,
+  /// Read-only access.
+  Guest,
+}
+"#)]);
+    let mut parser = Parser::new(&root, Options { package: Some("accounts".into()), ..Default::default() }).unwrap();
+    parser.parse().unwrap();
+    
+    let get = |message: &[(u32, Value)], number: u32| message.iter().filter(|(n, _)| *n == number).map(|(_, v)| v.clone()).collect::<Vec<_>>();
+    let set = decode(&parser.descriptor_set(true));
+    assert_eq!(set.len(), 1);
+    let file = set[0].1.as_message();
+    assert_eq!(get(&file, 1)[0].as_str(), "generated.proto");
+    assert_eq!(get(&file, 2)[0].as_str(), "accounts");
+    assert_eq!(get(&file, 12)[0].as_str(), "proto3");
+    
+    let user = get(&file, 4)[0].as_message();
+    assert_eq!(get(&user, 1)[0].as_str(), "User");
+    let fields = get(&user, 2).iter().map(|f| f.as_message()).collect::<Vec<_>>();
+    assert_eq!(fields.iter().map(|f| get(f, 1)[0].as_str().to_owned()).collect::<Vec<_>>(), vec!["id", "nickname", "roles"]);
+    assert_eq!(get(&fields[0], 5), vec![Value::Varint(4)]);
+    // `optional` поле входит в синтетический `oneof`
+    assert_eq!(get(&fields[1], 17), vec![Value::Varint(1)]);
+    assert_eq!(get(&fields[1], 9), vec![Value::Varint(0)]);
+    assert_eq!(get(&get(&user, 8)[0].as_message(), 1)[0].as_str(), "_nickname");
+    // `map` - повторяющееся поле с вложенным сообщением `RolesEntry`
+    assert_eq!(get(&fields[2], 4), vec![Value::Varint(3)]);
+    assert_eq!(get(&fields[2], 6)[0].as_str(), ".accounts.User.RolesEntry");
+    let entry = get(&user, 3)[0].as_message();
+    assert_eq!(get(&entry, 1)[0].as_str(), "RolesEntry");
+    let value = get(&entry, 2)[1].as_message();
+    assert_eq!(get(&value, 5), vec![Value::Varint(14)]);
+    assert_eq!(get(&value, 6)[0].as_str(), ".accounts.Role");
+    
+    let locations = get(&get(&file, 9)[0].as_message(), 1).iter().map(|l| l.as_message()).collect::<Vec<_>>();
+    let location = |path: &[i32]| locations.iter().find(|l| get(l, 1)[0].as_packed() == path).unwrap_or_else(|| panic!("no location for {:?}", path));
+    // `span` указывает на строки сгенерированного файла, считая с нуля
+    let id = location(&[4, 0, 2, 0]);
+    assert_eq!(get(id, 2)[0].as_packed(), vec![12, 2, 16]);
+    assert_eq!(get(id, 3)[0].as_str(), " Unique id.\n");
+    assert!(get(id, 4).is_empty());
+    assert_eq!(get(location(&[4, 0]), 2)[0].as_packed(), vec![10, 0, 15, 1]);
+    assert_eq!(get(location(&[4, 0]), 3)[0].as_str(), " A registered user.\n");
+    assert_eq!(get(location(&[4, 0, 2, 2]), 2)[0].as_packed(), vec![14, 2, 30]);
+    assert!(get(location(&[4, 0, 2, 1]), 3).is_empty());
+    assert_eq!(get(location(&[5, 0, 2, 1]), 2)[0].as_packed(), vec![6, 2, 12]);
+    assert_eq!(get(location(&[5, 0, 2, 1]), 3)[0].as_str(), " Read-only access.\n");
+    
+    assert!(get(&decode(&parser.descriptor_set(false))[0].1.as_message(), 9).is_empty());
+    
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&parser.source_map()).unwrap(), json!({
+      "accounts.User": "src/lib.rs:4",
+      "accounts.User.id": "src/lib.rs:6",
+      "accounts.User.nickname": "src/lib.rs:7",
+      "accounts.User.roles": "src/lib.rs:8",
+      "accounts.Role": "src/lib.rs:12",
+      "accounts.Role.Admin": "src/lib.rs:13",
+      "accounts.Role.Guest": "src/lib.rs:15",
+    }));
+  }
+  
+  #[test]
+  fn proto_spans_test() {
+    let contents = "syntax = \"proto3\";\n\nmessage Order\n{\n  oneof kind {\n    string note = 1; // nullable\n  }\n  enum Status {\n    OPEN = 0;\n  }\n}\n\nservice Shop {\n  rpc Get (Order) returns (Order);\n  rpc Put (Order) returns (Order) {\n    option idempotency_level = IDEMPOTENT;\n  }\n}\n";
+    let spans = proto_spans(contents);
+    let span = |path: &[&str]| spans.get(&path.iter().map(|p| p.to_string()).collect::<Vec<_>>()).cloned();
+    assert_eq!(span(&["Order"]), Some(vec![2, 0, 10, 1]));
+    assert_eq!(span(&["Order", "note"]), Some(vec![5, 4, 20]));
+    assert_eq!(span(&["Order", "Status"]), Some(vec![7, 2, 9, 3]));
+    assert_eq!(span(&["Order", "Status", "OPEN"]), Some(vec![8, 4, 13]));
+    assert_eq!(span(&["Shop"]), Some(vec![12, 0, 17, 1]));
+    assert_eq!(span(&["Shop", "Get"]), Some(vec![13, 2, 34]));
+    assert_eq!(span(&["Shop", "Put"]), Some(vec![14, 2, 16, 3]));
+    assert_eq!(span(&["kind"]), None);
+    assert_eq!(spans.len(), 7);
+  }
 }
@@ -0,0 +1,62 @@
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+use crate::types::TypesParser;
+use crate::utils::{MResult, R2Proto3Error};
+
+/// Запрос на перевод одного типа: `{"type": "Vec<Foo>", "known": ["Foo"]}`.
+#[derive(Deserialize)]
+struct TranslateRequest {
+  r#type: String,
+  #[serde(default)]
+  known: BTreeSet<String>,
+}
+
+/// Читает запросы построчно из `input` и пишет ответы в `output`, пока не закончится ввод.
+///
+/// На каждый запрос выводится ровно одна строка: `{"proto": "..."}` или `{"error": "..."}`.
+pub fn serve(types_parser: &TypesParser, input: impl BufRead, mut output: impl Write) -> MResult<()> {
+  for line in input.lines() {
+    let line = line.map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot read request"))?;
+    if line.trim().is_empty() { continue }
+    
+    let response = match serde_json::from_str::<TranslateRequest>(&line) {
+      Err(e) => json!({ "error": format!("invalid request: {}", e) }),
+      Ok(request) => {
+        let rust_type = TypesParser::drop_type_unnecessary_stuff(&request.r#type);
+        let result = types_parser.rust_type_to_protobuf(&rust_type, &request.known, false);
+        let warnings = std::mem::take(&mut *types_parser.warnings.borrow_mut());
+        match result {
+          Ok(proto) if warnings.is_empty() => json!({ "proto": proto }),
+          Ok(proto) => json!({ "proto": proto, "warnings": warnings }),
+          Err(e) => json!({ "error": e.to_string() }),
+        }
+      },
+    };
+    
+    writeln!(output, "{}", response).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write response"))?;
+    output.flush().map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write response"))?;
+  }
+  
+  Ok(())
+}
+
+#[cfg(test)]
+mod server_tests {
+  use super::*;
+  use crate::options::Options;
+  
+  #[test]
+  fn serve_test() {
+    let types_parser = TypesParser::new(&Options { quiet: true, ..Default::default() }).unwrap();
+    let input = "{\"type\": \"Vec<Foo>\", \"known\": [\"Foo\"]}\n\n{\"type\": \"Bar\"}\n";
+    let mut output = vec![];
+    serve(&types_parser, input.as_bytes(), &mut output).unwrap();
+    assert_eq!(
+      String::from_utf8(output).unwrap(),
+      "{\"proto\":\"repeated Foo\"}\n{\"error\":\"unknown type - `Bar`\"}\n",
+    );
+  }
+}
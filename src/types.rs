@@ -1,29 +1,91 @@
 use regex::Regex;
-use std::collections::BTreeSet;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 
+use crate::options::{BignumRepr, HalfRepr, OptionalBoolRepr, Options, TupleFieldStyle, UsizeWidth};
+use crate::parser::{ProtobufEntityType, ProtobufEnumVariant, ProtobufField};
 use crate::utils::{MResult, R2Proto3Error};
 
-pub(crate) struct TypesParser {
+/// Well-known типы Protobuf: тип Rust, соответствующий ему тип Protobuf и файл, который нужно импортировать.
+/// `<_>` в типе Rust соответствует любому параметру (например, часовому поясу).
+/// Короткие имена (`Duration`, `FieldMask`) уступают помеченным типам с тем же именем.
+pub(crate) const WELL_KNOWN_TYPES: &[(&str, &str, &str)] = &[
+  ("chrono::DateTime<_>",    "google.protobuf.Timestamp", "google/protobuf/timestamp.proto"),
+  ("DateTime<_>",            "google.protobuf.Timestamp", "google/protobuf/timestamp.proto"),
+  ("chrono::NaiveDateTime",  "google.protobuf.Timestamp", "google/protobuf/timestamp.proto"),
+  ("NaiveDateTime",          "google.protobuf.Timestamp", "google/protobuf/timestamp.proto"),
+  ("jiff::Timestamp", "google.protobuf.Timestamp", "google/protobuf/timestamp.proto"),
+  ("jiff::Zoned",     "google.protobuf.Timestamp", "google/protobuf/timestamp.proto"),
+  ("jiff::Span",      "google.protobuf.Duration",  "google/protobuf/duration.proto"),
+  ("Duration",               "google.protobuf.Duration",  "google/protobuf/duration.proto"),
+  ("std::time::Duration",    "google.protobuf.Duration",  "google/protobuf/duration.proto"),
+  ("core::time::Duration",   "google.protobuf.Duration",  "google/protobuf/duration.proto"),
+  ("prost_types::Any",       "google.protobuf.Any",       "google/protobuf/any.proto"),
+  ("()",                     "google.protobuf.Empty",     "google/protobuf/empty.proto"),
+  ("FieldMask",              "google.protobuf.FieldMask", "google/protobuf/field_mask.proto"),
+  ("prost_types::FieldMask", "google.protobuf.FieldMask", "google/protobuf/field_mask.proto"),
+];
+
+/// Обёртки, которые прозрачны для DTO: вместо них переводится внутренний тип.
+/// Второе значение - нужно ли предупреждать об этом (ленивая инициализация и атомарная замена значения при передаче теряются).
+pub(crate) const TRANSPARENT_WRAPPERS: &[(&str, bool)] = &[
+  ("Box",      false),
+  ("Rc",       false),
+  ("Arc",      false),
+  ("ArcSwap",  true),
+  ("OnceCell", true),
+  ("OnceLock", true),
+  ("Lazy",     true),
+  ("LazyLock", true),
+];
+
+pub struct TypesParser {
   inner_vec_type_re: Regex,
   inner_option_type_re: Regex,
   inner_map_type_re: Regex,
+  inner_array_type_re: Regex,
+  inner_set_type_re: Regex,
+  options: Options,
+  /// Типы, которые не удалось перевести, и количество их упоминаний
+  pub(crate) unknown_types: RefCell<BTreeMap<String, usize>>,
+  /// Все выведенные предупреждения
+  pub(crate) warnings: RefCell<Vec<String>>,
+  /// Вспомогательные типы, которые нужно добавить в файл, чтобы выразить некоторые типы Rust
+  pub(crate) synthetic_types: RefCell<BTreeMap<String, ProtobufEntityType>>,
+  /// Помеченные типы, вместо которых используются внешние типы Protobuf: имя -> (тип Protobuf, импортируемый файл)
+  pub(crate) external_types: RefCell<BTreeMap<String, (String, String)>>,
+  /// Псевдонимы типов крейта (`type UserId = u64;`): имя -> тип Rust
+  pub(crate) aliases: RefCell<BTreeMap<String, String>>,
+  /// Псевдонимы, которые раскрываются в данный момент, для обнаружения циклов
+  resolving_aliases: RefCell<Vec<String>>,
 }
 
 impl TypesParser {
-  pub(crate) fn new() -> MResult<Self> {
+  pub fn new(options: &Options) -> MResult<Self> {
     Ok(Self {
-      inner_vec_type_re: Regex::new(r#"Vec<([a-zA-Z0-9<>()\[\],:_ ]*)>"#)
+      inner_vec_type_re: Regex::new(r#"^(?:[a-z_]+::)*Vec<([a-zA-Z0-9<>()\[\],;:_ ]*)>$"#)
         .map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось собрать регулярное выражение для внутренних типов данных вектора"))?,
-      inner_option_type_re: Regex::new(r#"Option<([a-zA-Z0-9<>()\[\],:_ ]*)>"#)
+      inner_option_type_re: Regex::new(r#"^(?:[a-z_]+::)*Option<([a-zA-Z0-9<>()\[\],;:_ ]*)>$"#)
         .map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось собрать регулярное выражение для внутренних типов данных опционального типа"))?,
-      inner_map_type_re: Regex::new(r#"(HashMap<([a-zA-Z0-9<>()\[\],:_ ]*)>)|(BTreeMap<([a-zA-Z0-9<>()\[\],:_ ]*)>)"#)
+      inner_map_type_re: Regex::new(r#"^(?:[a-z_]+::)*(?:(HashMap<([a-zA-Z0-9<>()\[\],;:_ ]*)>)|(BTreeMap<([a-zA-Z0-9<>()\[\],;:_ ]*)>))$"#)
         .map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось собрать регулярное выражение для внутренних типов данных словаря"))?,
+      inner_array_type_re: Regex::new(r#"^\[([a-zA-Z0-9<>()\[\],;:_ ]*);[ ]*([A-Za-z0-9_:]+)[ ]*\]$"#)
+        .map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось собрать регулярное выражение для внутренних типов данных массива"))?,
+      inner_set_type_re: Regex::new(r#"^(?:[a-z_]+::)*(?:HashSet|BTreeSet)<([a-zA-Z0-9<>()\[\],;:_ ]*)>$"#)
+        .map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "Не удалось собрать регулярное выражение для внутренних типов данных множества"))?,
+      options: options.clone(),
+      unknown_types: RefCell::default(),
+      warnings: RefCell::default(),
+      synthetic_types: RefCell::default(),
+      external_types: RefCell::default(),
+      aliases: RefCell::default(),
+      resolving_aliases: RefCell::default(),
     })
   }
   
-  pub(crate) fn rust_type_to_protobuf<'a>(
+  pub(crate) fn rust_type_to_protobuf(
     &self,
-    rust_type: &'a str,
+    rust_type: &str,
     known_types: &BTreeSet<String>,
     for_map_key: bool,
   ) -> MResult<String> {
@@ -35,35 +97,145 @@ impl TypesParser {
     
     match rust_type {
       "f64"                => if !for_map_key { Ok("double".into()) } else { Err(R2Proto3Error::new(None, unsupported_key_msg.unwrap())) },
-      "f32" | "f16" | "f8" => if !for_map_key { Ok("float".into()) } else { Err(R2Proto3Error::new(None, unsupported_key_msg.unwrap())) },
-      "i64"                => Ok("int64".into()),
-      "i32" | "i16" | "i8" => Ok("int32".into()),
+      "f32"                => if !for_map_key { Ok("float".into()) } else { Err(R2Proto3Error::new(None, unsupported_key_msg.unwrap())) },
+      // `f16` - нестабильный примитив, `half::f16`/`half::bf16` - типы из крейта `half`. Все они без потерь расширяются до `float`
+      // либо передаются побитово в `bytes`.
+      "f16" | "half::f16" | "bf16" | "half::bf16" => if !for_map_key {
+        match self.options.half_as {
+          HalfRepr::Float => {
+            if self.options.strict {
+              self.warn(format!("`{}` is widened to `float`: values may not round-trip bit-exactly, consider `--half-as bytes`", rust_type));
+            }
+            Ok("float".into())
+          },
+          HalfRepr::Bytes => Ok("bytes".into()),
+        }
+      } else { Err(R2Proto3Error::new(None, unsupported_key_msg.unwrap())) },
+      "i64"                => Ok(if self.options.signed_as_zigzag { "sint64" } else { "int64" }.into()),
+      "i32" | "i16" | "i8" => Ok(if self.options.signed_as_zigzag { "sint32" } else { "int32" }.into()),
       "u64"                => Ok("uint64".into()),
       "u32" | "u16" | "u8" => Ok("uint32".into()),
       "bool"               => Ok("bool".into()),
       "String"             => Ok("string".into()),
-      "Vec<u8>"            => if !for_map_key { Ok("bytes".into()) } else { Err(R2Proto3Error::new(None, unsupported_key_msg.unwrap())) },
+      // Строка из одного символа; ограничение выводится комментарием к полю
+      "char"               => Ok("string".into()),
+      // Разрядность зависит от платформы; ширина выбирается через `--usize-as` и выводится комментарием к полю
+      "usize" => Ok(match self.options.usize_as { UsizeWidth::W32 => "uint32", UsizeWidth::W64 => "uint64" }.into()),
+      "isize" => Ok(match (self.options.usize_as, self.options.signed_as_zigzag) {
+        (UsizeWidth::W32, false) => "int32",
+        (UsizeWidth::W32, true) => "sint32",
+        (UsizeWidth::W64, false) => "int64",
+        (UsizeWidth::W64, true) => "sint64",
+      }.into()),
+      // Числа произвольной точности
+      "bigdecimal::BigDecimal" | "BigDecimal" | "rust_decimal::Decimal" | "Decimal" => Ok("string".into()),
+      "num_bigint::BigInt" | "BigInt" | "num_bigint::BigUint" | "BigUint" => match self.options.bignum_as {
+        BignumRepr::String => Ok("string".into()),
+        BignumRepr::Bytes if !for_map_key => Ok("bytes".into()),
+        BignumRepr::Bytes => Err(R2Proto3Error::new(None, unsupported_key_msg.unwrap())),
+      },
+      // Интернированные строки
+      "ustr::Ustr" | "Ustr" | "kstring::KString" | "KString" => Ok("string".into()),
+      // Передаётся в каноническом виде (`Url::as_str`)
+      "url::Url" | "Url" => Ok("string".into()),
+      // Подсети в записи CIDR (`10.0.0.0/8`), как их выдаёт `Display`
+      "ipnetwork::IpNetwork" | "IpNetwork" | "ipnetwork::Ipv4Network" | "Ipv4Network" | "ipnetwork::Ipv6Network" | "Ipv6Network"
+      | "cidr::IpCidr" | "IpCidr" | "cidr::Ipv4Cidr" | "Ipv4Cidr" | "cidr::Ipv6Cidr" | "Ipv6Cidr" => Ok("string".into()),
+      // Регулярные выражения ниже привязаны к началу и концу типа, поэтому `Option<Vec<u8>>` доходит сюда уже как `Vec<u8>`
+      "Vec<u8>" | "std::vec::Vec<u8>" | "alloc::vec::Vec<u8>" => if !for_map_key { Ok("bytes".into()) } else { Err(R2Proto3Error::new(None, unsupported_key_msg.unwrap())) },
+      // Точки крейта `geo` передаются вспомогательным сообщением `Point`
+      "geo::Point<f64>" | "geo::Point" | "geo::Coord<f64>" | "geo::Coord" | "geo_types::Point<f64>" | "geo_types::Coord<f64>" => {
+        if for_map_key {
+          Err(R2Proto3Error::new(None, unsupported_key_msg.unwrap()))
+        } else if !self.options.geo_as_message {
+          Err(R2Proto3Error::new(None, format!("`{}` has no Protobuf counterpart: pass `--geo-as-message` to generate a `Point` message for it", rust_type)))
+        } else {
+          self.add_synthetic_message("Point", &[("x", "f64", "double"), ("y", "f64", "double")]);
+          Ok("Point".into())
+        }
+      },
+      // `Range<u32>` -> `RangeUint32 { uint32 start = 1; uint32 end = 2; }`, `RangeInclusive<u32>` -> `RangeInclusiveUint32`
+      _ if let Some((range, inner)) = rust_type.strip_suffix('>').and_then(|t| t.split_once('<'))
+        && let Some(range @ ("Range" | "RangeInclusive")) = range.rsplit("::").next()
+        && !for_map_key =>
+      {
+        if !self.options.range_as_message {
+          return Err(R2Proto3Error::new(None, format!("`{}` has no Protobuf counterpart: pass `--range-as-message` to generate a message for it", rust_type)))
+        }
+        let inner = TypesParser::drop_type_unnecessary_stuff(inner);
+        let bound_type = self.rust_type_to_protobuf(&inner, known_types, false)?;
+        if bound_type.contains(' ') {
+          return Err(R2Proto3Error::new(None, format!("bounds of `{}` should be scalars or messages, got `{}`", rust_type, bound_type)))
+        }
+        let name = format!("{}{}", range, TypesParser::to_pascal_case(bound_type.replace('.', "_")));
+        self.add_synthetic_message(&name, &[("start", &inner, &bound_type), ("end", &inner, &bound_type)]);
+        Ok(name)
+      },
+      // Помеченный тип с тем же именем (например, свой `struct Duration`) важнее well-known типа
+      _ if let Some(proto3_type) = TypesParser::well_known_type(rust_type) && !known_types.contains(rust_type) => {
+        if !for_map_key { Ok(proto3_type.to_string()) } else { Err(R2Proto3Error::new(None, unsupported_key_msg.unwrap())) }
+      },
+      _ if let Some((wrapper, inner, warn)) = TypesParser::strip_wrapper(rust_type) => {
+        if warn {
+          self.warn(format!("`{}` is unwrapped to `{}`: only the current value is transferred", wrapper, inner));
+        }
+        self.rust_type_to_protobuf(&inner, known_types, for_map_key)
+      },
       _ => {
         if let Some((_, [inner])) = self.inner_vec_type_re.captures_iter(rust_type).map(|c| c.extract()).next() {
           let inner_type = self.rust_type_to_protobuf(inner, known_types, false)?;
-          if inner_type.starts_with("repeated") {
-            Err(R2Proto3Error::new(None, format!("need to use `repeated` twice: consider not to use Vec<Vec<_>> etc.")))
-          } else {
-            Ok(format!("repeated {}", inner_type))
+          TypesParser::repeated(rust_type, inner_type)
+        }
+        // Множества передаются списками: уникальность элементов Protobuf не проверяет
+        else if let Some((_, [inner])) = self.inner_set_type_re.captures_iter(rust_type).map(|c| c.extract()).next() {
+          let inner_type = self.rust_type_to_protobuf(inner, known_types, false)?;
+          TypesParser::repeated(rust_type, inner_type)
+        }
+        // `[u8; 32]` -> `bytes`, `[T; N]` -> `repeated T` (или сообщение `FloatArray16` с `--fixed-arrays-as-message`)
+        else if let Some((inner, len)) = self.fixed_array(rust_type) {
+          if for_map_key {
+            return Err(R2Proto3Error::new(None, unsupported_key_msg.unwrap()))
+          }
+          if inner == "u8" {
+            return Ok("bytes".into())
+          }
+          let repeated = TypesParser::repeated(rust_type, self.rust_type_to_protobuf(&inner, known_types, false)?)?;
+          if !self.options.fixed_arrays_as_message {
+            return Ok(repeated)
           }
+          let element_type = repeated.trim_start_matches("repeated ");
+          let name = format!("{}Array{}", TypesParser::to_pascal_case(element_type.replace('.', "_")), TypesParser::to_pascal_case(len.to_lowercase()));
+          self.synthetic_types.borrow_mut().entry(name.clone()).or_insert_with(|| ProtobufEntityType::Message(vec![
+            TypesParser::fixed_array_values(&inner, &len, repeated.clone()),
+          ]));
+          Ok(name)
         }
         else if let Some((_, [inner])) = self.inner_option_type_re.captures_iter(rust_type).map(|c| c.extract()).next() {
+          if self.options.optional_bool_as == OptionalBoolRepr::Enum && TypesParser::drop_type_unnecessary_stuff(inner) == "bool" {
+            self.synthetic_types.borrow_mut().entry("OptionalBool".to_owned()).or_insert_with(|| ProtobufEntityType::Enum(
+              ["UNSET", "FALSE", "TRUE"]
+                .iter()
+                .enumerate()
+                .map(|(value, name)| ProtobufEnumVariant { name: format!("OPTIONAL_BOOL_{}", name), value: value as i32, doc: None, deprecated: false, source_line: None })
+                .collect(),
+            ));
+            return Ok("OptionalBool".into())
+          }
           let inner_type = self.rust_type_to_protobuf(inner, known_types, false)?;
           if inner_type.starts_with("optional") {
-            Err(R2Proto3Error::new(None, format!("need to use `optional` twice: consider not to use Option<Option<_>> etc.")))
+            Err(R2Proto3Error::new(None, "need to use `optional` twice: consider not to use Option<Option<_>> etc."))
+          }
+          // `repeated` и `map` не бывают `optional`: отсутствие значения передаётся пустым списком или словарём
+          else if inner_type.starts_with("repeated") || inner_type.starts_with("map<") {
+            Ok(inner_type)
           } else {
             Ok(format!("optional {}", inner_type))
           }
         }
         else if let Some((_, [_, inner])) = self.inner_map_type_re.captures_iter(rust_type).map(|c| c.extract()).next() {
-          let inners = TypesParser::split_inner_types(inner)?.iter().map(|i| TypesParser::drop_type_unnecessary_stuff(i)).collect::<Vec<_>>();
+          let inners = TypesParser::split_inner_types(inner)?.iter().map(TypesParser::drop_type_unnecessary_stuff).collect::<Vec<_>>();
           if inners.len() != 2 {
-            return Err(R2Proto3Error::new(None, format!("there is only one or more than 2 inner types of `HashMap`/`BTreeMap`")))
+            return Err(R2Proto3Error::new(None, "there is only one or more than 2 inner types of `HashMap`/`BTreeMap`"))
           }
           let (key_type, value_type) = (&inners[0], &inners[1]);
           
@@ -73,12 +245,234 @@ impl TypesParser {
           Ok(format!("map<{}, {}>", inner_key_type, inner_value_type))
         }
         
-        else if known_types.contains(rust_type) { Ok(rust_type.into()) }
-        else { Err(R2Proto3Error::new(None, format!("unknown type - `{}`", rust_type))) }
+        else if known_types.contains(rust_type) { Ok(self.resolve_known_type(rust_type)) }
+        // `crate::users::User`, `super::Order` и т.п. сводятся к последнему сегменту, если он известен
+        else if self.options.strip_module_paths && let Some((_, name)) = rust_type.rsplit_once("::") && known_types.contains(name) {
+          Ok(self.resolve_known_type(name))
+        }
+        // Псевдонимы раскрываются, только если под этим именем нет помеченного типа
+        else if let Some((alias, target)) = self.alias_target(rust_type) {
+          if let Some(pos) = self.resolving_aliases.borrow().iter().position(|a| *a == alias) {
+            let cycle = self.resolving_aliases.borrow()[pos..].iter().chain(std::iter::once(&alias)).map(|a| format!("`{}`", a)).collect::<Vec<_>>();
+            return Err(R2Proto3Error::new(None, format!("type alias `{}` refers to itself: {}", alias, cycle.join(" -> "))))
+          }
+          self.resolving_aliases.borrow_mut().push(alias.clone());
+          // `type Coord = (f64, f64);` -> `message Coord { double anonymous_value_1 = 1; double anonymous_value_2 = 2; }`
+          let result = match target.trim().strip_prefix('(').and_then(|t| t.strip_suffix(')')).filter(|t| !t.trim().is_empty()) {
+            Some(_) if for_map_key => Err(R2Proto3Error::new(None, unsupported_key_msg.unwrap())),
+            Some(elements) => self.add_tuple_message(&alias, elements, known_types),
+            None => self.rust_type_to_protobuf(&target, known_types, for_map_key),
+          };
+          self.resolving_aliases.borrow_mut().pop();
+          result
+        }
+        else {
+          *self.unknown_types.borrow_mut().entry(rust_type.to_owned()).or_default() += 1;
+          Err(R2Proto3Error::new(None, format!("unknown type - `{}`", rust_type)))
+        }
       },
     }
   }
   
+  /// `[f32; 16]` -> (`f32`, `16`)
+  pub(crate) fn fixed_array(&self, rust_type: &str) -> Option<(String, String)> {
+    self.inner_array_type_re
+      .captures_iter(rust_type)
+      .map(|c| c.extract())
+      .next()
+      .map(|(_, [inner, len])| (TypesParser::drop_type_unnecessary_stuff(inner), len.to_owned()))
+  }
+  
+  /// Единственное поле сообщения для массива фиксированной длины; длина записывается в комментарий.
+  pub(crate) fn fixed_array_values(inner: &str, len: &str, repeated: String) -> ProtobufField {
+    ProtobufField {
+      name: "values".into(),
+      rust_type: format!("[{}; {}]", inner, len),
+      proto3_type: repeated,
+      field_num: 1,
+      example: None,
+      options: vec![],
+      todos: vec![],
+      oneof: None,
+      doc: Some(format!("fixed length: {}", len)),
+      deprecated: false,
+      source_line: None,
+    }
+  }
+  
+  /// Псевдоним и его значение: по полному имени или, с `--strip-module-paths`, по последнему сегменту пути.
+  fn alias_target(&self, rust_type: &str) -> Option<(String, String)> {
+    let aliases = self.aliases.borrow();
+    // Конкретизации обобщённых типов записаны без путей модулей: `crate::Page<crate::Order>` ищется как `Page<Order>`
+    if !aliases.contains_key(rust_type) && rust_type.contains('<') {
+      let instance = Self::without_module_paths(rust_type);
+      return aliases.get(&instance).map(|target| (instance, target.to_owned()))
+    }
+    let name = match rust_type.rsplit_once("::") {
+      Some((_, name)) if self.options.strip_module_paths && !aliases.contains_key(rust_type) => name,
+      _ => rust_type,
+    };
+    aliases.get(name).map(|target| (name.to_owned(), target.to_owned()))
+  }
+  
+  /// Является ли тип Rust `Option<...>`.
+  pub(crate) fn is_optional(&self, rust_type: &str) -> bool {
+    self.inner_option_type_re.is_match(&TypesParser::drop_type_unnecessary_stuff(rust_type))
+  }
+  
+  fn repeated(rust_type: &str, inner_type: String) -> MResult<String> {
+    if inner_type.starts_with("repeated") {
+      Err(R2Proto3Error::new(None, "need to use `repeated` twice: consider not to use Vec<Vec<_>> etc."))
+    } else if inner_type.starts_with("optional") {
+      Err(R2Proto3Error::new(None, format!("`{}` can't be expressed in `proto3`: elements of `repeated` fields can't be `optional`", rust_type)))
+    } else {
+      Ok(format!("repeated {}", inner_type))
+    }
+  }
+  
+  fn resolve_known_type(&self, name: &str) -> String {
+    match self.external_types.borrow().get(name) {
+      Some((proto3_type, _)) => proto3_type.to_owned(),
+      None => name.to_owned(),
+    }
+  }
+  
+  /// Регистрирует сообщение для кортежа под именем псевдонима; поля называются так же, как у кортежных структур.
+  fn add_tuple_message(&self, name: &str, elements: &str, known_types: &BTreeSet<String>) -> MResult<String> {
+    let elements = TypesParser::split_inner_types(elements)?
+      .into_iter()
+      .map(TypesParser::drop_type_unnecessary_stuff)
+      .filter(|e| !e.is_empty())
+      .collect::<Vec<_>>();
+    let mut fields = vec![];
+    for (position, element) in elements.iter().enumerate() {
+      let suffix = match self.options.tuple_field_style {
+        TupleFieldStyle::Field => position + 1,
+        TupleFieldStyle::Index => position,
+      };
+      let field_name = format!("{}{}", self.options.tuple_field_prefix, suffix);
+      fields.push((field_name, element.as_str(), self.rust_type_to_protobuf(element, known_types, false)?));
+    }
+    self.add_synthetic_message(name, &fields.iter().map(|(n, r, p)| (n.as_str(), *r, p.as_str())).collect::<Vec<_>>());
+    Ok(name.to_owned())
+  }
+  
+  /// Регистрирует вспомогательное сообщение из полей (имя, тип Rust, тип Protobuf), если его ещё нет.
+  fn add_synthetic_message(&self, name: &str, fields: &[(&str, &str, &str)]) {
+    self.synthetic_types.borrow_mut().entry(name.to_owned()).or_insert_with(|| ProtobufEntityType::Message(
+      fields
+        .iter()
+        .enumerate()
+        .map(|(i, (name, rust_type, proto3_type))| ProtobufField {
+          name: name.to_string(),
+          rust_type: rust_type.to_string(),
+          proto3_type: proto3_type.to_string(),
+          field_num: i as i32 + 1,
+          example: None,
+          options: vec![],
+          todos: vec![],
+          oneof: None,
+          doc: None,
+          deprecated: false,
+          source_line: None,
+        })
+        .collect(),
+    ));
+  }
+  
+  /// Возвращает файлы well-known и внешних типов, на которые ссылается тип Protobuf.
+  pub(crate) fn imports(&self, proto3_type: &str) -> Vec<String> {
+    let mut imports = TypesParser::well_known_imports(proto3_type).map(|i| i.to_owned()).collect::<Vec<_>>();
+    for (external_type, import) in self.external_types.borrow().values() {
+      if proto3_type.split([' ', '<', '>', ',']).any(|p| p == external_type) {
+        imports.push(import.to_owned());
+      }
+    }
+    imports
+  }
+  
+  /// Ищет well-known тип Protobuf для типа Rust.
+  fn well_known_type(rust_type: &str) -> Option<&'static str> {
+    WELL_KNOWN_TYPES
+      .iter()
+      .find(|(t, _, _)| match t.strip_suffix("<_>") {
+        Some(base) => rust_type.strip_prefix(base).is_some_and(|rest| rest.starts_with('<') && rest.ends_with('>')),
+        None => *t == rust_type,
+      })
+      .map(|(_, proto3_type, _)| *proto3_type)
+  }
+  
+  /// Возвращает файлы well-known типов, на которые ссылается тип Protobuf.
+  pub(crate) fn well_known_imports(proto3_type: &str) -> impl Iterator<Item = &'static str> {
+    WELL_KNOWN_TYPES
+      .iter()
+      .filter(move |(_, t, _)| proto3_type.split([' ', '<', '>', ',']).any(|p| p == *t))
+      .map(|(_, _, import)| *import)
+      // Один тип Protobuf может соответствовать нескольким типам Rust
+      .collect::<BTreeSet<_>>()
+      .into_iter()
+  }
+  
+  /// Заменяет `int32`/`int64` на `sint32`/`sint64` с кодированием zigzag, в том числе внутри `repeated`, `optional` и `map`.
+  /// Такие поля несовместимы на проводе с обычными `int32`/`int64`.
+  pub(crate) fn to_zigzag(proto3_type: &str) -> String {
+    TypesParser::map_type_names(proto3_type, |name| match name {
+      "int32" => Some("sint32".to_owned()),
+      "int64" => Some("sint64".to_owned()),
+      _ => None,
+    })
+  }
+  
+  /// Заменяет имена типов внутри типа Protobuf (`repeated Foo`, `map<string, Foo>`), для которых `f` возвращает замену.
+  pub(crate) fn map_type_names(proto3_type: &str, f: impl Fn(&str) -> Option<String>) -> String {
+    let mut result = String::new();
+    let mut token = String::new();
+    for sym in proto3_type.chars().chain(std::iter::once('\0')) {
+      if sym.is_alphanumeric() || sym == '_' || sym == '.' {
+        token.push(sym);
+        continue
+      }
+      match f(&token) {
+        Some(replacement) => result += &replacement,
+        None => result += &token,
+      }
+      token.clear();
+      if sym != '\0' { result.push(sym); }
+    }
+    result
+  }
+  
+  pub(crate) fn warn(&self, msg: impl Into<String>) {
+    let msg = msg.into();
+    if !self.options.quiet { println!("Warning: {}", msg); }
+    self.warnings.borrow_mut().push(msg);
+  }
+  
+  /// Отбрасывает пути модулей и выравнивает пробелы: `crate::Page<crate::Order,u32>` -> `Page<Order, u32>`.
+  pub(crate) fn without_module_paths(rust_type: &str) -> String {
+    let mut result = String::new();
+    let mut rest = rust_type.trim();
+    while let Some(sym) = rest.chars().next() {
+      if sym.is_alphanumeric() || sym == '_' {
+        let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        let tail = rest[len..].trim_start();
+        match tail.strip_prefix("::") {
+          Some(tail) => rest = tail.trim_start(),
+          None => { result += &rest[..len]; rest = &rest[len..]; }
+        }
+        continue
+      }
+      rest = &rest[sym.len_utf8()..];
+      match sym {
+        ',' => { result = result.trim_end().to_owned() + ", "; rest = rest.trim_start(); }
+        '<' | '(' | '[' => { result.push(sym); rest = rest.trim_start(); }
+        '>' | ')' | ']' => { result = result.trim_end().to_owned(); result.push(sym); }
+        _ => result.push(sym),
+      }
+    }
+    result
+  }
+  
   pub(crate) fn drop_type_unnecessary_stuff(rust_type: impl AsRef<str>) -> String {
     let mut rust_type = rust_type.as_ref().trim().to_owned();
     if let Some(pos) = rust_type.find("//") {
@@ -91,11 +485,112 @@ impl TypesParser {
     rust_type
   }
   
-  pub(crate) fn clear_type_name(name: impl AsRef<str>) -> String {
-    name.as_ref().replace("pub ", "").replace("pub(crate) ", "").replace("pub(super) ", "")
+  /// Разбирает целочисленный литерал Rust: `-5`, `1_000`, `0x1F`, `0o17`, `0b101`, `7u8`.
+  pub(crate) fn parse_int_literal(literal: &str) -> Option<i64> {
+    let literal = literal.trim().replace('_', "");
+    let (negative, literal) = match literal.strip_prefix('-') {
+      Some(literal) => (true, literal.trim()),
+      None => (false, literal.as_str()),
+    };
+    let (radix, digits) = match literal.get(..2) {
+      Some("0x") => (16, &literal[2..]),
+      Some("0o") => (8, &literal[2..]),
+      Some("0b") => (2, &literal[2..]),
+      _ => (10, literal),
+    };
+    // Суффикс типа: `7u8`, `-1i64`
+    let digits = match digits.find(['i', 'u']) {
+      Some(pos) => &digits[..pos],
+      None => digits,
+    };
+    let value = i64::from_str_radix(digits, radix).ok()?;
+    Some(if negative { -value } else { value })
+  }
+  
+  /// Содержит ли тип примитив `primitive`: `char` в `char`, `Vec<char>`, `HashMap<char, u32>`.
+  pub(crate) fn holds(rust_type: &str, primitive: &str) -> bool {
+    rust_type.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').any(|t| t == primitive)
+  }
+  
+  /// `PhantomData` не имеет представления на проводе, поэтому такие поля пропускаются целиком.
+  pub(crate) fn is_phantom_data(rust_type: &str) -> bool {
+    ["PhantomData<", "marker::PhantomData<", "std::marker::PhantomData<", "core::marker::PhantomData<"]
+      .iter()
+      .any(|p| rust_type.starts_with(p))
+  }
+  
+  /// Снимает обёртку из `TRANSPARENT_WRAPPERS`: `std::sync::Arc<Foo>` -> (`Arc`, `Foo`, false).
+  pub(crate) fn strip_wrapper(rust_type: &str) -> Option<(&'static str, String, bool)> {
+    let (head, inner) = rust_type.strip_suffix('>')?.split_once('<')?;
+    let name = head.trim().rsplit("::").next()?;
+    let (wrapper, warn) = TRANSPARENT_WRAPPERS.iter().find(|(w, _)| *w == name)?;
+    // У `Lazy<T, F>` вторым параметром идёт тип инициализатора (только именованный: `fn() -> T` здесь не разобрать)
+    let inner = TypesParser::split_inner_types(inner).ok()?.first().map(TypesParser::drop_type_unnecessary_stuff)?;
+    Some((wrapper, inner, *warn))
+  }
+  
+  pub(crate) fn to_pascal_case(name: impl AsRef<str>) -> String {
+    name
+      .as_ref()
+      .split(['_', '-'])
+      .filter(|p| !p.is_empty())
+      .map(|p| {
+        let mut chars = p.chars();
+        match chars.next() {
+          Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+          None => String::new(),
+        }
+      })
+      .collect()
+  }
+  
+  /// Применяет правило `#[serde(rename_all = "...")]` к имени поля в `snake_case`.
+  pub(crate) fn apply_rename_rule(name: &str, rule: &str) -> MResult<String> {
+    match rule {
+      "snake_case" => Ok(name.to_owned()),
+      "lowercase" => Ok(name.to_lowercase()),
+      "UPPERCASE" | "SCREAMING_SNAKE_CASE" => Ok(name.to_uppercase()),
+      "PascalCase" => Ok(TypesParser::to_pascal_case(name)),
+      "camelCase" => {
+        let pascal = TypesParser::to_pascal_case(name);
+        let mut chars = pascal.chars();
+        Ok(chars.next().map(|first| first.to_lowercase().chain(chars).collect()).unwrap_or_default())
+      },
+      "kebab-case" | "SCREAMING-KEBAB-CASE" => Err(R2Proto3Error::new(None, format!("`#[serde(rename_all = \"{}\")]` produces names that aren't valid Protobuf identifiers", rule))),
+      _ => Err(R2Proto3Error::new(None, format!("unknown `#[serde(rename_all = \"{}\")]` rule", rule))),
+    }
+  }
+  
+  /// Допустимый идентификатор Protobuf: латинские буквы, цифры и `_`, не начинается с цифры.
+  pub(crate) fn is_identifier(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+  }
+  
+  /// `OrderStatus` -> `ORDER_STATUS`
+  pub(crate) fn to_screaming_snake_case(name: impl AsRef<str>) -> String {
+    TypesParser::to_snake_case(name).to_uppercase()
+  }
+  
+  /// `OrderStatus` -> `order_status`, `HTTPServer` -> `http_server`, `userID` -> `user_id`
+  pub(crate) fn to_snake_case(name: impl AsRef<str>) -> String {
+    let chars = name.as_ref().chars().map(|c| if c == '-' || c == ' ' { '_' } else { c }).collect::<Vec<_>>();
+    let mut result = String::new();
+    for (i, &sym) in chars.iter().enumerate() {
+      if sym.is_uppercase() && i > 0 && !result.ends_with('_') {
+        let prev = chars[i - 1];
+        let next = chars.get(i + 1).copied().unwrap_or('_');
+        // Граница слова: после строчной буквы или цифры, либо перед последней заглавной аббревиатуры (`HTTPServer`)
+        if prev.is_lowercase() || prev.is_ascii_digit() || (prev.is_uppercase() && next.is_lowercase()) {
+          result.push('_');
+        }
+      }
+      if sym == '_' && result.ends_with('_') { continue }
+      result.extend(sym.to_lowercase());
+    }
+    result
   }
   
-  fn split_inner_types(inner: &str) -> MResult<Vec<&str>> {
+  pub(crate) fn split_inner_types(inner: &str) -> MResult<Vec<&str>> {
     use std::collections::VecDeque;
     const OPENERS: [char; 3] = ['<', '(', '['];
     const CLOSERS: [char; 3] = ['>', ')', ']'];
@@ -104,13 +599,13 @@ impl TypesParser {
     let mut types = Vec::new();
     let mut begin_index = 0usize;
     
-    for (i, sym) in inner.chars().enumerate() {
+    for (i, sym) in inner.char_indices() {
       if let Some(pos) = OPENERS.iter().position(|c| *c == sym) {
         stack.push_back(OPENERS[pos]);
       }
       else if let Some(pos) = CLOSERS.iter().position(|c| *c == sym) {
         if stack.pop_back().is_none_or(|o| OPENERS.iter().position(|c| *c == o).unwrap() != pos) {
-          return Err(R2Proto3Error::new(None, format!("can't parse inner types due to invalid types' openers and closers (`<([` and `>)]` stack")));
+          return Err(R2Proto3Error::new(None, "can't parse inner types due to invalid types' openers and closers (`<([` and `>)]` stack"));
         }
       }
       else if sym == ',' && stack.is_empty() && begin_index + 1 < i {
@@ -144,4 +639,388 @@ mod types_parser_tests {
     
     assert_eq!(TypesParser::split_inner_types(inner_map_type_re.captures_iter("HashMap<String, u32>").map(|c| c.extract::<2>()).next().unwrap().1[1]), Ok(vec!["String", " u32"]));
   }
+  
+  #[test]
+  fn half_precision_floats_test() {
+    let types_parser = TypesParser::new(&Options { strict: true, ..Default::default() }).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("half::f16", &known_types, false), Ok("float".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("half::bf16", &known_types, false), Ok("float".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<bf16>", &known_types, false), Ok("repeated float".to_owned()));
+    assert!(types_parser.rust_type_to_protobuf("half::f16", &known_types, true).is_err());
+    assert!(types_parser.rust_type_to_protobuf("f8", &known_types, false).is_err());
+    assert_eq!(types_parser.warnings.borrow().len(), 3);
+    
+    let types_parser = TypesParser::new(&Options { strict: true, half_as: HalfRepr::Bytes, ..Default::default() }).unwrap();
+    assert_eq!(types_parser.rust_type_to_protobuf("half::f16", &known_types, false), Ok("bytes".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<half::f16>", &known_types, false), Ok("optional bytes".to_owned()));
+    assert!(types_parser.rust_type_to_protobuf("half::f16", &known_types, true).is_err());
+    assert!(types_parser.warnings.borrow().is_empty());
+  }
+  
+  #[test]
+  fn zigzag_test() {
+    let types_parser = TypesParser::new(&Options { signed_as_zigzag: true, ..Default::default() }).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("i64", &known_types, false), Ok("sint64".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<i16>", &known_types, false), Ok("repeated sint32".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("u32", &known_types, false), Ok("uint32".to_owned()));
+    
+    assert_eq!(TypesParser::to_zigzag("map<int64, int32>"), "map<sint64, sint32>");
+    assert_eq!(TypesParser::to_zigzag("optional uint32"), "optional uint32");
+  }
+  
+  #[test]
+  fn optional_enum_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::from(["Status".to_owned()]);
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<Status>", &known_types, false), Ok("optional Status".to_owned()));
+    assert!(types_parser.rust_type_to_protobuf("Option<Option<Status>>", &known_types, false).is_err());
+  }
+  
+  #[test]
+  fn strip_module_paths_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::from(["User".to_owned()]);
+    assert_eq!(types_parser.rust_type_to_protobuf("crate::users::User", &known_types, false), Ok("User".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<super::User>", &known_types, false), Ok("repeated User".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("std::collections::HashMap<String, self::User>", &known_types, false), Ok("map<string, User>".to_owned()));
+    assert!(types_parser.rust_type_to_protobuf("crate::users::Admin", &known_types, false).is_err());
+    
+    let types_parser = TypesParser::new(&Options { strip_module_paths: false, ..Default::default() }).unwrap();
+    assert!(types_parser.rust_type_to_protobuf("crate::users::User", &known_types, false).is_err());
+  }
+  
+  #[test]
+  fn int_literals_test() {
+    assert_eq!(TypesParser::parse_int_literal(" 42"), Some(42));
+    assert_eq!(TypesParser::parse_int_literal("-1_000"), Some(-1000));
+    assert_eq!(TypesParser::parse_int_literal("0x1F"), Some(31));
+    assert_eq!(TypesParser::parse_int_literal("0b101"), Some(5));
+    assert_eq!(TypesParser::parse_int_literal("7u8"), Some(7));
+    assert_eq!(TypesParser::parse_int_literal("SOME_CONST"), None);
+  }
+  
+  #[test]
+  fn bignum_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("bigdecimal::BigDecimal", &known_types, false), Ok("string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("BigInt", &known_types, false), Ok("string".to_owned()));
+    
+    let types_parser = TypesParser::new(&Options { bignum_as: BignumRepr::Bytes, ..Default::default() }).unwrap();
+    assert_eq!(types_parser.rust_type_to_protobuf("num_bigint::BigInt", &known_types, false), Ok("bytes".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<BigUint>", &known_types, false), Ok("optional bytes".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("BigDecimal", &known_types, false), Ok("string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("rust_decimal::Decimal", &known_types, false), Ok("string".to_owned()));
+  }
+  
+  #[test]
+  fn optional_bool_test() {
+    let known_types = BTreeSet::new();
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<bool>", &known_types, false), Ok("optional bool".to_owned()));
+    assert!(types_parser.synthetic_types.borrow().is_empty());
+    
+    let types_parser = TypesParser::new(&Options { optional_bool_as: OptionalBoolRepr::Enum, ..Default::default() }).unwrap();
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<bool>", &known_types, false), Ok("OptionalBool".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<Option<bool>>", &known_types, false), Ok("repeated OptionalBool".to_owned()));
+    assert_eq!(types_parser.synthetic_types.borrow().len(), 1);
+  }
+  
+  #[test]
+  fn interned_strings_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("ustr::Ustr", &known_types, false), Ok("string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("KString", &known_types, false), Ok("string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("HashMap<Ustr, u32>", &known_types, false), Ok("map<string, uint32>".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("BTreeMap<kstring::KString, bool>", &known_types, false), Ok("map<string, bool>".to_owned()));
+  }
+  
+  #[test]
+  fn chrono_types_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("chrono::DateTime<Utc>", &known_types, false), Ok("google.protobuf.Timestamp".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("DateTime<chrono::FixedOffset>", &known_types, false), Ok("google.protobuf.Timestamp".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<NaiveDateTime>", &known_types, false), Ok("optional google.protobuf.Timestamp".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<chrono::NaiveDateTime>", &known_types, false), Ok("repeated google.protobuf.Timestamp".to_owned()));
+    assert!(types_parser.rust_type_to_protobuf("DateTime", &known_types, false).is_err());
+    assert!(types_parser.rust_type_to_protobuf("HashMap<DateTime<Utc>, u32>", &known_types, false).is_err());
+  }
+  
+  #[test]
+  fn duration_types_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("Duration", &known_types, false), Ok("google.protobuf.Duration".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<std::time::Duration>", &known_types, false), Ok("optional google.protobuf.Duration".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("prost_types::Any", &known_types, false), Ok("google.protobuf.Any".to_owned()));
+    assert_eq!(TypesParser::well_known_imports("repeated google.protobuf.Any").collect::<Vec<_>>(), vec!["google/protobuf/any.proto"]);
+  }
+  
+  #[test]
+  fn fixed_size_arrays_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::from(["Point".to_owned()]);
+    assert_eq!(types_parser.rust_type_to_protobuf("[u8; 32]", &known_types, false), Ok("bytes".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("[u8; NONCE_LEN]", &known_types, false), Ok("bytes".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<[u8; 16]>", &known_types, false), Ok("optional bytes".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<[u8; 32]>", &known_types, false), Ok("repeated bytes".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("[f32; 3]", &known_types, false), Ok("repeated float".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("[Point; 4]", &known_types, false), Ok("repeated Point".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<u8>", &known_types, false), Ok("bytes".to_owned()));
+    assert!(types_parser.rust_type_to_protobuf("[[f32; 3]; 3]", &known_types, false).is_err());
+    assert!(types_parser.rust_type_to_protobuf("HashMap<[u8; 4], u32>", &known_types, false).is_err());
+  }
+  
+  #[test]
+  fn sets_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::from(["Role".to_owned()]);
+    assert_eq!(types_parser.rust_type_to_protobuf("HashSet<String>", &known_types, false), Ok("repeated string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("std::collections::BTreeSet<u32>", &known_types, false), Ok("repeated uint32".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<BTreeSet<Role>>", &known_types, false), Ok("repeated Role".to_owned()));
+    assert!(types_parser.rust_type_to_protobuf("HashSet<Vec<u32>>", &known_types, false).is_err());
+    assert!(types_parser.rust_type_to_protobuf("Vec<BTreeSet<u32>>", &known_types, false).is_err());
+    assert!(types_parser.rust_type_to_protobuf("HashSet<Option<u32>>", &known_types, false).is_err());
+  }
+  
+  #[test]
+  fn snake_case_test() {
+    // camelCase
+    assert_eq!(TypesParser::to_snake_case("userId"), "user_id");
+    assert_eq!(TypesParser::to_snake_case("userID"), "user_id");
+    // PascalCase
+    assert_eq!(TypesParser::to_snake_case("OrderStatus"), "order_status");
+    assert_eq!(TypesParser::to_snake_case("HTTPServer"), "http_server");
+    assert_eq!(TypesParser::to_snake_case("XMLHttpRequest"), "xml_http_request");
+    // Смешанные
+    assert_eq!(TypesParser::to_snake_case("already_snake"), "already_snake");
+    assert_eq!(TypesParser::to_snake_case("HTTP2Server"), "http2_server");
+    assert_eq!(TypesParser::to_snake_case("user_ID"), "user_id");
+    assert_eq!(TypesParser::to_snake_case("Order__Status"), "order_status");
+    assert_eq!(TypesParser::to_snake_case("kebab-case"), "kebab_case");
+    assert_eq!(TypesParser::to_screaming_snake_case("HTTPStatus"), "HTTP_STATUS");
+  }
+  
+  #[test]
+  fn rename_rules_test() {
+    assert_eq!(TypesParser::apply_rename_rule("user_id", "camelCase"), Ok("userId".to_owned()));
+    assert_eq!(TypesParser::apply_rename_rule("user_id", "PascalCase"), Ok("UserId".to_owned()));
+    assert_eq!(TypesParser::apply_rename_rule("user_id", "SCREAMING_SNAKE_CASE"), Ok("USER_ID".to_owned()));
+    assert_eq!(TypesParser::apply_rename_rule("user_id", "snake_case"), Ok("user_id".to_owned()));
+    assert_eq!(TypesParser::apply_rename_rule("user_id", "lowercase"), Ok("user_id".to_owned()));
+    assert!(TypesParser::apply_rename_rule("user_id", "kebab-case").is_err());
+    assert!(TypesParser::apply_rename_rule("user_id", "Title Case").is_err());
+    assert!(TypesParser::is_identifier("_id2") && !TypesParser::is_identifier("2id") && !TypesParser::is_identifier("user-id"));
+  }
+  
+  #[test]
+  fn fixed_arrays_as_message_test() {
+    let types_parser = TypesParser::new(&Options { fixed_arrays_as_message: true, ..Default::default() }).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("[f32; 16]", &known_types, false), Ok("FloatArray16".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<[f32; 16]>", &known_types, false), Ok("repeated FloatArray16".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("[i64; DIM]", &known_types, false), Ok("Int64ArrayDim".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("[u8; 32]", &known_types, false), Ok("bytes".to_owned()));
+    match types_parser.synthetic_types.borrow().get("FloatArray16") {
+      Some(ProtobufEntityType::Message(fields)) => {
+        assert_eq!((fields[0].proto3_type.as_str(), fields[0].name.as_str(), fields[0].field_num), ("repeated float", "values", 1));
+        assert_eq!(fields[0].doc.as_deref(), Some("fixed length: 16"));
+      },
+      _ => panic!("`FloatArray16` message wasn't generated"),
+    }
+  }
+  
+  #[test]
+  fn network_types_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("ipnetwork::IpNetwork", &known_types, false), Ok("string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<Ipv6Network>", &known_types, false), Ok("repeated string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("cidr::IpCidr", &known_types, false), Ok("string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<cidr::Ipv4Cidr>", &known_types, false), Ok("optional string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("HashMap<ipnetwork::IpNetwork, String>", &known_types, false), Ok("map<string, string>".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("BTreeMap<IpCidr, u32>", &known_types, false), Ok("map<string, uint32>".to_owned()));
+  }
+  
+  #[test]
+  fn char_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("char", &known_types, false), Ok("string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<char>", &known_types, false), Ok("repeated string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<char>", &known_types, false), Ok("optional string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("HashMap<char, u32>", &known_types, false), Ok("map<string, uint32>".to_owned()));
+    assert!(TypesParser::holds("BTreeMap<char, String>", "char"));
+    assert!(!TypesParser::holds("chars::Charset", "char"));
+  }
+  
+  #[test]
+  fn usize_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<usize>", &known_types, false), Ok("repeated uint64".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("isize", &known_types, false), Ok("int64".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("HashMap<usize, String>", &known_types, false), Ok("map<uint64, string>".to_owned()));
+    
+    let types_parser = TypesParser::new(&Options { usize_as: UsizeWidth::W32, ..Default::default() }).unwrap();
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<usize>", &known_types, false), Ok("optional uint32".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("isize", &known_types, false), Ok("int32".to_owned()));
+  }
+  
+  #[test]
+  fn type_aliases_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::from(["User".to_owned()]);
+    types_parser.aliases.borrow_mut().extend([
+      ("UserId", "u64"),
+      ("OwnerId", "UserId"),
+      ("Users", "Vec<User>"),
+      ("Tags", "HashMap<UserId, String>"),
+      ("Ping", "Pong"),
+      ("Pong", "Option<Ping>"),
+    ].map(|(a, t)| (a.to_owned(), t.to_owned())));
+    assert_eq!(types_parser.rust_type_to_protobuf("UserId", &known_types, false), Ok("uint64".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("crate::ids::OwnerId", &known_types, false), Ok("uint64".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<OwnerId>", &known_types, false), Ok("optional uint64".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Users", &known_types, false), Ok("repeated User".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Tags", &known_types, false), Ok("map<uint64, string>".to_owned()));
+    assert_eq!(
+      types_parser.rust_type_to_protobuf("Ping", &known_types, false).unwrap_err().to_string(),
+      "type alias `Ping` refers to itself: `Ping` -> `Pong` -> `Ping`",
+    );
+    assert!(types_parser.resolving_aliases.borrow().is_empty());
+    
+    types_parser.aliases.borrow_mut().insert("Page<Order, u32>".into(), "PageOrderU32".into());
+    assert_eq!(TypesParser::without_module_paths("crate::Page< crate::shop::Order ,u32 >"), "Page<Order, u32>");
+    assert_eq!(types_parser.alias_target("super::Page<crate::Order,u32>"), Some(("Page<Order, u32>".into(), "PageOrderU32".into())));
+  }
+  
+  #[test]
+  fn tuple_aliases_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::from(["User".to_owned()]);
+    types_parser.aliases.borrow_mut().extend([
+      ("Coord", "(f64, f64)"),
+      ("Owned", "(User, Option<String>, Vec<u32>)"),
+    ].map(|(a, t)| (a.to_owned(), t.to_owned())));
+    assert_eq!(types_parser.rust_type_to_protobuf("Coord", &known_types, false), Ok("Coord".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<Coord>", &known_types, false), Ok("repeated Coord".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Owned", &known_types, false), Ok("Owned".to_owned()));
+    assert!(types_parser.rust_type_to_protobuf("HashMap<Coord, u32>", &known_types, false).is_err());
+    let synthetic_types = types_parser.synthetic_types.borrow();
+    let fields = |name: &str| match &synthetic_types[name] {
+      ProtobufEntityType::Message(fields) => fields.iter().map(|f| (f.name.clone(), f.proto3_type.clone(), f.field_num)).collect::<Vec<_>>(),
+      _ => unreachable!(),
+    };
+    assert_eq!(fields("Coord"), vec![
+      ("anonymous_value_1".to_owned(), "double".to_owned(), 1),
+      ("anonymous_value_2".to_owned(), "double".to_owned(), 2),
+    ]);
+    assert_eq!(fields("Owned"), vec![
+      ("anonymous_value_1".to_owned(), "User".to_owned(), 1),
+      ("anonymous_value_2".to_owned(), "optional string".to_owned(), 2),
+      ("anonymous_value_3".to_owned(), "repeated uint32".to_owned(), 3),
+    ]);
+  }
+  
+  #[test]
+  fn url_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("url::Url", &known_types, false), Ok("string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<Url>", &known_types, false), Ok("optional string".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("HashMap<String, url::Url>", &known_types, false), Ok("map<string, string>".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("BTreeMap<Url, u32>", &known_types, false), Ok("map<string, uint32>".to_owned()));
+  }
+  
+  #[test]
+  fn jiff_types_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("jiff::Timestamp", &known_types, false), Ok("google.protobuf.Timestamp".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("jiff::Zoned", &known_types, false), Ok("google.protobuf.Timestamp".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<jiff::Span>", &known_types, false), Ok("optional google.protobuf.Duration".to_owned()));
+    assert_eq!(TypesParser::well_known_imports("map<string, google.protobuf.Timestamp>").collect::<BTreeSet<_>>(), BTreeSet::from(["google/protobuf/timestamp.proto"]));
+    assert_eq!(TypesParser::well_known_imports("optional google.protobuf.Duration").collect::<Vec<_>>(), vec!["google/protobuf/duration.proto"]);
+  }
+  
+  #[test]
+  fn transparent_wrappers_test() {
+    let types_parser = TypesParser::new(&Options { quiet: true, ..Default::default() }).unwrap();
+    let known_types = BTreeSet::from(["Settings".to_owned()]);
+    assert_eq!(types_parser.rust_type_to_protobuf("ArcSwap<Settings>", &known_types, false), Ok("Settings".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("once_cell::sync::OnceCell<u64>", &known_types, false), Ok("uint64".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("LazyLock<String>", &known_types, false), Ok("string".to_owned()));
+    assert_eq!(types_parser.warnings.borrow().len(), 3);
+    
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<std::sync::Arc<Settings>>", &known_types, false), Ok("repeated Settings".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("HashMap<String, Box<u32>>", &known_types, false), Ok("map<string, uint32>".to_owned()));
+    assert_eq!(types_parser.warnings.borrow().len(), 3);
+  }
+  
+  #[test]
+  fn optional_bytes_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::new();
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<Vec<u8>>", &known_types, false), Ok("optional bytes".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<std::vec::Vec<u8>>", &known_types, false), Ok("optional bytes".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<Vec<u8>>", &known_types, false), Ok("repeated bytes".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("HashMap<String, Vec<u8>>", &known_types, false), Ok("map<string, bytes>".to_owned()));
+  }
+  
+  #[test]
+  fn geo_point_test() {
+    let known_types = BTreeSet::new();
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let error = types_parser.rust_type_to_protobuf("geo::Point<f64>", &known_types, false).unwrap_err();
+    assert!(error.to_string().contains("--geo-as-message"), "{}", error);
+    
+    let types_parser = TypesParser::new(&Options { geo_as_message: true, ..Default::default() }).unwrap();
+    assert_eq!(types_parser.rust_type_to_protobuf("geo::Point<f64>", &known_types, false), Ok("Point".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<geo::Coord<f64>>", &known_types, false), Ok("repeated Point".to_owned()));
+    assert_eq!(types_parser.synthetic_types.borrow().len(), 1);
+    match types_parser.synthetic_types.borrow().get("Point") {
+      Some(ProtobufEntityType::Message(fields)) => assert_eq!(
+        fields.iter().map(|f| (f.proto3_type.as_str(), f.name.as_str(), f.field_num)).collect::<Vec<_>>(),
+        vec![("double", "x", 1), ("double", "y", 2)],
+      ),
+      _ => panic!("`Point` message wasn't generated"),
+    }
+  }
+  
+  #[test]
+  fn optional_repeated_test() {
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let known_types = BTreeSet::from(["Foo".to_owned()]);
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<Vec<Foo>>", &known_types, false), Ok("repeated Foo".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Option<HashMap<String, Foo>>", &known_types, false), Ok("map<string, Foo>".to_owned()));
+    let error = types_parser.rust_type_to_protobuf("Vec<Option<Foo>>", &known_types, false).unwrap_err();
+    assert!(error.to_string().contains("`Vec<Option<Foo>>` can't be expressed in `proto3`"), "{}", error);
+  }
+  
+  #[test]
+  fn range_as_message_test() {
+    let known_types = BTreeSet::new();
+    let types_parser = TypesParser::new(&Options::default()).unwrap();
+    let error = types_parser.rust_type_to_protobuf("Range<u32>", &known_types, false).unwrap_err();
+    assert!(error.to_string().contains("--range-as-message"), "{}", error);
+    
+    let types_parser = TypesParser::new(&Options { range_as_message: true, ..Default::default() }).unwrap();
+    assert_eq!(types_parser.rust_type_to_protobuf("Range<u32>", &known_types, false), Ok("RangeUint32".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("Vec<std::ops::Range<u16>>", &known_types, false), Ok("repeated RangeUint32".to_owned()));
+    assert_eq!(types_parser.rust_type_to_protobuf("RangeInclusive<i64>", &known_types, false), Ok("RangeInclusiveInt64".to_owned()));
+    assert!(types_parser.rust_type_to_protobuf("Range<Vec<u32>>", &known_types, false).is_err());
+    assert_eq!(types_parser.synthetic_types.borrow().keys().collect::<Vec<_>>(), vec!["RangeInclusiveInt64", "RangeUint32"]);
+    match types_parser.synthetic_types.borrow().get("RangeUint32") {
+      Some(ProtobufEntityType::Message(fields)) => assert_eq!(
+        fields.iter().map(|f| (f.proto3_type.as_str(), f.name.as_str(), f.field_num)).collect::<Vec<_>>(),
+        vec![("uint32", "start", 1), ("uint32", "end", 2)],
+      ),
+      _ => panic!("`RangeUint32` message wasn't generated"),
+    }
+  }
 }
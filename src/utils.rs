@@ -1,11 +1,12 @@
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::ops::Deref;
+use std::time::Duration;
 
 #[derive(Debug)]
 // NOTE: ToProtobuf
-pub(crate) struct R2Proto3Error {
-  cause: Option<Box<dyn Error>>,
+pub struct R2Proto3Error {
+  cause: Option<Box<dyn Error + Send + Sync>>,
   description: String,
 }
 
@@ -13,22 +14,15 @@ impl PartialEq for R2Proto3Error {
   fn eq(&self, other: &Self) -> bool {
     self.description.eq(&other.description)
   }
-  
-  fn ne(&self, other: &Self) -> bool {
-    self.description.ne(&other.description)
-  }
 }
 
 impl R2Proto3Error {
-  pub(crate) fn new(
-    cause: Option<Box<dyn Error>>,
+  pub fn new(
+    cause: Option<Box<dyn Error + Send + Sync>>,
     description: impl Into<String>,
   ) -> Self {
     Self {
-      cause: match cause {
-        Some(cause) => Some(cause.into()),
-        None => None,
-      },
+      cause,
       description: description.into(),
     }
   }
@@ -44,15 +38,229 @@ impl Display for R2Proto3Error {
 }
 
 impl Error for R2Proto3Error {
-  fn source(&self) -> Option<&(dyn Error + 'static)> { None }
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    self.cause.as_deref().map(|c| c as &(dyn Error + 'static))
+  }
   fn description(&self) -> &str { &self.description }
-  fn cause(&self) -> Option<&dyn Error> {
-    self.cause.as_ref().map(|e| e.deref())
+  fn cause(&self) -> Option<&dyn Error> { self.source() }
+}
+
+pub type MResult<T> = std::result::Result<T, R2Proto3Error>;
+
+/// Загружает файл соответствий вида `ключ = значение`. Пустые строки и строки, начинающиеся с `#`, пропускаются.
+pub fn load_mapping_file(path: impl AsRef<std::path::Path>) -> MResult<BTreeMap<String, String>> {
+  let contents = std::fs::read_to_string(path).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot read mapping file"))?;
+  let mut mapping = BTreeMap::new();
+  for (i, line) in contents.lines().map(|l| l.trim()).enumerate() {
+    if line.is_empty() || line.starts_with('#') { continue }
+    match line.split_once('=') {
+      Some((key, value)) => { mapping.insert(key.trim().to_owned(), value.trim().to_owned()); },
+      None => return Err(R2Proto3Error::new(None, format!("mapping file line {} should look like `key = value`: `{}`", i + 1, line))),
+    }
   }
+  Ok(mapping)
+}
+
+/// Время, затраченное на каждую из фаз конвертации.
+#[derive(Debug, Default)]
+pub struct Timings {
+  /// Чтение и просмотр файлов идут параллельно, поэтому здесь суммарное время всех потоков
+  pub read: Duration,
+  pub extraction: Duration,
+  pub translation: Duration,
+  pub generation: Duration,
 }
 
-pub(crate) type MResult<T> = std::result::Result<T, R2Proto3Error>;
+impl Display for Timings {
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "Timings:")?;
+    writeln!(f, "  file walk & read:  {:?}", self.read)?;
+    writeln!(f, "  item extraction:   {:?}", self.extraction)?;
+    writeln!(f, "  translation:       {:?}", self.translation)?;
+    writeln!(f, "  generation:        {:?}", self.generation)
+  }
+}
 
 #[allow(unused)]
 // NOTE: ToProtobuf
 pub struct TestStruct(i32);
+
+/// Сравнивает сгенерированный файл с уже существующим. Возвращает unified diff, если они различаются.
+pub fn diff_against(existing_path: impl AsRef<std::path::Path>, generated: &str) -> MResult<Option<String>> {
+  let existing_path = existing_path.as_ref();
+  let existing = std::fs::read_to_string(existing_path).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot read existing proto file"))?;
+  if existing == generated { return Ok(None) }
+  
+  let diff = similar::TextDiff::from_lines(&existing, generated)
+    .unified_diff()
+    .header(&existing_path.display().to_string(), "generated")
+    .to_string();
+  Ok(Some(diff))
+}
+
+/// Сопоставляет имя с шаблоном: `*` - любая последовательность символов, `?` - любой один символ.
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+  let (pattern, name) = (pattern.chars().collect::<Vec<_>>(), name.chars().collect::<Vec<_>>());
+  let (mut p, mut n) = (0, 0);
+  let mut backtrack = None;
+  while n < name.len() {
+    match pattern.get(p) {
+      Some('*') => {
+        backtrack = Some((p, n));
+        p += 1;
+      },
+      Some(c) if *c == '?' || *c == name[n] => {
+        p += 1;
+        n += 1;
+      },
+      _ => match backtrack {
+        Some((bp, bn)) => {
+          backtrack = Some((bp, bn + 1));
+          (p, n) = (bp + 1, bn + 1);
+        },
+        None => return false,
+      },
+    }
+  }
+  pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Проверяет, нужно ли обходить путь внутри рабочего пространства при заданных шаблонах `--crate-filter`.
+/// Каталоги пропускаются, если могут привести к подходящему каталогу, файлы - только внутри подходящих каталогов.
+pub(crate) fn crate_filter_allows(filters: &[String], relative_path: &std::path::Path, is_dir: bool) -> bool {
+  if filters.is_empty() { return true }
+  let components = relative_path.iter().map(|c| c.to_string_lossy()).collect::<Vec<_>>();
+  filters.iter().any(|filter| {
+    let parts = filter.trim_matches('/').split('/').collect::<Vec<_>>();
+    let matched = components.iter().zip(&parts).all(|(c, p)| glob_match(p, c));
+    matched && (is_dir || components.len() > parts.len())
+  })
+}
+
+/// Каталог сборки Cargo: `target` рядом с `Cargo.toml` или любой каталог с меткой `CACHEDIR.TAG`, которую оставляет Cargo.
+pub(crate) fn is_cargo_target_dir(path: &std::path::Path) -> bool {
+  path.join("CACHEDIR.TAG").is_file()
+    || (path.file_name().is_some_and(|n| n == "target") && path.parent().is_some_and(|p| p.join("Cargo.toml").is_file()))
+}
+
+/// Подходит ли путь относительно корня крейта под один из шаблонов `--exclude`. Шаблон без `/` сравнивается
+/// с каждым компонентом пути (`vendor`, `*_generated.rs`), шаблон с `/` - с началом пути (`tests/fixtures`),
+/// `**` в нём заменяет любое число компонентов (`**/fixtures`).
+pub(crate) fn path_excluded(patterns: &[String], relative_path: &std::path::Path) -> bool {
+  fn matches(parts: &[&str], components: &[String]) -> bool {
+    match parts.split_first() {
+      None => true,
+      Some((&"**", rest)) => (0..=components.len()).any(|skip| matches(rest, &components[skip..])),
+      Some((part, rest)) => components.split_first().is_some_and(|(c, components)| glob_match(part, c) && matches(rest, components)),
+    }
+  }
+  
+  let components = relative_path.iter().map(|c| c.to_string_lossy().into_owned()).collect::<Vec<_>>();
+  patterns.iter().any(|pattern| {
+    let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+    if pattern.contains('/') {
+      matches(&pattern.split('/').collect::<Vec<_>>(), &components)
+    } else {
+      components.iter().any(|c| glob_match(pattern, c))
+    }
+  })
+}
+
+/// Проверяет сгенерированные файлы компилятором `protoc`: файлы записываются во временный каталог, и из основного
+/// файла `root_file` собирается набор дескрипторов. Ошибки `protoc` возвращаются как есть. Если `protoc` не найден,
+/// проверка пропускается и возвращается `false`.
+pub fn validate_with_protoc(protoc: impl AsRef<std::ffi::OsStr>, root_file: &str, files: &BTreeMap<String, String>) -> MResult<bool> {
+  let dir = std::env::temp_dir().join(format!("r2proto3_validate_{}", std::process::id()));
+  let _ = std::fs::remove_dir_all(&dir);
+  for (name, contents) in files {
+    let path = dir.join(name);
+    if let Some(parent) = path.parent() {
+      std::fs::create_dir_all(parent).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot create validation directory"))?;
+    }
+    std::fs::write(path, contents).map_err(|e| R2Proto3Error::new(Some(Box::new(e)), "cannot write proto file for validation"))?;
+  }
+  
+  let output = std::process::Command::new(protoc)
+    .arg(format!("--proto_path={}", dir.display()))
+    .arg(format!("--descriptor_set_out={}", dir.join("descriptor_set.pb").display()))
+    .arg(root_file)
+    .output();
+  let _ = std::fs::remove_dir_all(&dir);
+  match output {
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+    Err(e) => Err(R2Proto3Error::new(Some(Box::new(e)), "cannot run protoc")),
+    Ok(output) if output.status.success() => Ok(true),
+    Ok(output) => Err(R2Proto3Error::new(None, format!("generated proto doesn't compile with protoc:\n{}", String::from_utf8_lossy(&output.stderr).trim_end()))),
+  }
+}
+
+#[cfg(test)]
+mod utils_tests {
+  use super::*;
+  
+  #[test]
+  fn error_source_test() {
+    let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+    let error = R2Proto3Error::new(Some(Box::new(io)), "cannot read mapping file");
+    let source = error.source().unwrap();
+    assert_eq!(source.to_string(), "no such file");
+    assert_eq!(source.downcast_ref::<std::io::Error>().map(|e| e.kind()), Some(std::io::ErrorKind::NotFound));
+    assert!(R2Proto3Error::new(None, "no cause").source().is_none());
+  }
+  
+  #[test]
+  fn diff_against_test() {
+    let path = std::env::temp_dir().join(format!("r2proto3_diff_{}.proto", std::process::id()));
+    std::fs::write(&path, "syntax = \"proto3\";\n\nmessage A {\n  string a = 1;\n}\n").unwrap();
+    
+    assert_eq!(diff_against(&path, "syntax = \"proto3\";\n\nmessage A {\n  string a = 1;\n}\n").unwrap(), None);
+    
+    let diff = diff_against(&path, "syntax = \"proto3\";\n\nmessage A {\n  string a = 1;\n  uint32 b = 2;\n}\n").unwrap().unwrap();
+    assert!(diff.contains("+  uint32 b = 2;"), "{}", diff);
+    assert!(diff.starts_with(&format!("--- {}\n+++ generated\n", path.display())), "{}", diff);
+    
+    std::fs::remove_file(path).unwrap();
+  }
+  
+  #[test]
+  fn glob_match_test() {
+    assert!(glob_match("*", "users"));
+    assert!(glob_match("user?", "users"));
+    assert!(glob_match("*-svc", "billing-svc"));
+    assert!(glob_match("a*b*c", "a-x-b-y-c"));
+    assert!(!glob_match("*-svc", "billing-tool"));
+    assert!(!glob_match("user?", "user"));
+  }
+  
+  #[test]
+  fn path_excluded_test() {
+    let patterns = ["vendor".to_owned(), "*_generated.rs".to_owned(), "tests/fixtures".to_owned(), "**/snapshots/".to_owned()];
+    let excluded = |path: &str| path_excluded(&patterns, std::path::Path::new(path));
+    assert!(excluded("vendor"));
+    assert!(excluded("crates/app/vendor/lib.rs"));
+    assert!(excluded("src/api_generated.rs"));
+    assert!(excluded("tests/fixtures/input.rs"));
+    assert!(excluded("crates/app/tests/snapshots"));
+    assert!(!excluded("crates/app/tests/fixtures/input.rs"));
+    assert!(!excluded("src/vendors.rs"));
+    assert!(!excluded("src/lib.rs"));
+  }
+  
+  #[test]
+  fn validate_with_protoc_test() {
+    let files = BTreeMap::from([("generated.proto".to_owned(), "syntax = \"proto3\";\n".to_owned())]);
+    assert_eq!(validate_with_protoc("r2proto3-missing-protoc", "generated.proto", &files), Ok(false));
+    
+    // Вместо `protoc` - скрипт, который проверяет аргументы и отклоняет файл
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      let script = std::env::temp_dir().join(format!("r2proto3_fake_protoc_{}", std::process::id()));
+      std::fs::write(&script, "#!/bin/sh\n[ -f \"${1#--proto_path=}/$3\" ] || exit 2\necho \"$3:1:1: Expected top-level statement.\" >&2\nexit 1\n").unwrap();
+      std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+      let error = validate_with_protoc(&script, "generated.proto", &files).unwrap_err().to_string();
+      assert_eq!(error, "generated proto doesn't compile with protoc:\ngenerated.proto:1:1: Expected top-level statement.");
+      std::fs::remove_file(script).unwrap();
+    }
+  }
+}